@@ -1,6 +1,8 @@
-use bevy::{app::PluginsState, prelude::*};
+use bevy::{app::PluginsState, ecs::entity::EntityHashMap, prelude::*};
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use extol_sprite_layer::{LayerIndex, SpriteLayerPlugin};
+use extol_sprite_layer::{
+    ApplyTo, IntLayer, IntLayerPlugin, LayerIndex, SpriteLayerOptions, SpriteLayerPlugin,
+};
 
 #[derive(Debug, Clone, Component, Hash, PartialEq, Eq)]
 enum SpriteLayer {
@@ -17,10 +19,31 @@ impl LayerIndex for SpriteLayer {
     }
 }
 
+/// A layer that's expensive to clone, like one loaded from data.
+#[derive(Debug, Clone, Component, Hash, PartialEq, Eq)]
+struct StringLayer(String);
+
+impl LayerIndex for StringLayer {
+    fn as_z_coordinate(&self) -> f32 {
+        self.0.len() as f32
+    }
+}
+
 fn setup_app(count: u64) -> App {
+    setup_app_with(count, || SpriteLayer::Middle)
+}
+
+fn setup_app_with<Layer: LayerIndex>(count: u64, layer: impl Fn() -> Layer) -> App {
+    setup_app_with_plugin(count, SpriteLayerPlugin::<Layer>::default(), layer)
+}
+
+fn setup_app_with_plugin<Layer: Component>(
+    count: u64,
+    plugin: impl Plugin,
+    layer: impl Fn() -> Layer,
+) -> App {
     let mut app = App::new();
-    app.add_plugins(MinimalPlugins)
-        .add_plugins(SpriteLayerPlugin::<SpriteLayer>::default());
+    app.add_plugins(MinimalPlugins).add_plugins(plugin);
     for _ in 0..count {
         let sprite = Sprite {
             custom_size: Some(Vec2::new(60.0, 60.0)),
@@ -32,7 +55,7 @@ fn setup_app(count: u64) -> App {
                 transform: Transform::from_xyz(0., fastrand::f32(), 0.),
                 ..default()
             },
-            SpriteLayer::Middle,
+            layer(),
         ));
     }
     while app.plugins_state() != PluginsState::Ready {
@@ -43,6 +66,31 @@ fn setup_app(count: u64) -> App {
     app
 }
 
+/// An app with `roots` layered roots, each with `children` unlayered children, that only
+/// propagates layers and writes `RenderZCoordinate`s.
+fn setup_hierarchy_app(roots: u64, children: u64) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(SpriteLayerPlugin::<SpriteLayer>::default())
+        .insert_resource(SpriteLayerOptions {
+            y_sort: false,
+            apply_to: ApplyTo::ComponentOnly,
+            ..default()
+        });
+    for _ in 0..roots {
+        app.world_mut()
+            .spawn((TransformBundle::default(), SpriteLayer::Middle))
+            .with_children(|parent| {
+                for _ in 0..children {
+                    parent.spawn(TransformBundle::default());
+                }
+            });
+    }
+    app.finish();
+    app.cleanup();
+    app
+}
+
 pub fn criterion_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("update");
     for count in [1000, 2000, 4000, 8000, 16000] {
@@ -55,7 +103,65 @@ pub fn criterion_benchmark(c: &mut Criterion) {
             let mut app = setup_app(count);
             b.iter(|| app.update());
         });
+        group.bench_with_input(
+            BenchmarkId::new("string-layer", count),
+            &count,
+            |b, &count| {
+                let mut app =
+                    setup_app_with(count, || StringLayer("a fairly long layer name".into()));
+                b.iter(|| app.update());
+            },
+        );
     }
+    group.finish();
+
+    // 100k entities spread over 8 integer layers, with and without the specialized plugin
+    let mut group = c.benchmark_group("int-layer");
+    group.sample_size(20);
+    let count = 100_000;
+    let int_layer = || IntLayer(fastrand::u8(0..8));
+    group.throughput(criterion::Throughput::Elements(count));
+    group.bench_function("sprite-layer-plugin", |b| {
+        let mut app = setup_app_with(count, int_layer);
+        b.iter(|| app.update());
+    });
+    group.bench_function("int-layer-plugin", |b| {
+        let mut app = setup_app_with_plugin(count, IntLayerPlugin, int_layer);
+        b.iter(|| app.update());
+    });
+    group.finish();
+
+    // propagating layers through 100k entities and looking them up again, without sorting
+    let mut group = c.benchmark_group("propagate");
+    group.sample_size(20);
+    group.throughput(criterion::Throughput::Elements(100_000));
+    group.bench_function("hierarchy-100k", |b| {
+        let mut app = setup_hierarchy_app(10_000, 9);
+        b.iter(|| app.update());
+    });
+    group.finish();
+
+    // the map the layers are propagated into, versus a std map with the default hasher
+    let mut group = c.benchmark_group("entity-map");
+    let mut world = World::new();
+    let entities = (0..100_000)
+        .map(|_| world.spawn_empty().id())
+        .collect::<Vec<_>>();
+    group.throughput(criterion::Throughput::Elements(entities.len() as u64));
+    group.bench_function("entity-hash-map", |b| {
+        b.iter(|| {
+            let mut map = EntityHashMap::default();
+            map.extend(entities.iter().map(|entity| (*entity, 0usize)));
+            entities.iter().map(|entity| map[entity]).sum::<usize>()
+        });
+    });
+    group.bench_function("std-hash-map", |b| {
+        b.iter(|| {
+            let mut map = std::collections::HashMap::new();
+            map.extend(entities.iter().map(|entity| (*entity, 0usize)));
+            entities.iter().map(|entity| map[entity]).sum::<usize>()
+        });
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
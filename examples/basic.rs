@@ -25,7 +25,10 @@ fn main() {
         .add_systems(Startup, spawn_sprites)
         .insert_resource(ClearColor(Color::BLACK))
         // disable y-sorting for simplicity
-        .insert_resource(SpriteLayerOptions { y_sort: false })
+        .insert_resource(SpriteLayerOptions {
+            y_sort: false,
+            ..default()
+        })
         .run();
 }
 
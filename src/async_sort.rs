@@ -0,0 +1,122 @@
+//! Y-sorting on a background thread. Only available with the `async-sort` feature.
+use bevy::ecs::entity::EntityHashSet;
+use bevy::prelude::*;
+use bevy::tasks::{block_on, poll_once, AsyncComputeTaskPool, Task};
+
+use crate::{LayerMap, ZIndexSortKey};
+
+/// State for [`SpriteLayerOptions::async_sort`](crate::SpriteLayerOptions::async_sort): the
+/// most recently finished background sort, and the one in flight, if any.
+#[derive(Debug, Default)]
+pub struct AsyncSort {
+    order: Vec<(ZIndexSortKey, Entity)>,
+    members: EntityHashSet,
+    task: Option<Task<Vec<(ZIndexSortKey, Entity)>>>,
+}
+
+impl AsyncSort {
+    /// Like the sort in [`YSortParams::sort`](crate::YSortParams::sort), but uses the order from
+    /// the last background sort that finished, instead of sorting on this thread. Entities that
+    /// weren't in it are sorted on this thread and merged in, by their current keys. If no sort is
+    /// in flight, a new one is started from a snapshot of everyone's current keys.
+    pub(crate) fn sort<Layer>(
+        &mut self,
+        layers: &LayerMap<Layer>,
+        key_of: impl Fn(Entity) -> ZIndexSortKey,
+    ) -> Vec<Entity> {
+        if let Some(task) = &mut self.task {
+            if let Some(order) = block_on(poll_once(task)) {
+                self.members = order.iter().map(|(_, entity)| *entity).collect();
+                self.order = order;
+                self.task = None;
+            }
+        }
+
+        let mut fresh = layers
+            .keys()
+            .filter(|entity| !self.members.contains(*entity))
+            .map(|entity| (key_of(*entity), *entity))
+            .collect::<Vec<_>>();
+        fresh.sort_unstable_by_key(|(key, _)| *key);
+        let mut stale = self
+            .order
+            .iter()
+            .filter(|(_, entity)| layers.contains_key(entity))
+            .peekable();
+        let mut fresh = fresh.into_iter().peekable();
+        let mut sorted = Vec::with_capacity(layers.len());
+        loop {
+            let next = match (stale.peek(), fresh.peek()) {
+                (Some(a), Some(b)) if b.0 < a.0 => fresh.next(),
+                (Some(_), _) => stale.next().copied(),
+                (None, _) => fresh.next(),
+            };
+            let Some((_, entity)) = next else {
+                break;
+            };
+            sorted.push(entity);
+        }
+
+        if self.task.is_none() {
+            let mut snapshot = layers
+                .keys()
+                .map(|entity| (key_of(*entity), *entity))
+                .collect::<Vec<_>>();
+            self.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+                snapshot.sort_unstable_by_key(|(key, _)| *key);
+                snapshot
+            }));
+        }
+        sorted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        LayerIndex, RenderZCoordinate, SortPosition, SpriteLayerOptions, SpriteLayerPlugin,
+    };
+    use bevy::prelude::*;
+
+    #[derive(Debug, Copy, Clone, Component, PartialEq, Eq, Hash)]
+    struct Layer;
+
+    impl LayerIndex for Layer {
+        fn as_z_coordinate(&self) -> f32 {
+            0.0
+        }
+    }
+
+    fn get_z(app: &App, entity: Entity) -> f32 {
+        app.world().get::<RenderZCoordinate>(entity).unwrap().0
+    }
+
+    /// Updates until the background sort has caught up with the current positions.
+    fn settle(app: &mut App) {
+        for _ in 0..100 {
+            app.update();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn async_sort_catches_up() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Layer>::default())
+            .insert_resource(SpriteLayerOptions {
+                async_sort: true,
+                ..default()
+            });
+        let a = app.world_mut().spawn((SortPosition(1.0), Layer)).id();
+        let b = app.world_mut().spawn((SortPosition(0.0), Layer)).id();
+        // newly managed entities are sorted right away
+        app.update();
+        assert!(get_z(&app, a) < get_z(&app, b));
+
+        app.world_mut().get_mut::<SortPosition>(a).unwrap().0 = -1.0;
+        settle(&mut app);
+        assert!(get_z(&app, b) < get_z(&app, a));
+    }
+}
@@ -0,0 +1,99 @@
+//! A human-readable report of the plugin's configuration and state, for bug reports.
+use std::fmt::Write;
+
+use bevy::prelude::*;
+
+use crate::{
+    LayerIndexWithContext, PropagatedLayers, RenderZCoordinate, SpriteLayerOptions,
+    SpriteLayerZBounds,
+};
+
+/// How many entities [`debug_dump()`] lists at most.
+const SAMPLE_SIZE: usize = 20;
+
+/// Describes the [`SpriteLayerPlugin`](crate::SpriteLayerPlugin) for `Layer`: the crate version,
+/// the [`SpriteLayerOptions`], every layer with its z-coordinate and how many entities are on it,
+/// the [`SpriteLayerZBounds`], and the z-coordinates of a sample of entities from back to front,
+/// as of the last time [`SpriteLayerSet::SetZCoordinates`](crate::SpriteLayerSet) ran.
+///
+/// The format isn't stable; it's meant to be pasted into an issue when something is drawn in the
+/// wrong order.
+pub fn debug_dump<Layer: LayerIndexWithContext>(world: &World) -> String {
+    let mut out = String::new();
+    // Writing to a `String` can't fail.
+    let _ = write_dump::<Layer>(world, &mut out);
+    out
+}
+
+fn write_dump<Layer: LayerIndexWithContext>(world: &World, out: &mut String) -> std::fmt::Result {
+    writeln!(out, "extol_sprite_layer {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(out, "layer type: {}", std::any::type_name::<Layer>())?;
+    match world.get_resource::<SpriteLayerOptions>() {
+        Some(options) => writeln!(out, "options: {options:#?}")?,
+        None => writeln!(out, "options: not inserted")?,
+    }
+    let Some(layers) = world.get_resource::<PropagatedLayers<Layer>>() else {
+        return writeln!(out, "plugin not added");
+    };
+    if let Some(bounds) = world.get_resource::<SpriteLayerZBounds<Layer>>() {
+        writeln!(out, "z bounds: {}..={}", bounds.min, bounds.max)?;
+    }
+
+    let context = world.get_resource::<Layer::Context>();
+    let name = |layer: &Layer| match context {
+        Some(context) => layer.display_name_in(context).into_owned(),
+        None => format!("{layer:?}"),
+    };
+    let mut counts = layers
+        .layer_counts()
+        .map(|(layer, count)| {
+            let z = context.map(|context| layer.z_coordinate_in(context));
+            (z, layer, count)
+        })
+        .collect::<Vec<_>>();
+    counts.sort_by(|(a, ..), (b, ..)| a.unwrap_or(f32::NAN).total_cmp(&b.unwrap_or(f32::NAN)));
+    writeln!(out, "layers ({} entities):", layers.iter().count())?;
+    for (z, layer, count) in counts {
+        match z {
+            Some(z) => writeln!(out, "  {} at z = {z}: {count}", name(layer))?,
+            None => writeln!(out, "  {}: {count}", name(layer))?,
+        }
+    }
+
+    let mut order = layers
+        .iter()
+        .filter_map(|(entity, layer)| {
+            Some((world.get::<RenderZCoordinate>(entity)?.0, entity, layer))
+        })
+        .collect::<Vec<_>>();
+    order.sort_by(|(a_z, a, _), (b_z, b, _)| a_z.total_cmp(b_z).then(a.cmp(b)));
+    let step = order.len().div_ceil(SAMPLE_SIZE).max(1);
+    writeln!(out, "sample (every {step}, back to front):")?;
+    for (z, entity, layer) in order.into_iter().step_by(step) {
+        writeln!(out, "  {entity}: {} at z = {z}", name(layer))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixture::{test_app, Layer};
+
+    #[test]
+    fn dump() {
+        let mut app = test_app();
+        for i in 0..100 {
+            let layer = if i < 30 { Layer::Back } else { Layer::Front };
+            app.world_mut().spawn((TransformBundle::default(), layer));
+        }
+        app.update();
+        let dump = debug_dump::<Layer>(app.world());
+        assert!(dump.starts_with(concat!("extol_sprite_layer ", env!("CARGO_PKG_VERSION"))));
+        assert!(dump.contains("y_sort: "));
+        assert!(dump
+            .contains("layers (100 entities):\n  background at z = 0: 30\n  Front at z = 1: 70\n"));
+        assert!(dump.contains("sample (every 5, back to front):"));
+        assert_eq!(dump.matches(" at z = ").count(), 2 + 20);
+    }
+}
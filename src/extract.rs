@@ -0,0 +1,215 @@
+//! Applying z-coordinates to sprites and 2D meshes in the render app's [`ExtractSchedule`], for
+//! [`ApplyTo::ExtractedSprites`] and [`ApplyTo::Extracted`].
+use bevy::prelude::*;
+use bevy::render::{Extract, ExtractSchedule, RenderApp};
+use bevy::sprite::{extract_mesh2d, ExtractedSprites, RenderMesh2dInstances, SpriteSystem};
+
+use crate::{
+    ApplyTo, LayerIndexWithContext, PropagatedLayers, RenderZCoordinate, SpriteLayerOptions,
+    SpriteLayerSet,
+};
+
+/// Adds [`extract_z_coordinates`] and [`extract_mesh2d_z_coordinates`] to the render app, if
+/// there is one.
+pub(crate) fn add_extract_systems<Layer: LayerIndexWithContext>(app: &mut App) {
+    let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+        return;
+    };
+    render_app.add_systems(
+        ExtractSchedule,
+        (
+            extract_z_coordinates::<Layer>.after(SpriteSystem::ExtractSprites),
+            extract_mesh2d_z_coordinates::<Layer>.after(extract_mesh2d),
+        )
+            .in_set(SpriteLayerSet::ExtractZCoordinates),
+    );
+}
+
+/// Overwrites the z-coordinate of each extracted sprite with its main-world entity's
+/// [`RenderZCoordinate`]. Sprites whose entity isn't managed by the plugin are left alone.
+pub fn extract_z_coordinates<Layer: LayerIndexWithContext>(
+    options: Extract<Option<Res<SpriteLayerOptions>>>,
+    layers: Extract<Option<Res<PropagatedLayers<Layer>>>>,
+    z_query: Extract<Query<&RenderZCoordinate>>,
+    extracted_sprites: Option<ResMut<ExtractedSprites>>,
+) {
+    if !matches!(
+        options.as_ref().map(|options| options.apply_to),
+        Some(ApplyTo::ExtractedSprites | ApplyTo::Extracted)
+    ) {
+        return;
+    }
+    let (Some(layers), Some(mut extracted_sprites)) = (layers.as_ref(), extracted_sprites) else {
+        return;
+    };
+    for (entity, sprite) in extracted_sprites.sprites.iter_mut() {
+        // sliced sprites are extracted as several sprites on fresh entities
+        let source = sprite.original_entity.unwrap_or(*entity);
+        if !layers.contains(source) {
+            continue;
+        }
+        let Ok(z) = z_query.get(source) else {
+            continue;
+        };
+        let mut affine = sprite.transform.affine();
+        affine.translation.z = z.0;
+        sprite.transform = GlobalTransform::from(affine);
+    }
+}
+
+/// Overwrites the z-coordinate of each extracted 2D mesh with its main-world entity's
+/// [`RenderZCoordinate`], if [`SpriteLayerOptions::apply_to`] is [`ApplyTo::Extracted`]. Meshes
+/// whose entity isn't managed by the plugin are left alone.
+pub fn extract_mesh2d_z_coordinates<Layer: LayerIndexWithContext>(
+    options: Extract<Option<Res<SpriteLayerOptions>>>,
+    layers: Extract<Option<Res<PropagatedLayers<Layer>>>>,
+    z_query: Extract<Query<&RenderZCoordinate>>,
+    mesh_instances: Option<ResMut<RenderMesh2dInstances>>,
+) {
+    if options.as_ref().map(|options| options.apply_to) != Some(ApplyTo::Extracted) {
+        return;
+    }
+    let (Some(layers), Some(mut mesh_instances)) = (layers.as_ref(), mesh_instances) else {
+        return;
+    };
+    for (entity, instance) in mesh_instances.iter_mut() {
+        if !layers.contains(*entity) {
+            continue;
+        }
+        if let Ok(z) = z_query.get(*entity) {
+            instance.transforms.world_from_local.translation.z = z.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::render::MainWorld;
+    use bevy::sprite::{
+        ExtractedSprite, Material2dBindGroupId, Mesh2dTransforms, RenderMesh2dInstance,
+    };
+
+    use super::*;
+    use crate::LayerIndex;
+
+    #[derive(Clone, PartialEq, Eq, Hash, Debug, Component)]
+    struct TestLayer;
+
+    impl LayerIndex for TestLayer {
+        fn as_z_coordinate(&self) -> f32 {
+            0.0
+        }
+    }
+
+    fn extracted_sprite(original_entity: Option<Entity>) -> ExtractedSprite {
+        ExtractedSprite {
+            transform: GlobalTransform::from_xyz(1.0, 2.0, 0.0),
+            color: LinearRgba::WHITE,
+            rect: None,
+            custom_size: None,
+            image_handle_id: AssetId::default(),
+            flip_x: false,
+            flip_y: false,
+            anchor: Vec2::ZERO,
+            original_entity,
+        }
+    }
+
+    fn mesh_instance() -> RenderMesh2dInstance {
+        RenderMesh2dInstance {
+            transforms: Mesh2dTransforms {
+                world_from_local: (&bevy::math::Affine3A::from_translation(Vec3::new(
+                    1.0, 2.0, 0.0,
+                )))
+                    .into(),
+                flags: 0,
+            },
+            mesh_asset_id: AssetId::default(),
+            material_bind_group_id: Material2dBindGroupId::default(),
+            automatic_batching: false,
+        }
+    }
+
+    type Extracted = (
+        ExtractedSprites,
+        RenderMesh2dInstances,
+        Entity,
+        Entity,
+        Entity,
+    );
+
+    fn run_extract(apply_to: ApplyTo) -> Extracted {
+        let mut main_world = World::new();
+        main_world.insert_resource(SpriteLayerOptions {
+            apply_to,
+            ..default()
+        });
+        let layered = main_world.spawn(RenderZCoordinate(5.0)).id();
+        let sliced = main_world.spawn(RenderZCoordinate(7.0)).id();
+        let unlayered = main_world.spawn(RenderZCoordinate(9.0)).id();
+        let mut layers = PropagatedLayers::<TestLayer>::default();
+        layers.layers.insert(layered, &TestLayer);
+        layers.layers.insert(sliced, &TestLayer);
+        main_world.insert_resource(layers);
+
+        let mut render_world = World::new();
+        // make sure this doesn't collide with any main-world entity
+        let slice = Entity::from_raw(1000);
+        let mut extracted = ExtractedSprites::default();
+        extracted.sprites.insert(layered, extracted_sprite(None));
+        extracted
+            .sprites
+            .insert(slice, extracted_sprite(Some(sliced)));
+        extracted.sprites.insert(unlayered, extracted_sprite(None));
+        render_world.insert_resource(extracted);
+        let mut meshes = RenderMesh2dInstances::default();
+        meshes.insert(layered, mesh_instance());
+        meshes.insert(unlayered, mesh_instance());
+        render_world.insert_resource(meshes);
+        let mut main_world_resource = MainWorld::default();
+        *main_world_resource = main_world;
+        render_world.insert_resource(main_world_resource);
+
+        render_world.run_system_once(extract_z_coordinates::<TestLayer>);
+        render_world.run_system_once(extract_mesh2d_z_coordinates::<TestLayer>);
+        let extracted = render_world.remove_resource::<ExtractedSprites>().unwrap();
+        let meshes = render_world
+            .remove_resource::<RenderMesh2dInstances>()
+            .unwrap();
+        (extracted, meshes, layered, slice, unlayered)
+    }
+
+    #[test]
+    fn patches_extracted_sprites() {
+        let (extracted, meshes, layered, slice, unlayered) = run_extract(ApplyTo::ExtractedSprites);
+        let z = |entity| extracted.sprites[&entity].transform.translation();
+        assert_eq!(z(layered), Vec3::new(1.0, 2.0, 5.0));
+        assert_eq!(z(slice), Vec3::new(1.0, 2.0, 7.0));
+        assert_eq!(z(unlayered), Vec3::new(1.0, 2.0, 0.0));
+        // meshes are only patched with `ApplyTo::Extracted`
+        assert_eq!(
+            meshes[&layered].transforms.world_from_local.translation.z,
+            0.0
+        );
+    }
+
+    #[test]
+    fn patches_extracted_meshes() {
+        let (extracted, meshes, layered, _, unlayered) = run_extract(ApplyTo::Extracted);
+        let z = |entity| meshes[&entity].transforms.world_from_local.translation;
+        assert_eq!(z(layered), Vec3::new(1.0, 2.0, 5.0));
+        assert_eq!(z(unlayered), Vec3::new(1.0, 2.0, 0.0));
+        assert_eq!(extracted.sprites[&layered].transform.translation().z, 5.0);
+    }
+
+    #[test]
+    fn does_nothing_when_applying_to_global_transform() {
+        let (extracted, meshes, layered, _, _) = run_extract(ApplyTo::GlobalTransform);
+        assert_eq!(extracted.sprites[&layered].transform.translation().z, 0.0);
+        assert_eq!(
+            meshes[&layered].transforms.world_from_local.translation.z,
+            0.0
+        );
+    }
+}
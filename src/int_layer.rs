@@ -0,0 +1,363 @@
+//! A faster path for the common case where layers are small integers.
+use std::cmp::Reverse;
+
+use bevy::ecs::entity::EntityHashSet;
+use bevy::prelude::*;
+
+use crate::z_values::layer_offset;
+use crate::{
+    has_layered_ancestor, quantize_y, set_transform_z, ApplyTo, LayerIndex, RenderZCoordinate,
+    SortPosition, SpriteLayerOptions, SpriteLayerSet, LAYER_WINDOW,
+};
+
+/// A layer that's just a number; layer `n` has z-coordinate `n`. Use it with [`IntLayerPlugin`]
+/// for speed, or with a regular [`SpriteLayerPlugin`](crate::SpriteLayerPlugin) if you need its
+/// extra features.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Component, Reflect)]
+pub struct IntLayer(pub u8);
+
+impl LayerIndex for IntLayer {
+    fn as_z_coordinate(&self) -> f32 {
+        self.0 as f32 * LAYER_WINDOW
+    }
+}
+
+/// The [`IntLayer`] an entity has or inherited, as of the last time [`IntLayerPlugin`] ran.
+/// Inserted and removed automatically.
+#[derive(Copy, Clone, Debug, Component)]
+pub struct ResolvedIntLayer {
+    pub layer: IntLayer,
+    /// The last time this was resolved; anything older isn't managed anymore.
+    frame: u32,
+    /// The z-coordinate the `GlobalTransform` had before we overwrote it, and what we overwrote
+    /// it with.
+    original_z: f32,
+    written_z: f32,
+}
+
+/// A stripped-down, faster alternative to
+/// [`SpriteLayerPlugin<IntLayer>`](crate::SpriteLayerPlugin). Instead of collecting every
+/// managed entity's layer into a map each frame, it stores the resolved layer in a
+/// [`ResolvedIntLayer`] component on each entity and works off of that, and keeps per-layer
+/// bookkeeping in flat arrays.
+///
+/// Only [`SpriteLayerOptions::y_sort`], [`SpriteLayerOptions::sort_anchor_local`],
+/// [`SpriteLayerOptions::fixed_point_scale`], [`SpriteLayerOptions::mark_transforms_changed`],
+/// [`SpriteLayerOptions::max_hierarchy_depth`] and [`SortPosition`] are supported. Every
+/// [`SpriteLayerOptions::apply_to`] other than [`ApplyTo::GlobalTransform`] is treated as
+/// [`ApplyTo::ComponentOnly`]. Don't use it together with a `SpriteLayerPlugin<IntLayer>`.
+#[derive(Debug, Default)]
+pub struct IntLayerPlugin;
+
+impl Plugin for IntLayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpriteLayerOptions>()
+            .register_type::<SpriteLayerOptions>()
+            .register_type::<IntLayer>()
+            .add_systems(
+                Last,
+                update_int_layers.in_set(SpriteLayerSet::SetZCoordinates),
+            );
+    }
+}
+
+/// Propagates [`IntLayer`]s, y-sorts the managed entities and writes their z-coordinates, all in
+/// one pass. See [`IntLayerPlugin`].
+#[allow(clippy::too_many_arguments)]
+pub fn update_int_layers(
+    seed_query: Query<(Entity, &IntLayer, Option<&Parent>)>,
+    ancestor_query: Query<(Option<&Parent>, Has<IntLayer>)>,
+    recursive_query: Query<(Option<&Children>, Option<&IntLayer>)>,
+    mut resolved_query: Query<(Entity, &mut ResolvedIntLayer)>,
+    mut transform_query: Query<&mut GlobalTransform>,
+    position_query: Query<&SortPosition>,
+    mut z_query: Query<&mut RenderZCoordinate>,
+    options: Res<SpriteLayerOptions>,
+    mut frame: Local<u32>,
+    mut commands: Commands,
+) {
+    *frame = frame.wrapping_add(1);
+    let frame = *frame;
+
+    // Entities that don't have a `ResolvedIntLayer` yet.
+    let mut new = Vec::new();
+    let mut stack = Vec::new();
+    // Guards against malformed hierarchies, where an entity could be reached twice.
+    let mut visited = EntityHashSet::default();
+    for (entity, layer, parent) in &seed_query {
+        if !has_layered_ancestor(parent, &ancestor_query) {
+            stack.push((entity, *layer, 1));
+        }
+    }
+    while let Some((entity, inherited, depth)) = stack.pop() {
+        if !visited.insert(entity) {
+            continue;
+        }
+        let Ok((children, layer)) = recursive_query.get(entity) else {
+            continue;
+        };
+        let layer = layer.copied().unwrap_or(inherited);
+        match resolved_query.get_mut(entity) {
+            Ok((_, mut resolved)) => {
+                resolved.layer = layer;
+                resolved.frame = frame;
+            }
+            Err(_) => new.push((entity, layer)),
+        }
+        let Some(children) = children else {
+            continue;
+        };
+        if depth < options.max_hierarchy_depth {
+            stack.extend(children.iter().map(|child| (*child, layer, depth + 1)));
+        } else if !children.is_empty() {
+            error_once!(
+                "the hierarchy above {entity} is more than {} levels deep; its descendants won't \
+                 get a layer (see SpriteLayerOptions::max_hierarchy_depth)",
+                options.max_hierarchy_depth
+            );
+        }
+    }
+
+    let apply = options.apply_to == ApplyTo::GlobalTransform;
+    let mut managed = Vec::with_capacity(new.len());
+    for (entity, resolved) in &resolved_query {
+        if resolved.frame == frame {
+            managed.push((entity, resolved.layer));
+            continue;
+        }
+        if let Ok(mut transform) = transform_query.get_mut(entity) {
+            if transform.translation().z == resolved.written_z {
+                set_transform_z(
+                    &mut transform,
+                    resolved.original_z,
+                    options.mark_transforms_changed,
+                );
+            }
+        }
+        commands
+            .entity(entity)
+            .remove::<(ResolvedIntLayer, RenderZCoordinate)>();
+    }
+    managed.extend(new.iter().copied());
+
+    let y_sort = cfg!(feature = "y_sort") && options.y_sort;
+    let mut counts = [0u32; 256];
+    for (_, layer) in &managed {
+        counts[layer.0 as usize] += 1;
+    }
+    if y_sort {
+        let anchor = options.sort_anchor_local;
+        let sort_y = |entity| {
+            if let Ok(position) = position_query.get(entity) {
+                return position.0;
+            }
+            transform_query
+                .get(entity)
+                .map_or(0.0, |transform| transform.transform_point(anchor).y)
+        };
+        managed.sort_by_cached_key(|(entity, _)| {
+            (
+                Reverse(quantize_y(sort_y(*entity), options.fixed_point_scale)),
+                *entity,
+            )
+        });
+    }
+
+    let mut positions = [0u32; 256];
+    for (entity, layer) in managed {
+        let index = layer.0 as usize;
+        let mut z = layer.as_z_coordinate();
+        if y_sort {
+            z += layer_offset(
+                positions[index] as usize,
+                counts[index] as usize,
+                None,
+                |rank| rank,
+            ) * LAYER_WINDOW;
+            positions[index] += 1;
+        }
+        match z_query.get_mut(entity) {
+            Ok(mut render_z) => {
+                if render_z.0 != z {
+                    render_z.0 = z;
+                }
+            }
+            Err(_) => {
+                commands.entity(entity).try_insert(RenderZCoordinate(z));
+            }
+        }
+
+        let Ok(mut transform) = transform_query.get_mut(entity) else {
+            continue;
+        };
+        let current = transform.translation().z;
+        match resolved_query.get_mut(entity) {
+            Ok((_, mut resolved)) => {
+                // Anything other than what we wrote last time came from transform propagation.
+                if current != resolved.written_z {
+                    resolved.original_z = current;
+                }
+                if apply {
+                    resolved.written_z = z;
+                    set_transform_z(&mut transform, z, options.mark_transforms_changed);
+                } else {
+                    resolved.written_z = resolved.original_z;
+                    set_transform_z(
+                        &mut transform,
+                        resolved.original_z,
+                        options.mark_transforms_changed,
+                    );
+                }
+            }
+            Err(_) => {
+                let written_z = if apply { z } else { current };
+                commands.entity(entity).try_insert(ResolvedIntLayer {
+                    layer,
+                    frame,
+                    original_z: current,
+                    written_z,
+                });
+                set_transform_z(&mut transform, written_z, options.mark_transforms_changed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "y_sort")]
+    fn test_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(IntLayerPlugin);
+        app
+    }
+
+    fn spawn_at(app: &mut App, y: f32, layer: u8) -> Entity {
+        app.world_mut()
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.0, y, 0.0)),
+                IntLayer(layer),
+            ))
+            .id()
+    }
+
+    fn get_z(app: &App, entity: Entity) -> f32 {
+        app.world()
+            .get::<GlobalTransform>(entity)
+            .unwrap()
+            .translation()
+            .z
+    }
+
+    #[test]
+    fn matches_sprite_layer_plugin() {
+        let ys = (0..20).map(|i| (i * 7 % 20) as f32).collect::<Vec<_>>();
+        let mut zs = vec![];
+        for int_plugin in [true, false] {
+            let mut app = App::new();
+            app.add_plugins(MinimalPlugins).add_plugins(TransformPlugin);
+            if int_plugin {
+                app.add_plugins(IntLayerPlugin);
+            } else {
+                app.add_plugins(crate::SpriteLayerPlugin::<IntLayer>::default());
+            }
+            let entities = ys
+                .iter()
+                .enumerate()
+                .map(|(i, y)| spawn_at(&mut app, *y, (i % 3) as u8))
+                .collect::<Vec<_>>();
+            app.update();
+            app.update();
+            zs.push(
+                entities
+                    .iter()
+                    .map(|entity| get_z(&app, *entity))
+                    .collect::<Vec<_>>(),
+            );
+        }
+        assert_eq!(zs[0], zs[1]);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn inherited_and_removed() {
+        let mut app = test_app();
+        let parent = spawn_at(&mut app, 0.0, 2);
+        let child = app
+            .world_mut()
+            .spawn(TransformBundle::from_transform(Transform::from_xyz(
+                0.0, -1.0, 0.25,
+            )))
+            .set_parent(parent)
+            .id();
+        app.update();
+        assert_eq!(get_z(&app, parent), 2.0);
+        assert_eq!(get_z(&app, child), 2.5);
+
+        app.world_mut().entity_mut(parent).remove::<IntLayer>();
+        app.update();
+        assert_eq!(get_z(&app, parent), 0.0);
+        assert_eq!(get_z(&app, child), 0.25);
+        assert!(app.world().get::<RenderZCoordinate>(child).is_none());
+        assert!(app.world().get::<ResolvedIntLayer>(child).is_none());
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn reached_twice() {
+        // Transform propagation would panic on this hierarchy, so sort by `SortPosition`s.
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins).add_plugins(IntLayerPlugin);
+        let parents =
+            [0.0, 1.0].map(|y| app.world_mut().spawn((SortPosition(y), IntLayer(1))).id());
+        let child = app
+            .world_mut()
+            .spawn(SortPosition(2.0))
+            .set_parent(parents[0])
+            .id();
+        // a malformed hierarchy, where the child is listed under both parents
+        let children = app.world().get::<Children>(parents[0]).unwrap();
+        let children = Children::from_reflect(children).unwrap();
+        app.world_mut().entity_mut(parents[1]).insert(children);
+        app.update();
+        let mut zs = [parents[0], parents[1], child]
+            .map(|entity| app.world().get::<RenderZCoordinate>(entity).unwrap().0);
+        zs.sort_by(f32::total_cmp);
+        // three entities, so three slots
+        assert_eq!(
+            zs,
+            [0.0, 1.0, 2.0].map(|i| LAYER_WINDOW + i / 3.0 * LAYER_WINDOW)
+        );
+    }
+
+    #[test]
+    fn max_hierarchy_depth() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(IntLayerPlugin)
+            .insert_resource(SpriteLayerOptions {
+                max_hierarchy_depth: 2,
+                ..default()
+            });
+        let mut chain = vec![spawn_at(&mut app, 0.0, 1)];
+        for _ in 0..2 {
+            let parent = *chain.last().unwrap();
+            chain.push(
+                app.world_mut()
+                    .spawn(TransformBundle::default())
+                    .set_parent(parent)
+                    .id(),
+            );
+        }
+        app.update();
+        let resolved = chain
+            .iter()
+            .map(|entity| app.world().get::<ResolvedIntLayer>(*entity).is_some());
+        assert_eq!(resolved.collect::<Vec<_>>(), [true, true, false]);
+    }
+}
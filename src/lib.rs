@@ -1,13 +1,55 @@
 #![doc = include_str!("../README.md")]
-use std::cmp::Reverse;
+use std::any::TypeId;
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::marker::PhantomData;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
 
-use bevy::ecs::entity::EntityHashMap; // noticeably faster than std's
+use bevy::app::PluginGroupBuilder;
+use bevy::ecs::entity::{EntityHashMap, EntityHashSet}; // noticeably faster than std's
+use bevy::ecs::schedule::{InternedScheduleLabel, ScheduleLabel};
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use ordered_float::OrderedFloat;
-use tap::Tap;
+use bevy::reflect::GetTypeRegistration;
+#[cfg(feature = "bevy_render")]
+use bevy::render::{primitives::Aabb, view::RenderLayers};
+use bevy::utils::{HashMap, HashSet};
+
+#[cfg(feature = "async-sort")]
+mod async_sort;
+mod debug_dump;
+#[cfg(feature = "bevy_sprite")]
+mod extract;
+mod int_layer;
+mod migration;
+#[cfg(feature = "occupancy-overlay")]
+pub mod occupancy;
+mod precision;
+mod reactive;
+mod snapshot;
+#[cfg(test)]
+mod test_fixture;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+#[cfg(feature = "y_sort")]
+mod y_sort;
+mod z_values;
+
+#[cfg(feature = "async-sort")]
+pub use async_sort::AsyncSort;
+pub use debug_dump::debug_dump;
+pub use int_layer::{update_int_layers, IntLayer, IntLayerPlugin, ResolvedIntLayer};
+pub use migration::assign_layers_from_z;
+pub use precision::{update_precision_headroom, PrecisionHeadroom};
+pub use reactive::{collect_inherited_layers, update_moved_subtrees, InheritedLayer};
+pub use snapshot::{publish_sort_snapshot, SortSnapshot, SortSnapshots};
+#[cfg(feature = "y_sort")]
+pub use y_sort::{TimeSlicedSort, YSortParams, ZIndexSortKey};
+pub use z_values::{assign_z_values, even_layer_z, CoreLayer, CoreOptions};
 
 /// This plugin adjusts your entities' transforms so that their z-coordinates are sorted in the
 /// proper order, where the order is specified by the `Layer` component. Layers propagate to
@@ -15,8 +57,9 @@ use tap::Tap;
 ///
 /// Layers propagate to children, including 'through' entities with no [`GlobalTransform`].
 ///
-/// If you need to know the z-coordinate, you can read it out of the [`GlobalTransform`] after the
-/// [`SpriteLayer::SetZCoordinates`] set has run.
+/// If you need to know the z-coordinate, you can read it out of the entity's [`RenderZCoordinate`]
+/// (or its [`GlobalTransform`], unless you've changed [`SpriteLayerOptions::apply_to`]) after the
+/// [`SpriteLayerSet::SetZCoordinates`] set has run.
 ///
 /// In general you should only instantiate this plugin with a single type you use throughout your
 /// program.
@@ -28,323 +71,5720 @@ use tap::Tap;
 /// # use bevy::prelude::*;
 /// # use extol_sprite_layer::SpriteLayerOptions;
 /// # let mut app = App::new();
-/// app.insert_resource(SpriteLayerOptions { y_sort: false });
+/// app.insert_resource(SpriteLayerOptions { y_sort: false, ..default() });
 /// ```
 pub struct SpriteLayerPlugin<Layer> {
+    #[cfg(feature = "bevy_render")]
+    render_layers: Option<RenderLayers>,
+    extra_setup: Vec<AppSetup>,
+    on_sorted: Option<SortedCallback>,
+    z_base: f32,
+    precision_diagnostics: bool,
+    /// Whether layers are collected without propagating them; see [`FlatSpriteLayerPlugin`].
+    flat: bool,
+    /// Whether layers are propagated by observers; see
+    /// [`with_reactive_propagation`](Self::with_reactive_propagation).
+    reactive: bool,
+    /// Where layers are propagated and z-coordinates computed, if not [`Last`]; see
+    /// [`with_compute_schedule`](Self::with_compute_schedule).
+    compute_schedule: Option<InternedScheduleLabel>,
+    /// Where the plugin is in its [`SpriteLayerPluginGroup`], if it's in one.
+    group_rank: Option<usize>,
     phantom: PhantomData<Layer>,
 }
 
+/// Extra setup to perform when building the plugin.
+type AppSetup = Box<dyn Fn(&mut App) + Send + Sync>;
+
+/// See [`SpriteLayerPlugin::on_sorted`].
+type SortedCallback = Arc<dyn Fn(&[(Entity, f32)]) + Send + Sync>;
+
 impl<Layer> Default for SpriteLayerPlugin<Layer> {
     fn default() -> Self {
         Self {
+            #[cfg(feature = "bevy_render")]
+            render_layers: None,
+            extra_setup: Vec::new(),
+            on_sorted: None,
+            z_base: 0.0,
+            precision_diagnostics: false,
+            flat: false,
+            reactive: false,
+            compute_schedule: None,
+            group_rank: None,
             phantom: Default::default(),
         }
     }
 }
 
-impl<Layer: LayerIndex> Plugin for SpriteLayerPlugin<Layer> {
-    fn build(&self, app: &mut App) {
-        app.init_resource::<SpriteLayerOptions>()
-            .add_systems(
-                First,
-                clear_z_coordinates.in_set(SpriteLayerSet::ClearZCoordinates),
-            )
-            .add_systems(
+impl<Layer: LayerIndexWithContext> SpriteLayerPlugin<Layer> {
+    /// Automatically gives every entity that has a `C` component the given layer, unless it
+    /// already has one. This happens in the [`Last`] schedule, before
+    /// [`SpriteLayerSet::SetZCoordinates`], for entities that gained a `C` since the last time it
+    /// ran; an explicitly set layer is never overridden.
+    ///
+    /// ```
+    /// # use bevy::prelude::*;
+    /// # use extol_sprite_layer::{LayerIndex, SpriteLayerPlugin};
+    /// # #[derive(Debug, Clone, Component, PartialEq, Eq, Hash)]
+    /// # enum Layer { Actors }
+    /// # impl LayerIndex for Layer { fn as_z_coordinate(&self) -> f32 { 0.0 } }
+    /// #[derive(Component)]
+    /// struct Enemy;
+    ///
+    /// # let mut app = App::new();
+    /// app.add_plugins(SpriteLayerPlugin::<Layer>::default().auto_layer::<Enemy>(Layer::Actors));
+    /// ```
+    pub fn auto_layer<C: Component>(mut self, layer: Layer) -> Self {
+        self.extra_setup.push(Box::new(move |app| {
+            let layer = layer.clone();
+            app.add_systems(
                 Last,
-                // We need to run these systems *after* the transform's systems because they need the
-                // proper y-coordinate to be set for y-sorting.
-                (propagate_layers::<Layer>.pipe(set_z_coordinates::<Layer>),)
-                    .chain()
-                    .in_set(SpriteLayerSet::SetZCoordinates),
-            )
-            .register_type::<RenderZCoordinate>();
+                (move |query: Query<Entity, (Added<C>, Without<Layer>)>,
+                       mut commands: Commands| {
+                    for entity in &query {
+                        commands.entity(entity).try_insert(layer.clone());
+                    }
+                })
+                .before(SpriteLayerSet::SetZCoordinates),
+            );
+        }));
+        self
+    }
+
+    /// Keeps every managed entity's [`RenderLayers`] in sync with its layer, as given by
+    /// [`LayerIndex::render_layers`], so you can point a separate camera (say, for a minimap) at
+    /// only some of your sprite layers. Descendants that inherit their layer get its render layers
+    /// too. When an entity stops being managed, the `RenderLayers` it was given is removed again.
+    ///
+    /// This is independent of z-sorting: both are derived from the entity's layer, but entities
+    /// are still sorted against everything else on their layer, whichever cameras see them.
+    /// `RenderLayers` are written in the [`Last`] schedule, after
+    /// [`SpriteLayerSet::SetZCoordinates`], and Bevy computes visibility in [`PostUpdate`], so
+    /// newly managed entities and layer changes show up on the right cameras one frame late. Don't
+    /// combine this with [`with_render_layers`](Self::with_render_layers) unless you want to
+    /// filter by the derived render layers.
+    #[cfg(feature = "bevy_render")]
+    pub fn with_layer_render_layers(mut self) -> Self {
+        self.extra_setup.push(Box::new(|app| {
+            app.add_systems(
+                Last,
+                sync_render_layers::<Layer>.after(SpriteLayerSet::SetZCoordinates),
+            );
+        }));
+        self
+    }
+
+    /// Gives entities a provisional [`RenderZCoordinate`] as soon as they get a layer, instead of
+    /// only once the plugin runs in [`Last`], so systems that run after the spawning system's
+    /// commands are applied can read its draw order right away. The provisional z-coordinate is the
+    /// layer's z-coordinate (plus [`with_z_base`](Self::with_z_base)), without any y-sorting, and
+    /// is replaced with the real one in `Last`.
+    ///
+    /// This uses an observer for `Layer` being added, so it only applies to the entity the layer is
+    /// added to, not to descendants that will inherit it, and not to entities whose layer is
+    /// replaced. If the entity doesn't end up being managed (say, because it's filtered out by
+    /// [`ActiveSortSet`]), the provisional `RenderZCoordinate` is removed again in `Last`.
+    pub fn with_provisional_z(mut self) -> Self {
+        self.extra_setup.push(Box::new(|app| {
+            app.observe(insert_provisional_z::<Layer>);
+        }));
+        self
+    }
+
+    /// Publishes every frame's sort results to the [`SortSnapshots`] resource, so that code
+    /// running outside of the schedule can read them safely.
+    pub fn with_snapshots(mut self) -> Self {
+        self.extra_setup.push(Box::new(|app| {
+            app.init_resource::<SortSnapshots<Layer>>().add_systems(
+                Last,
+                publish_sort_snapshot::<Layer>.after(SpriteLayerSet::SetZCoordinates),
+            );
+        }));
+        self
     }
 }
 
-/// Configure how the sprite layer
-#[derive(Debug, Resource, Reflect)]
-pub struct SpriteLayerOptions {
-    pub y_sort: bool,
+impl<Layer: LayerIndexWithContext + GetTypeRegistration> SpriteLayerPlugin<Layer> {
+    /// Registers `Layer` for reflection, so that entities' layers are saved in and loaded from
+    /// scenes. This needs `Layer` to implement [`Reflect`]; derive it along with
+    /// `#[reflect(Component)]`, or the layer won't be inserted when a scene is loaded.
+    ///
+    /// ```
+    /// # use bevy::prelude::*;
+    /// # use extol_sprite_layer::{LayerIndex, SpriteLayerPlugin};
+    /// #[derive(Debug, Clone, Component, PartialEq, Eq, Hash, Reflect)]
+    /// #[reflect(Component)]
+    /// enum Layer {
+    ///     Background,
+    ///     Foreground,
+    /// }
+    /// # impl LayerIndex for Layer { fn as_z_coordinate(&self) -> f32 { 0.0 } }
+    ///
+    /// # let mut app = App::new();
+    /// app.add_plugins(SpriteLayerPlugin::<Layer>::default().with_reflect());
+    /// ```
+    pub fn with_reflect(mut self) -> Self {
+        self.extra_setup.push(Box::new(|app| {
+            app.register_type::<Layer>();
+        }));
+        self
+    }
 }
 
-impl Default for SpriteLayerOptions {
+impl<Layer> SpriteLayerPlugin<Layer> {
+    /// Only manage entities whose [`RenderLayers`] intersect `render_layers` (entities without a
+    /// `RenderLayers` component are on layer 0). Everything else is ignored by this plugin, as if
+    /// it had no layer.
+    ///
+    /// To give several cameras independent z spaces, use a separate layer type (a newtype is
+    /// fine) for each camera's [`RenderLayers`], and add one plugin per layer type.
+    #[cfg(feature = "bevy_render")]
+    pub fn with_render_layers(mut self, render_layers: RenderLayers) -> Self {
+        self.render_layers = Some(render_layers);
+        self
+    }
+
+    /// Calls `callback` every frame with every managed entity and its computed z-coordinate,
+    /// sorted from back to front. Use this to feed the ordering directly into your own pipeline
+    /// (say, for custom batching); combine it with [`ApplyTo::ComponentOnly`] if you don't want
+    /// the plugin to touch any transforms.
+    ///
+    /// The callback runs inside [`compute_render_z_coordinates`], once the z-coordinates have been
+    /// computed. The slice is only valid for the duration of the call, and newly managed entities
+    /// may not have their [`RenderZCoordinate`] inserted yet when it runs.
+    pub fn on_sorted(
+        mut self,
+        callback: impl Fn(&[(Entity, f32)]) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_sorted = Some(Arc::new(callback));
+        self
+    }
+
+    /// Adds `z_base` to every z-coordinate this plugin computes. This lets several independent
+    /// layer systems coexist without their z-coordinates overlapping: for example, a plugin for
+    /// world sprites whose layers span `[0, 100)` and one for overlay sprites with a `z_base` of
+    /// `100.0`. Each plugin only ever touches the entities it manages, so give them separate layer
+    /// types.
+    pub fn with_z_base(mut self, z_base: f32) -> Self {
+        self.z_base = z_base;
+        self
+    }
+
+    /// Keeps [`PrecisionHeadroom`] up to date every frame, taking the zoom of any orthographic
+    /// cameras into account, and logs a warning when the sort is about to run out of precision.
+    pub fn with_precision_diagnostics(mut self) -> Self {
+        self.precision_diagnostics = true;
+        self
+    }
+
+    /// Propagates layers incrementally instead of walking every layered hierarchy each frame:
+    /// observers keep an [`InheritedLayer`] component on every entity up to date as layers are
+    /// added and removed and entities are reparented, only revisiting the affected subtrees, and
+    /// each frame's layers are read from those. Entities that are moved from one parent to another
+    /// are caught up in [`Last`], since Bevy doesn't trigger observers for that. This is much
+    /// cheaper for large hierarchies that rarely change, and more expensive for ones that change
+    /// constantly, since each change is handled on its own.
+    ///
+    /// [`SpriteLayerOptions::max_hierarchy_depth`] isn't enforced in this mode. Has no effect on
+    /// a [`FlatSpriteLayerPlugin`], which doesn't propagate layers at all.
+    pub fn with_reactive_propagation(mut self) -> Self {
+        self.reactive = true;
+        self
+    }
+
+    /// Propagates layers and computes [`RenderZCoordinate`]s in `schedule` instead of [`Last`],
+    /// in its [`SpriteLayerSet::SetZCoordinates`]; they're still applied to transforms in
+    /// [`Last`]. For example, with rollback netcode, computing them in [`FixedUpdate`] makes them
+    /// part of the simulation's state, so they can be saved and restored along with it.
+    ///
+    /// To compute them the same way again after a rollback, restore everything they're computed
+    /// from too: layers, [`SortPosition`]s and so on, and [`LayerSeq`]s with
+    /// [`SpriteLayerOptions::spawn_order_tiebreak`]. The counter `LayerSeq`s come from only goes
+    /// up, so entities spawned after a rollback get larger ones than the first time around, but
+    /// they're still ordered by when they were spawned. Some options carry state of their own
+    /// from one computation to the next, which isn't in the world and so can't be saved or
+    /// restored: [`sort_hysteresis`](SpriteLayerOptions::sort_hysteresis),
+    /// [`time_sliced_sort`](SpriteLayerOptions::time_sliced_sort),
+    /// [`stable_ties`](SpriteLayerOptions::stable_ties),
+    /// [`sort_by_previous_frame`](SpriteLayerOptions::sort_by_previous_frame),
+    /// [`layer_transition`](SpriteLayerOptions::layer_transition),
+    /// [`freeze_y_sort`](SpriteLayerOptions::freeze_y_sort) and `async_sort`, as well as
+    /// [`FreezeInternalOrder`]. After a rollback, those carry on from the last computation
+    /// instead, so a warning is logged if any of the options are enabled when the app starts.
+    ///
+    /// Transforms aren't propagated before `FixedUpdate`, so y-sorting there sees
+    /// `GlobalTransform`s from the last time they were propagated. For determinism, sort by
+    /// [`SortPosition`]s that you update in the simulation instead.
+    pub fn with_compute_schedule(mut self, schedule: impl ScheduleLabel) -> Self {
+        self.compute_schedule = Some(schedule.intern());
+        self
+    }
+}
+
+/// A lighter [`SpriteLayerPlugin`] for scenes that don't layer hierarchies: only entities that
+/// have a `Layer` component themselves are managed, and layers aren't propagated to descendants,
+/// which saves walking every layered entity's hierarchy each frame. Everything else (sorting,
+/// applying z-coordinates, and all of the options) works the same way. A [`SceneDepthOffset`]
+/// only applies to the entity it's on.
+///
+/// Build it from a configured `SpriteLayerPlugin` with [`From`], or use the default:
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use extol_sprite_layer::{FlatSpriteLayerPlugin, LayerIndex, SpriteLayerPlugin};
+/// # #[derive(Debug, Clone, Component, PartialEq, Eq, Hash)]
+/// # struct Layer;
+/// # impl LayerIndex for Layer { fn as_z_coordinate(&self) -> f32 { 0.0 } }
+/// # let mut app = App::new();
+/// app.add_plugins(FlatSpriteLayerPlugin::from(
+///     SpriteLayerPlugin::<Layer>::default().with_z_base(10.0),
+/// ));
+/// ```
+pub struct FlatSpriteLayerPlugin<Layer>(SpriteLayerPlugin<Layer>);
+
+impl<Layer> Default for FlatSpriteLayerPlugin<Layer> {
     fn default() -> Self {
-        Self { y_sort: true }
+        SpriteLayerPlugin::default().into()
     }
 }
 
-/// Set for all systems related to [`SpriteLayerPlugin`]. This is run in the
-/// render app's [`ExtractSchedule`], *not* the main app.
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, SystemSet)]
-pub enum SpriteLayerSet {
-    ClearZCoordinates,
-    SetZCoordinates,
+impl<Layer> From<SpriteLayerPlugin<Layer>> for FlatSpriteLayerPlugin<Layer> {
+    fn from(plugin: SpriteLayerPlugin<Layer>) -> Self {
+        Self(SpriteLayerPlugin {
+            flat: true,
+            ..plugin
+        })
+    }
 }
 
-/// Trait for the type you use to indicate your sprites' layers. Add this as a
-/// component to any entity you want to treat as a sprite. Note that this does
-/// *not* propagate.
-pub trait LayerIndex: Eq + Hash + Component + Clone + Debug {
-    /// The actual numeric z-value that the layer index corresponds to.  Note
-    /// that the z-value for an entity can be any value in the range
-    /// `layer.as_z_coordinate() <= z < layer.as_z_coordinate() + 1.0`, and the
-    /// exact values are an implementation detail!
-    ///
-    /// With the default Bevy camera settings, your return values from this
-    /// function should be between 0 and 999.0, since the camera is at z =
-    /// 1000.0. Prefer smaller z-values since that gives more precision.
-    fn as_z_coordinate(&self) -> f32;
+impl<Layer: LayerIndexWithContext> Plugin for FlatSpriteLayerPlugin<Layer> {
+    fn build(&self, app: &mut App) {
+        self.0.build(app);
+    }
+
+    fn is_unique(&self) -> bool {
+        self.0.is_unique()
+    }
+
+    fn finish(&self, app: &mut App) {
+        self.0.finish(app);
+    }
+}
+
+/// Adds several [`SpriteLayerPlugin`]s for different layer types at once, stacking them along z
+/// so they can't overlap: each plugin's [`with_z_base`](SpriteLayerPlugin::with_z_base) is set to
+/// the sum of the spans of the plugins added before it, replacing whatever it was. A plugin's span
+/// must cover every z-coordinate its layers produce, including their
+/// [`sort_window`](LayerIndex::sort_window)s, starting from `0.0`.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use extol_sprite_layer::{LayerIndex, SpriteLayerPlugin, SpriteLayerPluginGroup};
+/// # #[derive(Debug, Clone, Component, PartialEq, Eq, Hash)]
+/// # struct WorldLayer;
+/// # impl LayerIndex for WorldLayer { fn as_z_coordinate(&self) -> f32 { 0.0 } }
+/// # #[derive(Debug, Clone, Component, PartialEq, Eq, Hash)]
+/// # struct HudLayer;
+/// # impl LayerIndex for HudLayer { fn as_z_coordinate(&self) -> f32 { 0.0 } }
+/// # let mut app = App::new();
+/// app.add_plugins(
+///     SpriteLayerPluginGroup::default()
+///         // world layers are in [0, 100)...
+///         .with(SpriteLayerPlugin::<WorldLayer>::default(), 100.0)
+///         // ...so HUD layers start at 100
+///         .with(SpriteLayerPlugin::<HudLayer>::default(), 10.0),
+/// );
+/// ```
+///
+/// The spans have to be given by hand, since there's no way to list every value of a layer type.
+///
+/// All of the plugins share [`SpriteLayerOptions`] and the [`RenderZCoordinate`] component, but
+/// never the same entity: one that ends up with layers of several of the group's types (say, a
+/// `HudLayer` entity whose parent has a `WorldLayer`, which it inherits) is only managed by
+/// whichever of their plugins was added last, the highest one up, as if it didn't have the other
+/// layers at all. Which plugin that is is tracked in [`GroupClaims`]. This only works if the
+/// plugins compute their z-coordinates in the same schedule.
+#[derive(Default)]
+pub struct SpriteLayerPluginGroup {
+    /// Where the next plugin's z-coordinates start.
+    next_base: f32,
+    /// The layer type of each plugin added so far.
+    layer_types: Vec<TypeId>,
+    plugins: Vec<Box<dyn FnOnce(PluginGroupBuilder) -> PluginGroupBuilder>>,
 }
 
-/// Clears the z-coordinate of everything with a `RenderZCoordinate` component.
-pub fn clear_z_coordinates(mut query: Query<&mut Transform, With<RenderZCoordinate>>) {
-    for mut transform in query.iter_mut() {
-        transform.bypass_change_detection().translation.z = 0.0;
+impl SpriteLayerPluginGroup {
+    /// Adds `plugin`, whose layers' z-coordinates fit in `[0, span)`, above every plugin added
+    /// so far.
+    ///
+    /// # Panics
+    ///
+    /// If the group already has a plugin for `Layer`.
+    pub fn with<Layer: LayerIndexWithContext>(
+        mut self,
+        plugin: SpriteLayerPlugin<Layer>,
+        span: f32,
+    ) -> Self {
+        let layer_type = TypeId::of::<Layer>();
+        assert!(
+            !self.layer_types.contains(&layer_type),
+            "SpriteLayerPluginGroup already has a plugin for {}",
+            std::any::type_name::<Layer>()
+        );
+        let plugin = SpriteLayerPlugin {
+            group_rank: Some(self.layer_types.len()),
+            ..plugin.with_z_base(self.next_base)
+        };
+        self.layer_types.push(layer_type);
+        self.next_base += span;
+        self.plugins.push(Box::new(move |group| group.add(plugin)));
+        self
     }
 }
 
-/// Propagates the `Layer` of each entity to the `InheritedLayer` of itself and all of its
-/// descendants.
-pub fn propagate_layers<Layer: LayerIndex>(
-    recursive_query: Query<(Option<&Children>, Option<&Layer>)>,
-    root_query: Query<(Entity, &Layer), Without<Parent>>,
-    mut size: Local<usize>,
-) -> EntityHashMap<Layer> {
-    let mut layer_map = EntityHashMap::default();
-    layer_map.reserve(*size);
-    for (entity, layer) in &root_query {
-        propagate_layers_impl(entity, layer, &recursive_query, &mut layer_map);
+impl PluginGroup for SpriteLayerPluginGroup {
+    fn build(self) -> PluginGroupBuilder {
+        self.plugins
+            .into_iter()
+            .fold(PluginGroupBuilder::start::<Self>(), |group, add| add(group))
     }
-    *size = size.max(layer_map.len());
-    layer_map
 }
 
-/// Recursive impl for [`inherited_layers`].
-fn propagate_layers_impl<Layer: LayerIndex>(
-    entity: Entity,
-    propagated_layer: &Layer,
-    query: &Query<(Option<&Children>, Option<&Layer>)>,
-    layer_map: &mut EntityHashMap<Layer>,
-) {
-    let (children, layer) = query.get(entity).expect("query shouldn't ever fail");
-    let layer = layer.unwrap_or(propagated_layer);
-    layer_map.insert(entity, layer.clone());
+/// The settings the [`SpriteLayerPlugin`] for `Layer` was built with. Unlike
+/// [`SpriteLayerOptions`], these can't be changed after the plugin has been added.
+#[derive(Resource)]
+pub struct SpriteLayerSettings<Layer> {
+    #[cfg(feature = "bevy_render")]
+    render_layers: Option<RenderLayers>,
+    on_sorted: Option<SortedCallback>,
+    z_base: f32,
+    /// Where the plugin is in its [`SpriteLayerPluginGroup`], if it's in one.
+    group_rank: Option<usize>,
+    /// Whether [`Plugin::finish`] has run, in case the plugin was added more than once.
+    finished: bool,
+    phantom: PhantomData<Layer>,
+}
 
-    let Some(children) = children else {
-        return;
-    };
+impl<Layer> Debug for SpriteLayerSettings<Layer> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut f = f.debug_struct("SpriteLayerSettings");
+        #[cfg(feature = "bevy_render")]
+        f.field("render_layers", &self.render_layers);
+        f.field("on_sorted", &self.on_sorted.as_ref().map(|_| ".."))
+            .field("z_base", &self.z_base)
+            .field("group_rank", &self.group_rank)
+            .finish()
+    }
+}
 
-    for child in children {
-        propagate_layers_impl(*child, layer, query, layer_map);
+impl<Layer> SpriteLayerSettings<Layer> {
+    /// See [`SpriteLayerPlugin::with_render_layers`].
+    #[cfg(feature = "bevy_render")]
+    pub fn render_layers(&self) -> Option<&RenderLayers> {
+        self.render_layers.as_ref()
+    }
+
+    /// See [`SpriteLayerPlugin::with_z_base`].
+    pub fn z_base(&self) -> f32 {
+        self.z_base
     }
 }
 
-/// Compute the z-coordinate that each entity should have. This is equal to its layer's equivalent
-/// z-coordinate, plus an offset in the range [0, 1) corresponding to its y-sorted position
-/// (if y-sorting is enabled).
-pub fn set_z_coordinates<Layer: LayerIndex>(
-    In(layers): In<EntityHashMap<Layer>>,
-    mut transform_query: Query<&mut GlobalTransform>,
-    options: Res<SpriteLayerOptions>,
-) {
-    if options.y_sort {
-        // We y-sort everything because this avoids the overhead of grouping
-        // entities by their layer.
-        let key_fn = |entity: &Entity| {
-            transform_query
-                .get(*entity)
-                .map(ZIndexSortKey::new)
-                .unwrap_or_else(|_| ZIndexSortKey::new(&Default::default()))
+impl<Layer: LayerIndexWithContext> Plugin for SpriteLayerPlugin<Layer> {
+    fn build(&self, app: &mut App) {
+        // Adding the plugin twice (say, through two plugin groups) would register every system
+        // twice, so only the first one counts.
+        if app
+            .world()
+            .contains_resource::<SpriteLayerSettings<Layer>>()
+        {
+            warn!(
+                "SpriteLayerPlugin<{}> was added more than once; only the first one's settings \
+                 are used",
+                std::any::type_name::<Layer>()
+            );
+            return;
+        }
+        app.init_resource::<SpriteLayerOptions>()
+            .init_resource::<PropagatedLayers<Layer>>()
+            .init_resource::<PreviousSortYs<Layer>>()
+            .init_resource::<OriginalZs<Layer>>()
+            .init_resource::<SpriteLayerZBounds<Layer>>()
+            .init_resource::<ActiveLayers<Layer>>()
+            .init_resource::<SortComparator>()
+            .init_resource::<SortTiebreak>()
+            .init_resource::<ZRemap>()
+            .init_resource::<GroupClaims>()
+            .init_resource::<Layer::Context>()
+            .insert_resource(SpriteLayerSettings::<Layer> {
+                #[cfg(feature = "bevy_render")]
+                render_layers: self.render_layers.clone(),
+                on_sorted: self.on_sorted.clone(),
+                z_base: self.z_base,
+                group_rank: self.group_rank,
+                finished: false,
+                phantom: PhantomData,
+            })
+            .configure_sets(
+                Last,
+                SpriteLayerSet::SetZCoordinates.after(TransformSystem::TransformPropagate),
+            )
+            .add_systems(First, snapshot_sort_ys::<Layer>)
+            .register_type::<SpriteLayerOptions>()
+            .register_type::<RenderZCoordinate>()
+            .register_type::<SortGroup>()
+            .register_type::<SortPosition>()
+            .register_type::<SceneDepthOffset>()
+            .register_type::<LayerSeq>()
+            .register_type::<TopmostOverlay>()
+            .register_type::<LockedSortIndex>()
+            .register_type::<ZSlice>()
+            .register_type::<SortScale>()
+            .register_type::<SortCamera>()
+            .register_type::<FreezeInternalOrder>();
+        // We need to run these systems *after* the transform's systems because they need the
+        // proper y-coordinate to be set for y-sorting.
+        let compute = if self.flat {
+            collect_layers::<Layer>
+                .pipe(compute_render_z_coordinates::<Layer>)
+                .into_configs()
+        } else if self.reactive {
+            reactive::add_observers::<Layer>(app);
+            (
+                update_moved_subtrees::<Layer>,
+                collect_inherited_layers::<Layer>.pipe(compute_render_z_coordinates::<Layer>),
+            )
+                .chain()
+        } else {
+            propagate_layers::<Layer>
+                .pipe(compute_render_z_coordinates::<Layer>)
+                .into_configs()
         };
-        // note: parallelizing with rayon is slower(!) here. I'm not sure why. maybe it has to do
-        // with some kind of inter-thread overhead or L1/L2 cache not being shared?
-        let y_sorted = layers
-            .keys()
-            .cloned()
-            .collect::<Vec<_>>()
-            .tap_mut(|v| v.sort_by_cached_key(key_fn));
+        let compute_schedule = self.compute_schedule.unwrap_or(Last.intern());
+        // Plugins higher up in a group claim their entities first.
+        let compute = match self.group_rank {
+            Some(rank) => {
+                app.configure_sets(compute_schedule, GroupSlot(rank).after(GroupSlot(rank + 1)));
+                compute.in_set(GroupSlot(rank))
+            }
+            None => compute,
+        };
+        match self.compute_schedule {
+            Some(schedule) => {
+                // Nothing moves between here and the next computation, so the z-coordinates we
+                // just wrote are the ones it will see.
+                app.add_systems(schedule, compute.in_set(SpriteLayerSet::SetZCoordinates))
+                    .add_systems(
+                        Last,
+                        (
+                            update_global_transforms::<Layer>,
+                            capture_original_zs::<Layer>,
+                        )
+                            .chain()
+                            .in_set(SpriteLayerSet::SetZCoordinates),
+                    );
+            }
+            None => {
+                app.add_systems(
+                    Last,
+                    (
+                        capture_original_zs::<Layer>,
+                        compute,
+                        update_global_transforms::<Layer>,
+                    )
+                        .chain()
+                        .in_set(SpriteLayerSet::SetZCoordinates),
+                );
+            }
+        }
+        if self.precision_diagnostics {
+            app.init_resource::<PrecisionHeadroom<Layer>>().add_systems(
+                Last,
+                update_precision_headroom::<Layer>.after(SpriteLayerSet::SetZCoordinates),
+            );
+        }
+        for setup in &self.extra_setup {
+            setup(app);
+        }
+    }
+
+    fn is_unique(&self) -> bool {
+        // We handle duplicates ourselves in `build`, instead of panicking.
+        false
+    }
 
-        let scale_factor = 1.0 / y_sorted.len() as f32;
-        for (i, entity) in y_sorted.into_iter().enumerate() {
-            let z = layers[&entity].as_z_coordinate() + (i as f32) * scale_factor;
-            set_transform_z(&mut transform_query, entity, z);
+    fn finish(&self, app: &mut App) {
+        let mut settings = app.world_mut().resource_mut::<SpriteLayerSettings<Layer>>();
+        if std::mem::replace(&mut settings.finished, true) {
+            return;
         }
-    } else {
-        for (entity, layer) in layers {
-            set_transform_z(&mut transform_query, entity, layer.as_z_coordinate());
+        // Checked here rather than in `build` so that plugins added after us count. Apps that only
+        // want `RenderZCoordinate` might not use transforms at all.
+        let component_only =
+            app.world().resource::<SpriteLayerOptions>().apply_to == ApplyTo::ComponentOnly;
+        if let Some(schedule) = self.compute_schedule {
+            let options = app.world().resource::<SpriteLayerOptions>();
+            let stateful = [
+                ("sort_hysteresis", options.sort_hysteresis > 0.0),
+                ("time_sliced_sort", options.time_sliced_sort.is_some()),
+                ("stable_ties", options.stable_ties),
+                ("sort_by_previous_frame", options.sort_by_previous_frame),
+                ("layer_transition", options.layer_transition.is_some()),
+                ("freeze_y_sort", options.freeze_y_sort),
+                #[cfg(feature = "async-sort")]
+                ("async_sort", options.async_sort),
+            ]
+            .into_iter()
+            .filter_map(|(name, enabled)| enabled.then_some(name))
+            .collect::<Vec<_>>();
+            if !stateful.is_empty() {
+                warn!(
+                    "SpriteLayerPlugin computes z-coordinates in {:?}, but these options keep \
+                     state between computations that can't be saved or restored, so rolling back \
+                     won't reproduce them: {}",
+                    schedule,
+                    stateful.join(", ")
+                );
+            }
+        }
+        if !component_only && !app.is_plugin_added::<TransformPlugin>() {
+            warn!(
+                "SpriteLayerPlugin was added without TransformPlugin, so GlobalTransforms won't \
+                 be propagated and y-sorting and z-coordinates will be wrong. Add TransformPlugin \
+                 (it's part of DefaultPlugins)."
+            );
         }
+        // The render app only exists once `RenderPlugin` has been built, which may happen after
+        // we're added.
+        #[cfg(feature = "bevy_sprite")]
+        extract::add_extract_systems::<Layer>(app);
     }
 }
 
-/// Sets the given entity's global transform z. Does nothing if it doesn't have one.
-fn set_transform_z(query: &mut Query<&mut GlobalTransform>, entity: Entity, z: f32) {
-    // hacky hacky; I can't find a way to directly mutate the GlobalTransform.
-    let Some(mut transform) = query.get_mut(entity).ok() else {
-        return;
-    };
-    let transform = transform.bypass_change_detection();
-    let mut affine = transform.affine();
-    affine.translation.z = z;
-    *transform = GlobalTransform::from(affine);
+/// Configure how the sprite layer
+///
+/// This is registered for reflection, so it can be edited in an inspector or saved along with
+/// your other settings.
+#[derive(Clone, Debug, PartialEq, Resource, Reflect)]
+#[reflect(Resource, Default)]
+pub struct SpriteLayerOptions {
+    /// Whether entities on the same layer are ordered by their y-coordinates. Defaults to `true`
+    /// if the `y_sort` feature is enabled, which it is by default; without it, this does nothing.
+    pub y_sort: bool,
+    /// Where the computed z-coordinates get written. See [`ApplyTo`].
+    pub apply_to: ApplyTo,
+    /// If set, y-sorting quantizes each entity's y-coordinate to the integer `round(y * scale)`
+    /// and sorts by that instead of comparing floats. This makes the ordering independent of
+    /// floating-point comparison subtleties, which is useful if you need it to be deterministic
+    /// across platforms (e.g. for netcode).
+    ///
+    /// The tradeoff is precision vs range: entities whose y-coordinates are less than about
+    /// `1.0 / scale` apart may round to the same key and will then be ordered arbitrarily, and
+    /// y-coordinates beyond `±i64::MAX / scale` all saturate to the same key. With a scale of
+    /// `1000.0`, for example, you get sub-pixel precision and a range far larger than any f32
+    /// position you'll run into in practice.
+    pub fixed_point_scale: Option<f32>,
+    /// If positive, y-sorting rounds each entity's y-coordinate to the nearest multiple of this
+    /// before sorting, and entities on the same layer that round to the same value share a single
+    /// z-coordinate. This intentionally merges the draw depth of near-aligned sprites: they can
+    /// no longer swap order (and flicker) as they jitter by less than the epsilon, and since
+    /// there are fewer distinct slots, each one gets more of the layer's precision. Multiples of
+    /// `0.5` round away from zero. Defaults to `0.0`, which disables this.
+    pub sort_epsilon: f32,
+    /// If positive, each entity's y-coordinate only counts as having moved, for y-sorting, once
+    /// it's more than this far from the y-coordinate it was last sorted by. So two entities that
+    /// slowly pass each other (or jitter around the same height) swap places once, instead of
+    /// flickering back and forth while they're level. In exchange, the draw order can lag behind
+    /// by up to twice this distance. Defaults to `0.0`, which disables this.
+    pub sort_hysteresis: f32,
+    /// The point, in each entity's local space, whose world-space y-coordinate is used for
+    /// y-sorting. It's transformed by the entity's full [`GlobalTransform`], so rotation and scale
+    /// are taken into account; for example, setting this to the bottom of your sprites sorts them
+    /// by their 'feet' even if they're attached to a rotating parent. The default of
+    /// [`Vec3::ZERO`] sorts by the entity's translation.
+    pub sort_anchor_local: Vec3,
+    /// If set, entities that are tied on their y-coordinate are ordered by how far their
+    /// [`sort_anchor_local`](Self::sort_anchor_local) point is along this world-space axis, with
+    /// the ones further along it in front. For example, [`Vec3::X`] draws the rightmost of two
+    /// sprites at the same height in front, which keeps diagonal rows in isometric scenes
+    /// consistent instead of leaving them to [`Entity`] order. This comes before the other
+    /// tiebreaks, like [`spawn_order_tiebreak`](Self::spawn_order_tiebreak). Entities without a
+    /// [`GlobalTransform`] count as being at 0. Defaults to `None`.
+    pub secondary_sort_axis: Option<Vec3>,
+    /// If enabled, entities that are still tied after
+    /// [`secondary_sort_axis`](Self::secondary_sort_axis) are ordered by the z-coordinate their
+    /// [`GlobalTransform`] had before the plugin overwrote it, with larger z in front. Use this
+    /// if other systems already assign a meaningful z that should act as a depth hint within a
+    /// layer. The z-coordinates are recorded into [`OriginalZs`] at the start of
+    /// [`SpriteLayerSet::SetZCoordinates`]. Defaults to `false`.
+    pub z_hint_tiebreak: bool,
+    /// If enabled, entities with an [`Aabb`] are y-sorted by the world-space bottom of their
+    /// bounding box instead of by [`sort_anchor_local`](Self::sort_anchor_local): the
+    /// lowest y-coordinate of the box's corners once they're transformed by the entity's
+    /// [`GlobalTransform`]. This sorts meshes, atlas sprites and scaled or rotated sprites by
+    /// their 'feet' without having to know their size. Entities without an `Aabb` fall back to
+    /// `sort_anchor_local`. Only available with the `bevy_render` feature.
+    #[cfg(feature = "bevy_render")]
+    pub sort_by_aabb_bottom: bool,
+    /// If set to `Some(n)`, amortizes the cost of y-sorting large, mostly-static scenes by reusing
+    /// last frame's ordering. Each frame, only entities that are newly managed, whose
+    /// [`GlobalTransform`] or [`SortPosition`] changed since last frame (according to Bevy's change
+    /// detection), or that fall in this frame's 1-in-`n` round-robin slice have their position
+    /// re-read; those get sorted and merged back into the rest, which costs `O(N + k log k)` for
+    /// `k` re-read entities instead of `O(N log N)`.
+    ///
+    /// Anything that moves normally is always re-read, so moving actors never look stale. The
+    /// tradeoff is that if an entity's `GlobalTransform` changes *without* triggering change
+    /// detection (say, via `bypass_change_detection`), it can be drawn in the wrong order for
+    /// up to `n` frames, until its slice comes around.
+    pub time_sliced_sort: Option<u32>,
+    /// If enabled, entities that are tied after y-sorting are ordered by when they first became
+    /// managed by the plugin, with newer entities in front. Otherwise, ties are broken by
+    /// [`Entity`], which doesn't reflect spawn order once entity ids get reused.
+    ///
+    /// This works by inserting a [`LayerSeq`] into every managed entity the first time the plugin
+    /// sees it, which costs 8 bytes per entity (and an archetype move when it's inserted).
+    pub spawn_order_tiebreak: bool,
+    /// If enabled and [`y_sort`](Self::y_sort) is disabled, entities on the same layer still get
+    /// distinct z-coordinates spread over the layer's window, so they don't z-fight. They're
+    /// ordered by spawn order if [`spawn_order_tiebreak`](Self::spawn_order_tiebreak) is enabled
+    /// and by [`Entity`] otherwise, so the order is stable from frame to frame. This costs a sort,
+    /// but unlike y-sorting doesn't read any transforms.
+    pub within_layer_tiebreak: bool,
+    /// If enabled, entities that are still tied after everything else (including
+    /// [`spawn_order_tiebreak`](Self::spawn_order_tiebreak)) keep the relative order they had last
+    /// frame, so coincident sprites never swap places from one frame to the next. Entities that
+    /// weren't sorted last frame go behind the ones that were.
+    ///
+    /// This remembers every managed entity's position in last frame's order, which costs around
+    /// 16 bytes per entity.
+    pub stable_ties: bool,
+    /// If enabled, entities that inherit their layer from an ancestor are y-sorted together with
+    /// that ancestor (the nearest one with its own layer) as a unit, using the ancestor's
+    /// y-coordinate, instead of by their own y-coordinates. Within the unit, they're ordered by
+    /// their local [`Transform`] z-coordinates relative to the ancestor, so a child with a negative
+    /// z is drawn behind its parent and one with a positive z is drawn in front. This is like
+    /// giving each layered entity and its inheriting descendants their own [`SortGroup`];
+    /// entities with an explicit `SortGroup` use that instead.
+    pub sort_inherited_by_owner: bool,
+    /// If set, y-coordinates are clamped to this range (including its end) before y-sorting, so
+    /// everything above it is tied at the back and everything below it is tied at the front. Use
+    /// this when you know only a band of y-coordinates can be on screen, so the sort's precision
+    /// (see [`fixed_point_scale`](Self::fixed_point_scale)) isn't spent on entities far outside
+    /// it.
+    pub y_range: Option<Range<f32>>,
+    /// If set, y-coordinates are also clamped to a band this far above and below the camera
+    /// with a [`SortCamera`], which moves with it, the same way as [`y_range`](Self::y_range).
+    /// Entities outside the band are tied at its edges, so for something like an endless
+    /// scroller, only what's near the camera is sorted finely, and entities far away keep a
+    /// stable order among themselves (by the usual tiebreaks) however far the camera goes. Does
+    /// nothing if there's no `SortCamera`, or with
+    /// [`sort_by_camera_depth`](Self::sort_by_camera_depth). Defaults to `None`.
+    pub camera_y_band: Option<f32>,
+    /// What to do with managed entities that have neither a [`GlobalTransform`] nor a
+    /// [`SortPosition`], and so have no y-coordinate to be sorted by. See [`MissingTransform`].
+    /// This only matters when y-sorting.
+    pub missing_transform: MissingTransform,
+    /// How many levels deep layers propagate, counting the layered entity they start from as the
+    /// first level. Entities deeper than this don't get a layer, and an error is logged. This
+    /// guards against pathological hierarchies; real ones shouldn't come anywhere close to the
+    /// default of 1024.
+    pub max_hierarchy_depth: usize,
+    /// Whether writing z-coordinates into [`GlobalTransform`]s should trip change detection.
+    /// By default they're written with `bypass_change_detection`, so systems looking for
+    /// `Changed<GlobalTransform>` don't see every entity change every frame. Turn this on if you
+    /// need those systems to see the sorted z-coordinates; only transforms whose z-coordinate
+    /// actually changed are marked. Note that with [`time_sliced_sort`](Self::time_sliced_sort),
+    /// entities marked this way are re-read on the next frame.
+    pub mark_transforms_changed: bool,
+    /// If enabled, y-sorting happens on Bevy's
+    /// [`AsyncComputeTaskPool`](bevy::tasks::AsyncComputeTaskPool) instead of in the frame. Only
+    /// available with the `async-sort` feature. This is meant for scenes so large that sorting them
+    /// doesn't fit in the frame budget.
+    ///
+    /// Each frame, the plugin starts sorting a snapshot of every entity's sort key in the
+    /// background (unless the previous sort is still running), and uses the order from the most
+    /// recently finished one. So the ordering lags behind by at least one frame, and by more if a
+    /// sort takes longer than a frame; entities that moved past each other can be drawn in the
+    /// wrong order for that long. Newly managed entities are sorted into the stale order right
+    /// away, and z-coordinates are still written every frame.
+    ///
+    /// Ignored if a [`SortComparator`] is set, and takes precedence over
+    /// [`time_sliced_sort`](Self::time_sliced_sort).
+    #[cfg(feature = "async-sort")]
+    pub async_sort: bool,
+    /// If enabled, entities are y-sorted by where their [`GlobalTransform`] was at the start of the
+    /// frame (recorded in [`PreviousSortYs`] during [`First`]) rather than where it is once
+    /// transforms have been propagated. That is, the ordering lags one frame behind movement, so
+    /// two entities that just passed each other can be drawn in the wrong order for a frame.
+    ///
+    /// In exchange, the order no longer depends on this frame's transform propagation having run,
+    /// so it's not thrown off by systems that move things late in the frame. Entities that weren't
+    /// managed last frame, and ones with a [`SortPosition`], are sorted by their current position.
+    pub sort_by_previous_frame: bool,
+    /// If set, an entity whose layer changes doesn't jump straight to its new z-coordinate, but
+    /// moves there linearly over this long, starting from the z-coordinate it had before the
+    /// change. This avoids a visible pop when, say, a character walks from behind a building to
+    /// in front of it. The target is recomputed every frame, so the entity still ends up in the
+    /// right place if it moves within its new layer along the way; if its layer changes again
+    /// before it gets there, it starts over from wherever it is. Newly managed entities aren't
+    /// animated.
+    ///
+    /// While an entity is moving between layers, it isn't within either layer's window, so it can
+    /// be drawn in front of or behind things on layers in between. This needs [`Time`] (from
+    /// Bevy's `TimePlugin`); without it, entities snap to their new layer. Defaults to `None`.
+    pub layer_transition: Option<Duration>,
+    /// If enabled, y-sorting is paused: every entity keeps the offset above its layer's
+    /// z-coordinate that it had the last time it was y-sorted, so entities that move don't change
+    /// places within their layer, say, during a cutscene. Layers still apply as usual, so an
+    /// entity whose layer changes moves to its new layer, at the same offset above it. Entities
+    /// that weren't y-sorted before the freeze are placed at their layer's z-coordinate.
+    ///
+    /// This only does anything if [`y_sort`](Self::y_sort) is enabled. Remembering the offsets
+    /// costs around 12 bytes per managed entity while y-sorting, whether or not this is ever
+    /// used.
+    pub freeze_y_sort: bool,
+    /// If enabled, each layer's [`sort_window`](LayerIndex::sort_window) is centered on its
+    /// z-coordinate instead of starting there, so y-sorted entities are spread over
+    /// `[z - window / 2, z + window / 2)` and a layer's z-coordinate marks the middle of its depth
+    /// slice rather than the back. Entities that aren't spread out, like without y-sorting, are
+    /// at the layer's z-coordinate either way.
+    ///
+    /// Layers still don't overlap as long as each one's window is no larger than the gap to its
+    /// neighbors, but that now counts half of each neighbor's window: with the default window of
+    /// [`LAYER_WINDOW`], layers need to be at least that far apart, as usual.
+    pub offset_center: bool,
+    /// If enabled, entities are sorted by their depth in front of the camera with a
+    /// [`SortCamera`] instead of by their y-coordinates, with nearer entities in front. This is
+    /// what you want for camera-facing billboards and particles in a 3D scene, where transparent
+    /// sprites have to be drawn back to front. The depth is measured along the camera's forward
+    /// direction from the [`sort_anchor_local`](Self::sort_anchor_local) point, like Bevy does
+    /// for its own transparent objects. Entities are still grouped by layer, and everything else
+    /// that applies to y-sorting, such as [`SortPosition`] and the tiebreaks, applies to this
+    /// too.
+    ///
+    /// If there's no `SortCamera`, entities are y-sorted as usual. Since the depth is read from
+    /// entities' [`GlobalTransform`]s, use [`ApplyTo::ComponentOnly`] (or
+    /// `ApplyTo::ExtractedSprites` or `ApplyTo::Extracted`) so the plugin doesn't change what
+    /// it's sorting by.
+    pub sort_by_camera_depth: bool,
+    /// If set, a layer with more entities than this gets only this many distinct offsets within
+    /// its window: the entities are spread out as usual, and each one's offset is then rounded
+    /// down to the nearest of the evenly spaced slots, like [`LayerIndex::sort_resolution`].
+    /// Neighbors in the sorted order share a slot, and the order between slots is kept, so
+    /// entities at least `window / max` apart in z are guaranteed to be drawn in order, however
+    /// crowded the layer gets; entities sharing a slot can be drawn in either order. Layers with
+    /// at most this many entities are unaffected. Defaults to `None`.
+    pub max_slots_per_layer: Option<u32>,
 }
 
-/// Used to sort the entities within a sprite layer.
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct ZIndexSortKey(Reverse<OrderedFloat<f32>>);
-
-impl ZIndexSortKey {
-    // This is reversed because bevy uses +y pointing upwards, which is the
-    // opposite of what you generally want.
-    fn new(transform: &GlobalTransform) -> Self {
-        Self(Reverse(OrderedFloat(transform.translation().y)))
+impl Default for SpriteLayerOptions {
+    fn default() -> Self {
+        Self {
+            y_sort: cfg!(feature = "y_sort"),
+            apply_to: ApplyTo::default(),
+            fixed_point_scale: None,
+            sort_epsilon: 0.0,
+            sort_hysteresis: 0.0,
+            sort_anchor_local: Vec3::ZERO,
+            secondary_sort_axis: None,
+            z_hint_tiebreak: false,
+            #[cfg(feature = "bevy_render")]
+            sort_by_aabb_bottom: false,
+            time_sliced_sort: None,
+            spawn_order_tiebreak: false,
+            within_layer_tiebreak: false,
+            stable_ties: false,
+            sort_inherited_by_owner: false,
+            y_range: None,
+            camera_y_band: None,
+            missing_transform: MissingTransform::default(),
+            max_hierarchy_depth: 1024,
+            mark_transforms_changed: false,
+            #[cfg(feature = "async-sort")]
+            async_sort: false,
+            sort_by_previous_frame: false,
+            layer_transition: None,
+            freeze_y_sort: false,
+            offset_center: false,
+            sort_by_camera_depth: false,
+            max_slots_per_layer: None,
+        }
     }
 }
 
-/// Stores the z-coordinate that will be used at render time. Don't modify this yourself.
-#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Component, Reflect)]
-pub struct RenderZCoordinate(pub f32);
+/// Controls where the z-coordinates stored in [`RenderZCoordinate`] end up.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Default)]
+pub enum ApplyTo {
+    /// Write the z-coordinate into the entity's [`GlobalTransform`] in the [`Last`] schedule. It
+    /// stays there until transform propagation recomputes the `GlobalTransform`, so other systems
+    /// may see it. This works for anything that renders off of [`GlobalTransform`].
+    #[default]
+    GlobalTransform,
+    /// Leave the main world's transforms alone and instead patch the z-coordinate of extracted
+    /// sprites in the render app's [`ExtractSchedule`], so the z-coordinate is applied in lockstep
+    /// with rendering. This only affects entities that are extracted as sprites; other systems in
+    /// the main world won't see the computed z except through [`RenderZCoordinate`].
+    #[cfg(feature = "bevy_sprite")]
+    ExtractedSprites,
+    /// Like [`ExtractedSprites`](Self::ExtractedSprites), but patches 2D meshes (anything with a
+    /// [`Mesh2dHandle`](bevy::sprite::Mesh2dHandle)) as they're extracted too, so the render app
+    /// has the last word on the z-coordinate of everything 2D, however the main world's
+    /// transforms end up.
+    ///
+    /// Since nothing is written to the main world, systems there that read
+    /// `GlobalTransform::translation().z` see the z-coordinate transform propagation computed,
+    /// not the one that's rendered; read [`RenderZCoordinate`] instead. Anything that's rendered
+    /// some other way, like 3D meshes or UI, isn't affected at all.
+    #[cfg(feature = "bevy_sprite")]
+    Extracted,
+    /// Like [`GlobalTransform`](Self::GlobalTransform), but instead of replacing the
+    /// z-coordinate, only add the entity's y-sorted offset within its layer's window, counted
+    /// from the start of the window, to the z-coordinate transform propagation computed. Use this
+    /// if you manage the coarse z-coordinates yourself, say for parallax, and only want the
+    /// plugin to break ties; you'll probably want to give your layers a small
+    /// [`LayerIndex::sort_window`] too.
+    ///
+    /// [`SceneDepthOffset`]s and [`SpriteLayerPlugin::with_z_base`] aren't part of the offset, and
+    /// a [`TopmostOverlay`]'s offset is just past the end of its own layer's window (further for
+    /// higher overlays), rather than all the way up to where its [`RenderZCoordinate`] is.
+    BiasAdd,
+    /// Don't apply the z-coordinates anywhere; just compute and store them in
+    /// [`RenderZCoordinate`]. Use this if you're consuming the z-coordinates yourself, e.g. in a
+    /// custom rendering path.
+    ///
+    /// Combined with [`SortPosition`], this works for entities that have no transforms at all:
+    /// nothing the plugin does requires a [`Transform`] or [`GlobalTransform`] then, and the
+    /// plugin won't warn about `TransformPlugin` being missing.
+    ComponentOnly,
+}
 
-#[cfg(test)]
-mod tests {
-    use bevy::ecs::system::RunSystemOnce;
+/// How y-sorting handles managed entities without a [`GlobalTransform`] or [`SortPosition`]. These
+/// are usually intermediate entities in a hierarchy that only exist to group their children, but
+/// an entity that's meant to be drawn and is missing its transform is probably a bug.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Default)]
+pub enum MissingTransform {
+    /// Sort the entity as if it were at the origin.
+    #[default]
+    Origin,
+    /// Sort the entity as if it were at the origin, and log a warning when it becomes managed.
+    Warn,
+    /// Don't manage the entity at all: it doesn't get a [`RenderZCoordinate`] and isn't in
+    /// [`PropagatedLayers`]. Its descendants still inherit its layer.
+    Skip,
+}
 
-    use super::*;
+/// Decides which of two entities' [`GlobalTransform`]s is drawn further back when y-sorting.
+/// `Ordering::Less` means the first one is further back.
+pub type CompareTransforms = dyn Fn(&GlobalTransform, &GlobalTransform) -> Ordering + Send + Sync;
 
-    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Component)]
-    enum Layer {
-        Top,
-        Middle,
-        Bottom,
+/// Replaces the y-coordinate comparison used for y-sorting with your own, for things like
+/// isometric sorting or sorting by distance to the camera. The default of `None` uses the
+/// built-in y-sort, drawing entities further up further back.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use extol_sprite_layer::SortComparator;
+/// # let mut app = App::new();
+/// // further left is further back
+/// app.insert_resource(SortComparator::new(|a, b| {
+///     a.translation().x.total_cmp(&b.translation().x)
+/// }));
+/// ```
+///
+/// Entities are still spread over their own layer's window as usual, and a custom comparison only
+/// changes the order within each layer. It replaces the y-coordinate entirely, so
+/// [`SortPosition`], [`SortGroup`], [`SpriteLayerOptions::time_sliced_sort`] and the other
+/// y-coordinate-based options don't apply; ties fall back to the usual tiebreaks (such as
+/// [`SpriteLayerOptions::spawn_order_tiebreak`]). Entities without a `GlobalTransform` are compared
+/// as if they had [`GlobalTransform::IDENTITY`].
+///
+/// This is slower than the built-in y-sort, since it calls a boxed closure for every comparison
+/// instead of sorting by precomputed keys.
+#[derive(Resource, Default, Clone)]
+pub struct SortComparator(pub Option<Arc<CompareTransforms>>);
+
+impl SortComparator {
+    pub fn new(
+        compare: impl Fn(&GlobalTransform, &GlobalTransform) -> Ordering + Send + Sync + 'static,
+    ) -> Self {
+        Self(Some(Arc::new(compare)))
     }
+}
 
-    impl LayerIndex for Layer {
-        fn as_z_coordinate(&self) -> f32 {
-            use Layer::*;
-            match self {
-                Bottom => 0.0,
-                Middle => 1.0,
-                Top => 2.0,
-            }
-        }
+impl Debug for SortComparator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SortComparator")
+            .field(&self.0.as_ref().map(|_| ".."))
+            .finish()
     }
+}
 
-    fn test_app() -> App {
-        let mut app = App::new();
-        app.add_plugins(MinimalPlugins)
-            .add_plugins(TransformPlugin)
-            .add_plugins(SpriteLayerPlugin::<Layer>::default());
+/// Decides which of two entities is drawn further back when they're otherwise tied.
+/// `Ordering::Less` means the first one is further back.
+pub type CompareEntities = dyn Fn(Entity, Entity) -> Ordering + Send + Sync;
 
-        app
+/// A custom tiebreak for entities on the same layer that are at the same y-coordinate (or that
+/// a [`SortComparator`] considers equal), for gameplay rules like 'the player is always drawn over
+/// NPCs at the same height' that you'd rather not bake into components. It's consulted before the
+/// built-in tiebreaks ([`SpriteLayerOptions::spawn_order_tiebreak`],
+/// [`SpriteLayerOptions::stable_ties`] and finally [`Entity`]), which still apply to whatever it
+/// considers equal. With [`SpriteLayerOptions::within_layer_tiebreak`], everything on a layer is
+/// tied, so it orders the whole layer. The default of `None` goes straight to the built-in
+/// tiebreaks.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use extol_sprite_layer::SortTiebreak;
+/// # let mut app = App::new();
+/// # let player = Entity::PLACEHOLDER;
+/// app.insert_resource(SortTiebreak::new(move |a, b| {
+///     // the player is in front of anything it's tied with
+///     (a == player).cmp(&(b == player))
+/// }));
+/// ```
+///
+/// Like [`SortComparator`], this calls a boxed closure during the sort, so it's slower than the
+/// built-in tiebreaks; it's only called for tied entities, though, so the cost depends on how
+/// many of those there are. Setting it also disables [`SpriteLayerOptions::time_sliced_sort`]
+/// and `SpriteLayerOptions::async_sort`, since those reuse orders that were computed without it.
+#[derive(Resource, Default, Clone)]
+pub struct SortTiebreak(pub Option<Arc<CompareEntities>>);
+
+impl SortTiebreak {
+    pub fn new(compare: impl Fn(Entity, Entity) -> Ordering + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(compare)))
     }
+}
 
-    /// Just verify that adding the plugin doesn't somehow blow everything up.
-    #[test]
-    fn plugin_add_smoke_check() {
-        let _ = test_app();
+impl Debug for SortTiebreak {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SortTiebreak")
+            .field(&self.0.as_ref().map(|_| ".."))
+            .finish()
     }
+}
 
-    fn transform_at(x: f32, y: f32) -> TransformBundle {
-        TransformBundle::from_transform(Transform::from_xyz(x, y, 0.0))
+/// Maps a z-coordinate to another one.
+pub type RemapZ = dyn Fn(f32) -> f32 + Send + Sync;
+
+/// A function applied to every z-coordinate [`update_global_transforms`] writes into a
+/// [`GlobalTransform`], for depth buffer layouts or shaders that expect a different range than
+/// the one the layers span, e.g. a logarithmic one. [`RenderZCoordinate`]s are left as they are,
+/// so sorting isn't affected. The default of `None` writes them unchanged.
+///
+/// The function has to be strictly increasing (or strictly decreasing, to flip the whole order),
+/// or entities will be drawn in a different order than they were sorted in. It's called for
+/// every managed entity every frame, so it should be cheap. With [`ApplyTo::BiasAdd`], it's
+/// applied to the sum of the entity's own z-coordinate and its offset.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use extol_sprite_layer::ZRemap;
+/// # let mut app = App::new();
+/// // squeeze everything into 0..1
+/// app.insert_resource(ZRemap::new(|z| z / (1.0 + z.abs()) / 2.0 + 0.5));
+/// ```
+#[derive(Resource, Default, Clone)]
+pub struct ZRemap(pub Option<Arc<RemapZ>>);
+
+impl ZRemap {
+    pub fn new(remap: impl Fn(f32) -> f32 + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(remap)))
     }
+}
 
-    fn get_z(world: &World, entity: Entity) -> f32 {
-        world
-            .get::<GlobalTransform>(entity)
-            .unwrap()
-            .translation()
-            .z
+impl Debug for ZRemap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ZRemap")
+            .field(&self.0.as_ref().map(|_| ".."))
+            .finish()
     }
+}
 
-    #[test]
-    fn simple() {
-        let mut app = test_app();
-        let top = app
-            .world_mut()
-            .spawn((transform_at(1.0, 1.0), Layer::Top))
-            .id();
-        let middle = app
-            .world_mut()
-            .spawn((transform_at(1.0, 1.0), Layer::Middle))
-            .id();
-        let bottom = app
-            .world_mut()
-            .spawn((transform_at(1.0, 1.0), Layer::Bottom))
-            .id();
-        app.update();
+/// If this resource exists, only the entities in it are managed; every other entity is treated as
+/// if it had no layer. Use this to cull the sort down to the entities that might be on screen,
+/// say, by querying your own spatial index for the camera's surroundings every frame. Layers
+/// still propagate through inactive entities to their descendants.
+///
+/// An entity that leaves the set stops being managed: its [`RenderZCoordinate`] is removed and
+/// its [`GlobalTransform`] gets back its original z-coordinate, just like if it had lost its
+/// layer. When it enters the set again, it's sorted in with everything else that frame. So that
+/// entities don't visibly pop as they cross its border, the set should cover a little more than
+/// what's visible.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct ActiveSortSet(pub EntityHashSet);
 
-        assert!(get_z(app.world(), bottom) < get_z(app.world(), middle));
-        assert!(get_z(app.world(), middle) < get_z(app.world(), top));
+/// The y-coordinate each entity managed by the [`SpriteLayerPlugin`] for `Layer` had at the start
+/// of the frame, as recorded by [`snapshot_sort_ys`]. Only filled in if
+/// [`SpriteLayerOptions::sort_by_previous_frame`] is enabled.
+#[derive(Debug, Resource)]
+pub struct PreviousSortYs<Layer> {
+    ys: EntityHashMap<f32>,
+    /// Entities whose y-coordinate is different from the one in the previous snapshot.
+    moved: EntityHashSet,
+    phantom: PhantomData<Layer>,
+}
+
+impl<Layer> Default for PreviousSortYs<Layer> {
+    fn default() -> Self {
+        Self {
+            ys: Default::default(),
+            moved: Default::default(),
+            phantom: PhantomData,
+        }
     }
+}
 
-    fn layer_bundle(layer: Layer) -> impl Bundle {
-        (transform_at(0.0, 0.0), layer)
+impl<Layer> PreviousSortYs<Layer> {
+    /// The y-coordinate the given entity will be sorted by this frame, if it was recorded.
+    pub fn get(&self, entity: Entity) -> Option<f32> {
+        self.ys.get(&entity).copied()
     }
 
-    #[test]
-    fn inherited() {
+    /// Whether the given entity's recorded y-coordinate changed since the previous frame.
+    pub fn moved(&self, entity: Entity) -> bool {
+        self.moved.contains(&entity)
+    }
+}
+
+/// The z-coordinate each entity's [`GlobalTransform`] had before the [`SpriteLayerPlugin`] for
+/// `Layer` overwrote it, as recorded by [`capture_original_zs`], for
+/// [`SpriteLayerOptions::z_hint_tiebreak`]. Only filled in if that's enabled.
+#[derive(Debug, Resource)]
+pub struct OriginalZs<Layer> {
+    /// The original z-coordinates of entities whose `GlobalTransform` still has the one the
+    /// plugin wrote. Everyone else's is whatever their `GlobalTransform` has now.
+    overwritten: EntityHashMap<f32>,
+    phantom: PhantomData<Layer>,
+}
+
+impl<Layer> Default for OriginalZs<Layer> {
+    fn default() -> Self {
+        Self {
+            overwritten: Default::default(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Layer> OriginalZs<Layer> {
+    /// The z-coordinate the given entity had before the plugin overwrote it, given its current
+    /// `GlobalTransform`.
+    pub fn get(&self, entity: Entity, transform: &GlobalTransform) -> f32 {
+        self.overwritten
+            .get(&entity)
+            .copied()
+            .unwrap_or_else(|| transform.translation().z)
+    }
+}
+
+/// Records into [`OriginalZs`] the z-coordinate that each entity whose [`GlobalTransform`] the
+/// plugin overwrote last frame had before that, unless transform propagation has recomputed it
+/// since. Runs at the start of [`SpriteLayerSet::SetZCoordinates`], or with
+/// [`SpriteLayerPlugin::with_compute_schedule`], right after [`update_global_transforms`] in
+/// [`Last`], ready for the next time z-coordinates are computed.
+pub fn capture_original_zs<Layer: LayerIndexWithContext>(
+    layers: Res<PropagatedLayers<Layer>>,
+    transforms: Query<&GlobalTransform>,
+    options: Res<SpriteLayerOptions>,
+    mut original_zs: ResMut<OriginalZs<Layer>>,
+) {
+    original_zs.overwritten.clear();
+    if !options.z_hint_tiebreak {
+        return;
+    }
+    for (entity, (original, written)) in &layers.original_z {
+        if transforms
+            .get(*entity)
+            .is_ok_and(|transform| transform.translation().z == *written)
+        {
+            original_zs.overwritten.insert(*entity, *original);
+        }
+    }
+}
+
+/// Records the y-coordinate of every entity that was managed last frame into
+/// [`PreviousSortYs`], before anything has had a chance to move this frame. Runs in [`First`].
+pub fn snapshot_sort_ys<Layer: LayerIndexWithContext>(
+    layers: Res<PropagatedLayers<Layer>>,
+    transforms: Query<&GlobalTransform>,
+    #[cfg(feature = "bevy_render")] aabbs: Query<&Aabb>,
+    options: Res<SpriteLayerOptions>,
+    mut previous: ResMut<PreviousSortYs<Layer>>,
+) {
+    let previous = &mut *previous;
+    let old = std::mem::take(&mut previous.ys);
+    previous.moved.clear();
+    if !options.sort_by_previous_frame {
+        return;
+    }
+    let anchor = options.sort_anchor_local;
+    for (entity, _) in layers.iter() {
+        let Ok(transform) = transforms.get(entity) else {
+            continue;
+        };
+        #[cfg(feature = "bevy_render")]
+        if options.sort_by_aabb_bottom {
+            if let Ok(aabb) = aabbs.get(entity) {
+                let y = aabb_bottom(transform, aabb);
+                if old.get(&entity) != Some(&y) {
+                    previous.moved.insert(entity);
+                }
+                previous.ys.insert(entity, y);
+                continue;
+            }
+        }
+        let y = transform.transform_point(anchor).y;
+        if old.get(&entity) != Some(&y) {
+            previous.moved.insert(entity);
+        }
+        previous.ys.insert(entity, y);
+    }
+}
+
+/// The lowest world-space y-coordinate of `aabb`'s corners, transformed by `transform`. See
+/// [`SpriteLayerOptions::sort_by_aabb_bottom`].
+#[cfg(feature = "bevy_render")]
+fn aabb_bottom(transform: &GlobalTransform, aabb: &Aabb) -> f32 {
+    let center = Vec3::from(aabb.center);
+    let half_extents = Vec3::from(aabb.half_extents);
+    (0..8)
+        .map(|corner| {
+            let sign = Vec3::new(
+                if corner & 1 == 0 { -1.0 } else { 1.0 },
+                if corner & 2 == 0 { -1.0 } else { 1.0 },
+                if corner & 4 == 0 { -1.0 } else { 1.0 },
+            );
+            transform.transform_point(center + sign * half_extents).y
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+/// Set for all systems related to [`SpriteLayerPlugin`]. `SetZCoordinates` runs in the main app's
+/// [`Last`] schedule, after anything in [`Last`] that's in [`TransformSystem::TransformPropagate`].
+/// If you move things in [`Last`], order those systems `.before(SpriteLayerSet::SetZCoordinates)`
+/// so the sort sees where they moved to; since transform propagation normally happens in
+/// [`PostUpdate`], you'll also need to propagate transforms again in [`Last`] (in that set), or
+/// sort by [`SortPosition`]. `ExtractZCoordinates` runs in the render app's `ExtractSchedule`
+/// (after sprites are extracted), and only does anything if [`SpriteLayerOptions::apply_to`] is
+/// `ApplyTo::ExtractedSprites` or `ApplyTo::Extracted`.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, SystemSet)]
+pub enum SpriteLayerSet {
+    SetZCoordinates,
+    ExtractZCoordinates,
+}
+
+/// Where the plugin at the given position in a [`SpriteLayerPluginGroup`] computes its
+/// z-coordinates. Each one runs after the one above it.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, SystemSet)]
+struct GroupSlot(usize);
+
+/// Which plugin in a [`SpriteLayerPluginGroup`] manages each entity that has a layer of one of the
+/// group's types, by its position in the group. Updated by each plugin as it computes its
+/// z-coordinates, from the top of the group down, so only entities that no plugin higher up
+/// claimed this frame are left for the ones below it.
+#[derive(Debug, Default, Resource)]
+pub struct GroupClaims(EntityHashMap<usize>);
+
+impl GroupClaims {
+    /// The position in its group of the plugin that manages `entity`, if it's in a group.
+    pub fn get(&self, entity: Entity) -> Option<usize> {
+        self.0.get(&entity).copied()
+    }
+}
+
+/// Trait for the type you use to indicate your sprites' layers. Add this as a
+/// component to any entity you want to treat as a sprite. Layers propagate to
+/// descendants that don't have a layer of their own.
+///
+/// Layers don't have to be a fixed set of values. If some entities' z should be a continuous
+/// function of gameplay state (e.g. altitude), you can store that state in the layer and compute
+/// the z-coordinate from it in [`as_z_coordinate`](Self::as_z_coordinate). In that case, you'll
+/// usually want to shrink or disable the y-sort offset for those layers with
+/// [`sort_window`](Self::sort_window), so it doesn't overlap the continuous z-values.
+pub trait LayerIndex: Eq + Hash + Component + Clone + Debug {
+    /// The actual numeric z-value that the layer index corresponds to.  Note
+    /// that the z-value for an entity can be any value in the range
+    /// `layer.as_z_coordinate() <= z < layer.as_z_coordinate() + layer.sort_window()` (or the same
+    /// range centered on it, with [`SpriteLayerOptions::offset_center`]), and the exact values are
+    /// an implementation detail!
+    ///
+    /// With the default Bevy camera settings, your return values from this
+    /// function should be between 0 and 999.0, since the camera is at z =
+    /// 1000.0. Prefer smaller z-values since that gives more precision.
+    fn as_z_coordinate(&self) -> f32;
+
+    /// The size of the range above [`as_z_coordinate`](Self::as_z_coordinate) that y-sorting
+    /// spreads this layer's entities over. Defaults to [`LAYER_WINDOW`]. Return something smaller
+    /// than the gap to the next layer's z-coordinate to keep layers from overlapping, or `0.0` to
+    /// put every entity on this layer at exactly `as_z_coordinate()`.
+    fn sort_window(&self) -> f32 {
+        LAYER_WINDOW
+    }
+
+    /// If set to `Some(n)`, y-sorted offsets within this layer's window are rounded down to one of
+    /// `n` evenly spaced slots, instead of being spread continuously. Entities in the same slot
+    /// get the same z-coordinate, so only use this for layers where the order of nearby entities
+    /// doesn't matter, like far-away parallax layers; in exchange, the slots are far apart, so
+    /// they stay distinguishable regardless of how many entities there are. Defaults to `None`.
+    fn sort_resolution(&self) -> Option<u32> {
+        None
+    }
+
+    /// Maps an entity's y-sorted rank within this layer, as a fraction in `[0, 1)` (`0.0` is the
+    /// backmost entity), to its offset as a fraction of [`sort_window`](Self::sort_window). The
+    /// default is the identity, which spreads entities evenly over the window; override it for
+    /// effects like compressing depth toward the back of the layer, without moving anything.
+    ///
+    /// The result must also be in `[0, 1)`, and the curve must be non-decreasing, or entities
+    /// will be drawn out of order or outside the window. It's applied before
+    /// [`sort_resolution`](Self::sort_resolution)'s rounding.
+    fn offset_curve(&self, normalized_rank: f32) -> f32 {
+        normalized_rank
+    }
+
+    /// Whether this layer's entities are y-sorted. If `false`, they're all put at exactly
+    /// [`as_z_coordinate`](Self::as_z_coordinate) and left out of the sort entirely, so a layer
+    /// with lots of entities whose order doesn't matter, like a tile map, doesn't cost anything
+    /// to sort. Defaults to `true`; has no effect unless [`SpriteLayerOptions::y_sort`] is set.
+    fn y_sort(&self) -> bool {
+        true
+    }
+
+    /// Whether this layer is a stack, like a pile of cards: its entities are ordered purely by
+    /// when they were placed on it, ignoring their positions, with the most recently placed one
+    /// in front. An entity counts as placed when it's first managed on this layer, or when it
+    /// moves here from another layer; either way, it gets a new [`LayerSeq`]. Defaults to
+    /// `false`. Works whether or not [`SpriteLayerOptions::y_sort`] is set.
+    fn stack_order(&self) -> bool {
+        false
+    }
+
+    /// The name debug tooling, like [`debug_dump()`] and the occupancy overlay, labels this layer
+    /// with. Defaults to its `Debug` representation; override it if that's verbose, say for
+    /// data-driven layers that know their own names.
+    fn display_name(&self) -> Cow<'_, str> {
+        Cow::Owned(format!("{self:?}"))
+    }
+
+    /// The [`RenderLayers`] that entities on this layer are given, if the plugin was built with
+    /// [`SpriteLayerPlugin::with_layer_render_layers`]. Defaults to render layer 0, like entities
+    /// without a `RenderLayers` component. Only available with the `bevy_render` feature.
+    #[cfg(feature = "bevy_render")]
+    fn render_layers(&self) -> RenderLayers {
+        RenderLayers::default()
+    }
+}
+
+/// Like [`LayerIndex`], but the z-coordinate is computed with access to a context resource. Use
+/// this if your layers are defined at runtime, such as from a config file, so that the
+/// z-coordinate of each layer has to be looked up somewhere. The [`SpriteLayerPlugin`] accepts
+/// either kind of layer; every [`LayerIndex`] is a `LayerIndexWithContext` with [`NoContext`].
+///
+/// The plugin initializes the context resource if it doesn't exist yet, and reads it every frame,
+/// so changes to it take effect immediately.
+pub trait LayerIndexWithContext: Eq + Hash + Component + Clone + Debug {
+    /// The resource needed to compute z-coordinates.
+    type Context: Resource + FromWorld;
+
+    /// The same as [`LayerIndex::as_z_coordinate`], but with the context.
+    fn z_coordinate_in(&self, context: &Self::Context) -> f32;
+
+    /// The same as [`LayerIndex::sort_window`], but with the context.
+    fn sort_window_in(&self, _context: &Self::Context) -> f32 {
+        LAYER_WINDOW
+    }
+
+    /// The same as [`LayerIndex::sort_resolution`], but with the context.
+    fn sort_resolution_in(&self, _context: &Self::Context) -> Option<u32> {
+        None
+    }
+
+    /// The same as [`LayerIndex::offset_curve`], but with the context.
+    fn offset_curve_in(&self, _context: &Self::Context, normalized_rank: f32) -> f32 {
+        normalized_rank
+    }
+
+    /// The same as [`LayerIndex::y_sort`], but with the context.
+    fn y_sort_in(&self, _context: &Self::Context) -> bool {
+        true
+    }
+
+    /// The same as [`LayerIndex::stack_order`], but with the context.
+    fn stack_order_in(&self, _context: &Self::Context) -> bool {
+        false
+    }
+
+    /// The same as [`LayerIndex::display_name`], but with the context.
+    fn display_name_in(&self, _context: &Self::Context) -> Cow<'_, str> {
+        Cow::Owned(format!("{self:?}"))
+    }
+
+    /// The same as [`LayerIndex::render_layers`], but with the context.
+    #[cfg(feature = "bevy_render")]
+    fn render_layers_in(&self, _context: &Self::Context) -> RenderLayers {
+        RenderLayers::default()
+    }
+}
+
+/// The context of a [`LayerIndex`], which doesn't need any.
+#[derive(Debug, Default, Resource)]
+pub struct NoContext;
+
+impl<Layer: LayerIndex> LayerIndexWithContext for Layer {
+    type Context = NoContext;
+
+    fn z_coordinate_in(&self, _context: &NoContext) -> f32 {
+        self.as_z_coordinate()
+    }
+
+    fn sort_window_in(&self, _context: &NoContext) -> f32 {
+        self.sort_window()
+    }
+
+    fn sort_resolution_in(&self, _context: &NoContext) -> Option<u32> {
+        self.sort_resolution()
+    }
+
+    fn offset_curve_in(&self, _context: &NoContext, normalized_rank: f32) -> f32 {
+        self.offset_curve(normalized_rank)
+    }
+
+    fn y_sort_in(&self, _context: &NoContext) -> bool {
+        self.y_sort()
+    }
+
+    fn stack_order_in(&self, _context: &NoContext) -> bool {
+        self.stack_order()
+    }
+
+    fn display_name_in(&self, _context: &NoContext) -> Cow<'_, str> {
+        self.display_name()
+    }
+
+    #[cfg(feature = "bevy_render")]
+    fn render_layers_in(&self, _context: &NoContext) -> RenderLayers {
+        self.render_layers()
+    }
+}
+
+/// The default size of the range of z-coordinates that y-sorting spreads each layer's entities
+/// over (see [`LayerIndex::sort_window`]). An entity on a layer with z-coordinate `z` ends up
+/// somewhere in `z..z + LAYER_WINDOW`, so the z-coordinates of your layers should be at least this
+/// far apart.
+pub const LAYER_WINDOW: f32 = 1.0;
+
+/// Implements [`LayerIndex`] for a fieldless enum by listing its variants from back to front. The
+/// first variant gets z-coordinate 0, and each subsequent one is `gap` higher than the last
+/// (`gap` defaults to [`LAYER_WINDOW`]). Leaving a generous gap means you can add layers in
+/// between later without renumbering everything. Every variant must be listed exactly once.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use extol_sprite_layer::{layer_order, LayerIndex};
+/// #[derive(Debug, Clone, Component, PartialEq, Eq, Hash)]
+/// enum SpriteLayer {
+///     Background,
+///     Terrain,
+///     Actors,
+///     Foreground,
+/// }
+///
+/// layer_order!(SpriteLayer: Background, Terrain, Actors, Foreground; gap = 10.0);
+///
+/// assert_eq!(SpriteLayer::Background.as_z_coordinate(), 0.0);
+/// assert_eq!(SpriteLayer::Actors.as_z_coordinate(), 20.0);
+/// ```
+#[macro_export]
+macro_rules! layer_order {
+    ($layer:ident: $($variant:ident),+ $(,)?) => {
+        $crate::layer_order!($layer: $($variant),+; gap = $crate::LAYER_WINDOW);
+    };
+    ($layer:ident: $($variant:ident),+ $(,)?; gap = $gap:expr) => {
+        impl $crate::LayerIndex for $layer {
+            fn as_z_coordinate(&self) -> f32 {
+                // Lets the compiler number the variants for us.
+                #[allow(dead_code)]
+                enum Order {
+                    $($variant),+
+                }
+                let gap: f32 = $gap;
+                match self {
+                    $($layer::$variant => Order::$variant as u32 as f32 * gap),+
+                }
+            }
+        }
+    };
+}
+
+/// Estimates how many y-sorted entities can share a layer with the given z-coordinate before
+/// neighboring entities' z-coordinates become indistinguishable, because the spacing between
+/// adjacent `f32`s near `layer_z` is larger than the spacing between entities.
+///
+/// Compare this against the number of entities on that layer. Since precision gets worse as the
+/// magnitude of the z-coordinate grows, layers with larger magnitude z-coordinates can hold fewer
+/// entities.
+///
+/// ```
+/// # use extol_sprite_layer::max_entities_for_precision;
+/// assert_eq!(max_entities_for_precision(0.0), 1 << 23);
+/// assert_eq!(max_entities_for_precision(990.0), 1 << 14);
+/// ```
+pub fn max_entities_for_precision(layer_z: f32) -> usize {
+    // The top of the window is where the floats are sparsest.
+    let top = layer_z.abs() + LAYER_WINDOW;
+    let step = f32::from_bits(top.to_bits() + 1) - top;
+    (LAYER_WINDOW / step) as usize
+}
+
+/// The layer of every entity managed by the [`SpriteLayerPlugin`] for `Layer`, including ones
+/// that inherited their layer from an ancestor, as of the last time
+/// [`SpriteLayerSet::SetZCoordinates`] ran.
+#[derive(Debug, Resource)]
+pub struct PropagatedLayers<Layer> {
+    layers: LayerMap<Layer>,
+    /// The z-coordinate each `GlobalTransform` had before [`update_global_transforms`]
+    /// overwrote it, and the z-coordinate it was overwritten with.
+    original_z: EntityHashMap<(f32, f32)>,
+    /// For [`SpriteLayerOptions::layer_transition`]: the z-coordinate each entity that's moving
+    /// between layers started from, and how long ago its layer changed.
+    transitions: EntityHashMap<(f32, Duration)>,
+    /// Entities that got a provisional [`RenderZCoordinate`] from [`insert_provisional_z`] since
+    /// the last frame.
+    provisional: EntityHashSet,
+    /// For [`SpriteLayerOptions::freeze_y_sort`]: each entity's offset above its layer's
+    /// z-coordinate from the last time it was y-sorted.
+    offsets: EntityHashMap<f32>,
+    /// For [`ApplyTo::BiasAdd`]: each entity's offset within its layer's window, without its
+    /// [`SceneDepthOffset`] or the plugin's z base.
+    biases: EntityHashMap<f32>,
+}
+
+impl<Layer> Default for PropagatedLayers<Layer> {
+    fn default() -> Self {
+        Self {
+            layers: Default::default(),
+            original_z: Default::default(),
+            transitions: Default::default(),
+            provisional: Default::default(),
+            offsets: Default::default(),
+            biases: Default::default(),
+        }
+    }
+}
+
+impl<Layer> PropagatedLayers<Layer> {
+    /// The layer of the given entity, or `None` if it isn't managed by the plugin.
+    pub fn get(&self, entity: Entity) -> Option<&Layer> {
+        self.layers.get(&entity)
+    }
+
+    /// Whether the given entity is managed by the plugin.
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.layers.contains_key(&entity)
+    }
+
+    /// Iterates over every managed entity and its layer, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &Layer)> {
+        self.layers.iter()
+    }
+
+    /// Iterates over every layer that at least one managed entity has, and how many have it, in
+    /// arbitrary order.
+    pub fn layer_counts(&self) -> impl Iterator<Item = (&Layer, usize)> {
+        let mut counts = vec![0; self.layers.distinct.len()];
+        for index in self.layers.entities.values() {
+            counts[*index] += 1;
+        }
+        self.layers
+            .distinct
+            .iter()
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+    }
+
+    /// The sum of the [`SceneDepthOffset`]s of the given entity and its ancestors, or `0.0` if it
+    /// isn't managed by the plugin.
+    pub fn scene_offset(&self, entity: Entity) -> f32 {
+        self.layers.scene_offset(&entity)
+    }
+
+    /// The number of managed entities.
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Whether there are no managed entities.
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+}
+
+/// A map from entities to their layers. Each distinct layer is only stored once, so entities
+/// don't need their own copy of a layer that's expensive to clone.
+#[derive(Debug)]
+pub struct LayerMap<Layer> {
+    /// Every distinct layer, in arbitrary order.
+    distinct: Vec<Layer>,
+    /// The index of each entity's layer in `distinct`.
+    entities: EntityHashMap<usize>,
+    /// The total [`SceneDepthOffset`] of each entity that has a nonzero one.
+    scene_offsets: EntityHashMap<f32>,
+}
+
+impl<Layer> Default for LayerMap<Layer> {
+    fn default() -> Self {
+        Self {
+            distinct: Vec::new(),
+            entities: Default::default(),
+            scene_offsets: Default::default(),
+        }
+    }
+}
+
+impl<Layer> std::ops::Index<&Entity> for LayerMap<Layer> {
+    type Output = Layer;
+
+    fn index(&self, entity: &Entity) -> &Layer {
+        &self.distinct[self.entities[entity]]
+    }
+}
+
+impl<Layer> LayerMap<Layer> {
+    /// The layer of the given entity, if it has one.
+    pub fn get(&self, entity: &Entity) -> Option<&Layer> {
+        self.entities
+            .get(entity)
+            .map(|index| &self.distinct[*index])
+    }
+
+    /// Whether the given entity has a layer.
+    pub fn contains_key(&self, entity: &Entity) -> bool {
+        self.entities.contains_key(entity)
+    }
+
+    /// Iterates over every entity, in arbitrary order.
+    pub fn keys(&self) -> impl Iterator<Item = &Entity> {
+        self.entities.keys()
+    }
+
+    /// Iterates over every entity and its layer, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &Layer)> {
+        self.entities
+            .iter()
+            .map(|(entity, index)| (*entity, &self.distinct[*index]))
+    }
+
+    /// Every distinct layer, in arbitrary order. This may include layers that no entity has
+    /// anymore.
+    pub fn distinct_layers(&self) -> &[Layer] {
+        &self.distinct
+    }
+
+    /// The index of the given entity's layer in [`distinct_layers`](Self::distinct_layers).
+    pub fn layer_index(&self, entity: &Entity) -> Option<usize> {
+        self.entities.get(entity).copied()
+    }
+
+    /// The sum of the [`SceneDepthOffset`]s of the given entity and its ancestors.
+    pub fn scene_offset(&self, entity: &Entity) -> f32 {
+        self.scene_offsets.get(entity).copied().unwrap_or(0.0)
+    }
+
+    /// The number of entities.
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Whether there are no entities.
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+#[cfg(all(test, feature = "bevy_sprite"))]
+impl<Layer: PartialEq + Clone> LayerMap<Layer> {
+    /// Gives `entity` the given layer, without cloning it if it's already present.
+    pub(crate) fn insert(&mut self, entity: Entity, layer: &Layer) {
+        let index = match self.distinct.iter().position(|existing| existing == layer) {
+            Some(index) => index,
+            None => {
+                self.distinct.push(layer.clone());
+                self.distinct.len() - 1
+            }
+        };
+        self.entities.insert(entity, index);
+    }
+}
+
+/// Propagates the `Layer` of each entity to itself and all of its descendants, returning the
+/// resulting layer of each entity. Entities that set their own `Layer` override the one they'd
+/// otherwise inherit. Unlayered ancestors are skipped over, so a layered entity whose ancestors
+/// have no layer is treated the same as a layered root.
+///
+/// [`SceneDepthOffset`]s are accumulated along the way, starting from the layered roots'
+/// ancestors.
+///
+/// If the plugin was built with `SpriteLayerPlugin::with_render_layers` (with the `bevy_render`
+/// feature), entities that aren't on those render layers are left out of the result (but their
+/// descendants can still inherit their layer).
+#[allow(clippy::too_many_arguments)]
+pub fn propagate_layers<Layer: LayerIndexWithContext>(
+    recursive_query: Query<(Option<&Children>, Option<&Layer>, Option<&SceneDepthOffset>)>,
+    seed_query: Query<(Entity, &Layer, Option<&Parent>)>,
+    ancestor_query: Query<(Option<&Parent>, Has<Layer>)>,
+    scene_offset_query: Query<&SceneDepthOffset>,
+    options: Res<SpriteLayerOptions>,
+    #[cfg(feature = "bevy_render")] settings: Res<SpriteLayerSettings<Layer>>,
+    #[cfg(feature = "bevy_render")] render_layers_query: Query<&RenderLayers>,
+    mut size: Local<usize>,
+) -> LayerMap<Layer> {
+    let mut layer_map = LayerMap::default();
+    layer_map.entities.reserve(*size);
+    let mut indices = HashMap::<&Layer, usize>::default();
+    for (entity, layer, parent) in &seed_query {
+        // Anything with a layered ancestor will get visited when we propagate from that ancestor.
+        if !has_layered_ancestor(parent, &ancestor_query) {
+            // Scene roots above the layered root still apply.
+            let scene_offset =
+                parent
+                    .filter(|_| !scene_offset_query.is_empty())
+                    .map_or(0.0, |parent| {
+                        let parent_of =
+                            |entity| ancestor_query.get(entity).ok()?.0.map(Parent::get);
+                        self_and_ancestors(parent.get(), parent_of)
+                            .filter_map(|entity| scene_offset_query.get(entity).ok())
+                            .map(|offset| offset.0)
+                            .sum()
+                    });
+            let complete = propagate_layers_impl(
+                entity,
+                None,
+                layer,
+                scene_offset,
+                &recursive_query,
+                &mut indices,
+                &mut layer_map,
+                options.max_hierarchy_depth,
+            );
+            if !complete {
+                error_once!(
+                    "the hierarchy under {entity} is more than {} levels deep; deeper entities \
+                     won't get a layer (see SpriteLayerOptions::max_hierarchy_depth)",
+                    options.max_hierarchy_depth
+                );
+            }
+        }
+    }
+    #[cfg(feature = "bevy_render")]
+    retain_render_layers(&mut layer_map, &settings, &render_layers_query);
+    *size = size.max(layer_map.len());
+    layer_map
+}
+
+/// Collects the layer of every entity that has one, without propagating them to descendants. Used
+/// by [`FlatSpriteLayerPlugin`] in place of [`propagate_layers`].
+pub fn collect_layers<Layer: LayerIndexWithContext>(
+    query: Query<(Entity, &Layer, Option<&SceneDepthOffset>)>,
+    #[cfg(feature = "bevy_render")] settings: Res<SpriteLayerSettings<Layer>>,
+    #[cfg(feature = "bevy_render")] render_layers_query: Query<&RenderLayers>,
+) -> LayerMap<Layer> {
+    let mut layer_map = LayerMap::default();
+    layer_map.entities.reserve(query.iter().len());
+    let mut indices = HashMap::<&Layer, usize>::default();
+    for (entity, layer, scene_offset) in &query {
+        let index = *indices.entry(layer).or_insert_with(|| {
+            layer_map.distinct.push(layer.clone());
+            layer_map.distinct.len() - 1
+        });
+        layer_map.entities.insert(entity, index);
+        if let Some(offset) = scene_offset.filter(|offset| offset.0 != 0.0) {
+            layer_map.scene_offsets.insert(entity, offset.0);
+        }
+    }
+    #[cfg(feature = "bevy_render")]
+    retain_render_layers(&mut layer_map, &settings, &render_layers_query);
+    layer_map
+}
+
+/// Drops the entities whose [`RenderLayers`] don't intersect the ones the plugin was built with,
+/// if any. See [`SpriteLayerPlugin::with_render_layers`].
+#[cfg(feature = "bevy_render")]
+pub(crate) fn retain_render_layers<Layer>(
+    layer_map: &mut LayerMap<Layer>,
+    settings: &SpriteLayerSettings<Layer>,
+    render_layers_query: &Query<&RenderLayers>,
+) {
+    if let Some(render_layers) = &settings.render_layers {
+        layer_map.entities.retain(|entity, _| {
+            render_layers_query
+                .get(*entity)
+                .unwrap_or(&RenderLayers::default())
+                .intersects(render_layers)
+        });
+    }
+}
+
+/// Whether any ancestor of the entity with the given parent has a `Layer`.
+pub(crate) fn has_layered_ancestor<Layer: LayerIndexWithContext>(
+    parent: Option<&Parent>,
+    ancestor_query: &Query<(Option<&Parent>, Has<Layer>)>,
+) -> bool {
+    let Some(parent) = parent else {
+        return false;
+    };
+    let parent_of = |entity| ancestor_query.get(entity).ok()?.0.map(Parent::get);
+    self_and_ancestors(parent.get(), parent_of).any(|entity| {
+        ancestor_query
+            .get(entity)
+            .is_ok_and(|(_, has_layer)| has_layer)
+    })
+}
+
+/// Iterates over `entity` and then each of its ancestors in turn, using `parent_of` to find an
+/// entity's parent. Stops early if the hierarchy has a cycle, so a malformed hierarchy can't hang
+/// us.
+pub(crate) fn self_and_ancestors(
+    entity: Entity,
+    parent_of: impl Fn(Entity) -> Option<Entity>,
+) -> impl Iterator<Item = Entity> {
+    // Brent's algorithm: a cycle will eventually hit a checkpoint.
+    let (mut checkpoint, mut steps, mut limit) = (entity, 0, 1);
+    std::iter::successors(Some(entity), move |entity| {
+        let parent = parent_of(*entity)?;
+        if parent == checkpoint {
+            warn_once!("found a cycle in the entity hierarchy involving {parent}; ignoring it");
+            return None;
+        }
+        steps += 1;
+        if steps == limit {
+            checkpoint = parent;
+            steps = 0;
+            limit *= 2;
+        }
+        Some(parent)
+    })
+}
+
+/// Recursive impl for [`propagate_layers`]. `propagated` is the index of the layer inherited from
+/// the parent (which is `parent_layer`), if any; `indices` maps each distinct layer to its index.
+/// Returns false if it stopped descending because `depth_left` ran out.
+///
+/// Entities that are already in `layer_map` are skipped, which can only happen if the hierarchy
+/// is malformed (an entity listed as the child of several entities, or a cycle).
+#[allow(clippy::too_many_arguments)]
+fn propagate_layers_impl<'a, Layer: LayerIndexWithContext>(
+    entity: Entity,
+    propagated: Option<usize>,
+    parent_layer: &'a Layer,
+    parent_scene_offset: f32,
+    query: &'a Query<(Option<&Children>, Option<&Layer>, Option<&SceneDepthOffset>)>,
+    indices: &mut HashMap<&'a Layer, usize>,
+    layer_map: &mut LayerMap<Layer>,
+    depth_left: usize,
+) -> bool {
+    if depth_left == 0 {
+        return false;
+    }
+    if layer_map.entities.contains_key(&entity) {
+        warn_once!(
+            "{entity} was reached twice while propagating layers; the hierarchy is malformed"
+        );
+        return true;
+    }
+    // This can only fail if a child was despawned without being removed from its parent.
+    let Ok((children, layer, scene_offset)) = query.get(entity) else {
+        return true;
+    };
+    let scene_offset = parent_scene_offset + scene_offset.map_or(0.0, |offset| offset.0);
+    let (index, layer) = match (propagated, layer) {
+        // Most entities have the same layer as their parent, so skip hashing those.
+        (Some(index), None) => (index, parent_layer),
+        (Some(index), Some(layer)) if layer == parent_layer => (index, parent_layer),
+        (_, layer) => {
+            let layer = layer.unwrap_or(parent_layer);
+            let index = *indices.entry(layer).or_insert_with(|| {
+                layer_map.distinct.push(layer.clone());
+                layer_map.distinct.len() - 1
+            });
+            (index, layer)
+        }
+    };
+    layer_map.entities.insert(entity, index);
+    if scene_offset != 0.0 {
+        layer_map.scene_offsets.insert(entity, scene_offset);
+    }
+
+    let Some(children) = children else {
+        return true;
+    };
+
+    let mut complete = true;
+    for child in children {
+        complete &= propagate_layers_impl(
+            *child,
+            Some(index),
+            layer,
+            scene_offset,
+            query,
+            indices,
+            layer_map,
+            depth_left - 1,
+        );
+    }
+    complete
+}
+
+/// Inserts a provisional [`RenderZCoordinate`] into an entity that was just given a layer. See
+/// [`SpriteLayerPlugin::with_provisional_z`].
+pub fn insert_provisional_z<Layer: LayerIndexWithContext>(
+    trigger: Trigger<OnAdd, Layer>,
+    query: Query<&Layer, Without<RenderZCoordinate>>,
+    context: Res<Layer::Context>,
+    settings: Res<SpriteLayerSettings<Layer>>,
+    mut propagated: ResMut<PropagatedLayers<Layer>>,
+    mut commands: Commands,
+) {
+    let entity = trigger.entity();
+    let Ok(layer) = query.get(entity) else {
+        return;
+    };
+    let z = layer.z_coordinate_in(&context) + settings.z_base;
+    commands.entity(entity).try_insert(RenderZCoordinate(z));
+    propagated.provisional.insert(entity);
+}
+
+/// Compute the z-coordinate that each entity should have and store it in its
+/// [`RenderZCoordinate`]. This is equal to its layer's equivalent z-coordinate, plus an offset in
+/// the range `[0, layer.sort_window())` corresponding to its y-sorted position (if y-sorting is
+/// enabled).
+///
+/// Entities that were managed last frame but aren't anymore have their `RenderZCoordinate`
+/// removed. Afterwards, [`PropagatedLayers`] is updated to hold `layers`, minus any entities
+/// skipped because of [`SpriteLayerOptions::missing_transform`].
+///
+/// Newly managed entities get their `RenderZCoordinate` through [`Commands`] using `try_insert`,
+/// so entities that are despawned before the commands are applied are silently ignored.
+#[allow(clippy::too_many_arguments)]
+pub fn compute_render_z_coordinates<Layer: LayerIndexWithContext>(
+    In(mut layers): In<LayerMap<Layer>>,
+    inputs: SortInputs,
+    #[cfg(feature = "y_sort")] mut y_sorting: YSortParams<Layer>,
+    mut z_query: Query<&mut RenderZCoordinate>,
+    options: Res<SpriteLayerOptions>,
+    mut propagated: ResMut<PropagatedLayers<Layer>>,
+    context: Res<Layer::Context>,
+    settings: Res<SpriteLayerSettings<Layer>>,
+    mut next_seq: Local<u64>,
+    mut bounds: ResMut<SpriteLayerZBounds<Layer>>,
+    mut active_layers: ResMut<ActiveLayers<Layer>>,
+    mut claims: ResMut<GroupClaims>,
+    mut commands: Commands,
+) {
+    #[cfg(not(feature = "y_sort"))]
+    if options.y_sort {
+        warn_once!(
+            "SpriteLayerOptions::y_sort is enabled, but extol_sprite_layer was built without the \
+             y_sort feature, so nothing will be y-sorted"
+        );
+    }
+    // Plugins higher up in the group have already claimed their entities this frame.
+    if let Some(rank) = settings.group_rank {
+        claims.0.retain(|_, owner| *owner != rank);
+        layers
+            .entities
+            .retain(|entity, _| claims.get(*entity).filter(|owner| *owner > rank).is_none());
+    }
+    if cfg!(feature = "y_sort")
+        && options.y_sort
+        && options.missing_transform != MissingTransform::Origin
+    {
+        let missing = layers
+            .keys()
+            .filter(|entity| {
+                !inputs.transforms.contains(**entity) && !inputs.positions.contains(**entity)
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        for entity in missing {
+            if options.missing_transform == MissingTransform::Skip {
+                layers.entities.remove(&entity);
+            } else if !propagated.layers.contains_key(&entity) {
+                warn!(
+                    "{entity} has a sprite layer but no GlobalTransform or SortPosition, so it's \
+                     being y-sorted as if it were at the origin"
+                );
+            }
+        }
+    }
+    if let Some(active) = &inputs.active {
+        layers
+            .entities
+            .retain(|entity, _| active.0.contains(entity));
+    }
+    if let Some(rank) = settings.group_rank {
+        claims
+            .0
+            .extend(layers.entities.keys().map(|entity| (*entity, rank)));
+    }
+    let provisional = std::mem::take(&mut propagated.provisional);
+    for entity in propagated.layers.keys().chain(&provisional) {
+        // Managed by another plugin in the group now, which takes care of its z-coordinate.
+        let claimed = claims
+            .get(*entity)
+            .is_some_and(|owner| Some(owner) != settings.group_rank);
+        if !layers.contains_key(entity) && !claimed {
+            if let Some(mut entity) = commands.get_entity(*entity) {
+                entity.remove::<RenderZCoordinate>();
+            }
+        }
+    }
+    let mut in_use = vec![false; layers.distinct.len()];
+    for index in layers.entities.values() {
+        in_use[*index] = true;
+    }
+    let in_use = layers
+        .distinct
+        .iter()
+        .zip(in_use)
+        .filter_map(|(layer, used)| used.then_some(layer));
+    if in_use.clone().count() != active_layers.0.len()
+        || !in_use.clone().all(|layer| active_layers.0.contains(layer))
+    {
+        active_layers.0 = in_use.cloned().collect();
+    }
+    if layers.is_empty() {
+        if let Some(on_sorted) = &settings.on_sorted {
+            on_sorted(&[]);
+        }
+        propagated.layers = layers;
+        propagated.transitions.clear();
+        propagated.offsets.clear();
+        propagated.biases.clear();
+        return;
+    }
+
+    let y_sort = cfg!(feature = "y_sort") && options.y_sort;
+    // Stacked layers are ordered by `LayerSeq` instead; layers that opt out of y-sorting are put
+    // at their base z. Either way, they're left out of everything below.
+    let stacked = layers
+        .distinct_layers()
+        .iter()
+        .map(|layer| layer.stack_order_in(&context))
+        .collect::<Vec<_>>();
+    let unsorted = layers
+        .distinct_layers()
+        .iter()
+        .map(|layer| y_sort && !layer.y_sort_in(&context))
+        .collect::<Vec<_>>();
+    let set_aside = |index: usize| stacked[index] || unsorted[index];
+    let rest = (0..layers.distinct.len()).any(set_aside).then(|| LayerMap {
+        distinct: layers.distinct.clone(),
+        entities: layers
+            .entities
+            .iter()
+            .filter(|(_, index)| !set_aside(**index))
+            .map(|(entity, index)| (*entity, *index))
+            .collect(),
+        scene_offsets: EntityHashMap::default(),
+    });
+
+    let mut new_seqs = EntityHashMap::<u64>::default();
+    if options.spawn_order_tiebreak || stacked.contains(&true) {
+        let mut new_entities = layers
+            .entities
+            .iter()
+            .filter(|(entity, index)| {
+                let placed = stacked[**index]
+                    && propagated.layers.get(entity) != Some(&layers.distinct[**index]);
+                placed
+                    || (!inputs.seqs.contains(**entity)
+                        && (options.spawn_order_tiebreak || stacked[**index]))
+            })
+            .map(|(entity, _)| *entity)
+            .collect::<Vec<_>>();
+        // Entities that show up in the same frame are ordered by `Entity`, like without this
+        // option.
+        new_entities.sort();
+        for entity in new_entities {
+            new_seqs.insert(entity, *next_seq);
+            commands.entity(entity).try_insert(LayerSeq(*next_seq));
+            *next_seq += 1;
+        }
+    }
+    #[cfg(feature = "y_sort")]
+    if options.y_sort {
+        y_sorting.update_frozen(&layers, &z_query);
+    }
+
+    // `as_z_coordinate` might not be trivial, so only call it once per distinct layer.
+    let layer_zs = layers
+        .distinct_layers()
+        .iter()
+        .map(|layer| layer.z_coordinate_in(&context))
+        .collect::<Vec<_>>();
+    // Where each layer's window starts, how large it is, and its resolution.
+    let windows = layers
+        .distinct_layers()
+        .iter()
+        .zip(&layer_zs)
+        .map(|(layer, z)| {
+            let window = layer.sort_window_in(&context);
+            let start = if options.offset_center {
+                z - window / 2.0
+            } else {
+                *z
+            };
+            (start, window, layer.sort_resolution_in(&context))
+        })
+        .collect::<Vec<_>>();
+    // Overlays are placed after everything else.
+    let is_overlay = |entity: &Entity| inputs.is_overlay(entity);
+
+    let mut sorted = Vec::new();
+    let scene_offsets = &layers.scene_offsets;
+    let (mut min_z, mut max_z) = (f32::INFINITY, f32::NEG_INFINITY);
+    let PropagatedLayers {
+        layers: previous_layers,
+        transitions,
+        offsets,
+        biases,
+        ..
+    } = &mut *propagated;
+    let bias = options.apply_to == ApplyTo::BiasAdd;
+    biases.clear();
+    let freeze = y_sort && options.freeze_y_sort;
+    offsets.retain(|entity, _| y_sort && layers.contains_key(entity));
+    // Set aside while frozen, since `set_z` only records offsets while y-sorting.
+    let frozen_offsets = if freeze {
+        std::mem::take(offsets)
+    } else {
+        EntityHashMap::default()
+    };
+    let transition = options
+        .layer_transition
+        .zip(inputs.time.as_ref().map(|time| time.delta()));
+    match transition {
+        Some((_, delta)) => transitions.retain(|entity, (_, elapsed)| {
+            *elapsed += delta;
+            layers.contains_key(entity)
+        }),
+        None => transitions.clear(),
+    }
+    // Only written when it differs, so `Changed<RenderZCoordinate>` means it actually moved.
+    let mut set_z = |entity: Entity, z: f32| {
+        if y_sort && !freeze && !is_overlay(&entity) {
+            offsets.insert(entity, z - layer_zs[layers.entities[&entity]]);
+        }
+        if bias && !is_overlay(&entity) {
+            biases.insert(entity, z - windows[layers.entities[&entity]].0);
+        }
+        let mut z = z + settings.z_base + scene_offsets.get(&entity).copied().unwrap_or(0.0);
+        if let Some((duration, _)) = transition {
+            let previous = previous_layers.get(&entity);
+            if previous.is_some() && previous != layers.get(&entity) {
+                if let Ok(render_z) = z_query.get(entity) {
+                    transitions.insert(entity, (render_z.0, Duration::ZERO));
+                }
+            }
+            if let Some((from, elapsed)) = transitions.get(&entity) {
+                let progress = elapsed.as_secs_f32() / duration.as_secs_f32();
+                if progress < 1.0 {
+                    z = from + (z - from) * progress;
+                } else {
+                    transitions.remove(&entity);
+                }
+            }
+        }
+        min_z = min_z.min(z);
+        max_z = max_z.max(z);
+        if settings.on_sorted.is_some() {
+            sorted.push((entity, z));
+        }
+        match z_query.get_mut(entity) {
+            Ok(mut render_z) => {
+                if render_z.0 != z {
+                    render_z.0 = z;
+                }
+            }
+            Err(_) => {
+                commands.entity(entity).try_insert(RenderZCoordinate(z));
+            }
+        }
+    };
+
+    if rest.is_some() {
+        let seq_of = |entity: &Entity| {
+            new_seqs
+                .get(entity)
+                .copied()
+                .or_else(|| inputs.seqs.get(*entity).ok().map(|seq| seq.0))
+        };
+        let mut stack = Vec::new();
+        for (entity, index) in &layers.entities {
+            if is_overlay(entity) {
+                continue;
+            }
+            if stacked[*index] {
+                stack.push(*entity);
+            } else if unsorted[*index] {
+                set_z(*entity, layer_zs[*index]);
+            }
+        }
+        stack.sort_by_cached_key(|entity| (seq_of(entity), *entity));
+        spread_within_layers(
+            stack,
+            &layers,
+            &windows,
+            &context,
+            is_overlay,
+            None,
+            options.max_slots_per_layer,
+            &inputs,
+            &mut set_z,
+        );
+    }
+    let main = rest.as_ref().unwrap_or(&layers);
+    if freeze {
+        for (entity, index) in &main.entities {
+            if !is_overlay(entity) {
+                let offset = frozen_offsets.get(entity).copied().unwrap_or(0.0);
+                set_z(*entity, layer_zs[*index] + offset);
+            }
+        }
+    } else if y_sort {
+        #[cfg(feature = "y_sort")]
+        y_sorting.sort(
+            main, &inputs, &options, &new_seqs, &windows, &context, &mut set_z,
+        );
+    } else if options.within_layer_tiebreak {
+        // Spread the layers out like y-sorting does, but with every entity at the same y.
+        let mut ordered = main
+            .keys()
+            .filter(|entity| !is_overlay(entity))
+            .copied()
+            .collect::<Vec<_>>();
+        ordered.sort_by_cached_key(|entity| {
+            let seq = options.spawn_order_tiebreak.then(|| {
+                inputs
+                    .seqs
+                    .get(*entity)
+                    .map_or_else(|_| new_seqs[entity], |seq| seq.0)
+            });
+            (seq, *entity)
+        });
+        if inputs.tiebreak.0.is_some() {
+            // Stable, so it keeps the order above for anything the custom tiebreak considers tied.
+            ordered.sort_by(|a, b| inputs.compare_tied(*a, *b));
+        }
+        let ordered = apply_locked_indices(ordered, main, &inputs.locked, is_overlay);
+        spread_within_layers(
+            ordered,
+            main,
+            &windows,
+            &context,
+            is_overlay,
+            None,
+            options.max_slots_per_layer,
+            &inputs,
+            &mut set_z,
+        );
+    } else {
+        for (entity, index) in &main.entities {
+            if !is_overlay(entity) {
+                set_z(*entity, layer_zs[*index]);
+            }
+        }
+    }
+
+    let mut overlays = inputs
+        .overlays
+        .iter()
+        .filter(|(entity, _)| layers.contains_key(entity))
+        .map(|(entity, overlay)| (overlay.0, entity))
+        .collect::<Vec<_>>();
+    if !overlays.is_empty() {
+        overlays.sort();
+        let ceiling = windows
+            .iter()
+            .map(|(base, window, _)| base + window)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let count = overlays.len() as f32;
+        let scale_factor = LAYER_WINDOW / count;
+        let mut overlay_biases = Vec::new();
+        for (i, (_, entity)) in overlays.into_iter().enumerate() {
+            set_z(entity, ceiling + i as f32 * scale_factor);
+            if bias {
+                // Just past the top of their own layer's window, in the same order, instead of
+                // all the way up at the ceiling.
+                let window = windows[layers.entities[&entity]].1;
+                overlay_biases.push((entity, window * (1.0 + i as f32 / count)));
+            }
+        }
+        biases.extend(overlay_biases);
+    }
+
+    if let Some(on_sorted) = &settings.on_sorted {
+        // Already sorted if we y-sorted, but this is cheap in that case.
+        sorted.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        on_sorted(&sorted);
+    }
+
+    // Everything could have been skipped, say, because it's missing a transform.
+    if min_z <= max_z {
+        bounds.min = min_z;
+        bounds.max = max_z;
+    }
+    if freeze {
+        *offsets = frozen_offsets;
+    }
+    *previous_layers = layers;
+}
+
+/// Places each layer's entities over its own window, in the given order, with
+/// [`z_values::spread_over_windows`]. `windows` holds each distinct layer's z-coordinate, sort
+/// window and sort resolution.
+///
+/// If `slot_key` is given, consecutive entities on the same layer with the same key share a slot
+/// (see [`SpriteLayerOptions::sort_epsilon`]). Layers with more than `max_slots` slots are
+/// rounded down to that many (see [`SpriteLayerOptions::max_slots_per_layer`]). Offsets are
+/// scaled by the entities' [`ZSlice`]s.
+#[allow(clippy::too_many_arguments)]
+fn spread_within_layers<Layer: LayerIndexWithContext>(
+    order: Vec<Entity>,
+    layers: &LayerMap<Layer>,
+    windows: &[(f32, f32, Option<u32>)],
+    context: &Layer::Context,
+    is_overlay: impl Fn(&Entity) -> bool,
+    slot_key: Option<&dyn Fn(Entity) -> i64>,
+    max_slots: Option<u32>,
+    inputs: &SortInputs,
+    mut set_z: impl FnMut(Entity, f32),
+) {
+    let order = order
+        .into_iter()
+        .filter(|entity| !is_overlay(entity))
+        .collect::<Vec<_>>();
+    let items = order
+        .iter()
+        .map(|entity| z_values::SpreadItem {
+            layer: layers.entities[entity],
+            slot_key: slot_key.map(|slot_key| slot_key(*entity)),
+            slice: inputs.slice_of(*entity),
+        })
+        .collect::<Vec<_>>();
+    let z_values = z_values::spread_over_windows(&items, windows, max_slots, |index, rank| {
+        layers.distinct[index].offset_curve_in(context, rank)
+    });
+    for (entity, z) in order.into_iter().zip(z_values) {
+        set_z(entity, z);
+    }
+}
+
+/// Moves each entity with a [`LockedSortIndex`] to its slot in its layer's part of `order`,
+/// keeping everyone else in the same order around it. Only the order within each layer is
+/// preserved; the layers' parts are concatenated, and overlays are left out.
+fn apply_locked_indices<Layer>(
+    order: Vec<Entity>,
+    layers: &LayerMap<Layer>,
+    locked: &Query<(Entity, &LockedSortIndex)>,
+    is_overlay: impl Fn(&Entity) -> bool,
+) -> Vec<Entity> {
+    if locked.is_empty() {
+        return order;
+    }
+    let mut by_layer = vec![(Vec::new(), Vec::new()); layers.distinct.len()];
+    for entity in order {
+        let Some(index) = layers.layer_index(&entity).filter(|_| !is_overlay(&entity)) else {
+            continue;
+        };
+        let (unlocked, pinned) = &mut by_layer[index];
+        match locked.get(entity) {
+            Ok((_, slot)) => pinned.push((slot.0 as usize, entity)),
+            Err(_) => unlocked.push(entity),
+        }
+    }
+    let mut result = Vec::with_capacity(layers.len());
+    for (unlocked, mut pinned) in by_layer {
+        pinned.sort();
+        let count = unlocked.len() + pinned.len();
+        let start = result.len();
+        let mut unlocked = unlocked.into_iter();
+        let mut next_slot = 0;
+        for (i, (slot, entity)) in pinned.iter().enumerate() {
+            // Ties take the next free slot, and slots past the end are pulled back so that every
+            // locked entity still fits.
+            let slot = (*slot).max(next_slot).min(count - (pinned.len() - i));
+            while result.len() - start < slot {
+                result.extend(unlocked.next());
+            }
+            result.push(*entity);
+            next_slot = slot + 1;
+        }
+        result.extend(unlocked);
+    }
+    result
+}
+
+/// The components [`compute_render_z_coordinates`] reads to sort entities.
+#[derive(SystemParam)]
+pub struct SortInputs<'w, 's> {
+    transforms: Query<'w, 's, &'static GlobalTransform>,
+    positions: Query<'w, 's, &'static SortPosition>,
+    seqs: Query<'w, 's, &'static LayerSeq>,
+    overlays: Query<'w, 's, (Entity, &'static TopmostOverlay)>,
+    active: Option<Res<'w, ActiveSortSet>>,
+    tiebreak: Res<'w, SortTiebreak>,
+    time: Option<Res<'w, Time>>,
+    locked: Query<'w, 's, (Entity, &'static LockedSortIndex)>,
+    slices: Query<'w, 's, &'static ZSlice>,
+}
+
+impl SortInputs<'_, '_> {
+    /// Whether the entity is placed above everything else by a [`TopmostOverlay`].
+    fn is_overlay(&self, entity: &Entity) -> bool {
+        self.overlays.contains(*entity)
+    }
+
+    /// The fraction of its offset within its layer's window that the entity gets. See [`ZSlice`].
+    fn slice_of(&self, entity: Entity) -> f32 {
+        self.slices
+            .get(entity)
+            .map_or(1.0, |slice| slice.0.clamp(0.0, 1.0))
+    }
+
+    /// Orders two entities that are otherwise tied with the [`SortTiebreak`], if there is one.
+    fn compare_tied(&self, a: Entity, b: Entity) -> Ordering {
+        self.tiebreak
+            .0
+            .as_ref()
+            .map_or(Ordering::Equal, |compare| compare(a, b))
+    }
+}
+
+/// Copies the [`RenderZCoordinate`] of each entity in [`PropagatedLayers`] into its
+/// [`GlobalTransform`], if [`SpriteLayerOptions::apply_to`] is [`ApplyTo::GlobalTransform`], or
+/// adds its offset within its layer's window to it for [`ApplyTo::BiasAdd`] (see there for what
+/// that includes), passing the result through the [`ZRemap`], if any.
+///
+/// The written z-coordinate stays in the `GlobalTransform` until transform propagation next
+/// recomputes it. Entities that stop being managed (or all of them, if `apply_to` changes) get
+/// back the z-coordinate transform propagation had computed for them, unless it's been recomputed
+/// since.
+///
+/// With the `bevy_ui` feature, UI `Node`s are left alone, since the UI lays them out itself; they
+/// still get a [`RenderZCoordinate`], and a warning is logged the first time each one is skipped.
+pub fn update_global_transforms<Layer: LayerIndexWithContext>(
+    mut query: Query<(Option<&RenderZCoordinate>, &mut GlobalTransform), NotUiNode>,
+    mut layers: ResMut<PropagatedLayers<Layer>>,
+    options: Res<SpriteLayerOptions>,
+    remap: Res<ZRemap>,
+    #[cfg(feature = "bevy_ui")] ui_nodes: Query<(), With<bevy::ui::Node>>,
+    #[cfg(feature = "bevy_ui")] mut warned: Local<EntityHashSet>,
+) {
+    let bias = options.apply_to == ApplyTo::BiasAdd;
+    let apply = bias || options.apply_to == ApplyTo::GlobalTransform;
+    let PropagatedLayers {
+        layers,
+        original_z,
+        biases,
+        ..
+    } = &mut *layers;
+    original_z.retain(|entity, (original, written)| {
+        if apply && layers.contains_key(entity) {
+            return true;
+        }
+        if let Ok((_, mut transform)) = query.get_mut(*entity) {
+            if transform.translation().z == *written {
+                set_transform_z(&mut transform, *original, options.mark_transforms_changed);
+            }
+        }
+        false
+    });
+    if !apply {
+        return;
+    }
+    #[cfg(feature = "bevy_ui")]
+    {
+        warned.retain(|entity| layers.contains_key(entity));
+        for entity in layers.keys() {
+            if ui_nodes.contains(*entity) && warned.insert(*entity) {
+                warn!(
+                    "{entity} is a UI node with a {}; not touching its transform",
+                    std::any::type_name::<Layer>()
+                );
+            }
+        }
+    }
+    for entity in layers.keys() {
+        if let Ok((Some(z), mut transform)) = query.get_mut(*entity) {
+            let current = transform.translation().z;
+            let (original, written) = original_z.entry(*entity).or_insert((current, current));
+            // Anything other than what we wrote last frame came from transform propagation.
+            if current != *written {
+                *original = current;
+            }
+            let z = if bias {
+                *original + biases.get(entity).copied().unwrap_or(0.0)
+            } else {
+                z.0
+            };
+            *written = remap.0.as_ref().map_or(z, |remap| remap(z));
+            set_transform_z(&mut transform, *written, options.mark_transforms_changed);
+        }
+    }
+}
+
+/// Filters out UI nodes, which [`update_global_transforms`] doesn't touch.
+#[cfg(feature = "bevy_ui")]
+type NotUiNode = Without<bevy::ui::Node>;
+#[cfg(not(feature = "bevy_ui"))]
+type NotUiNode = ();
+
+/// Gives every entity in [`PropagatedLayers`] the [`RenderLayers`] of its layer, and removes them
+/// from entities that stopped being managed. See
+/// [`SpriteLayerPlugin::with_layer_render_layers`].
+#[cfg(feature = "bevy_render")]
+pub fn sync_render_layers<Layer: LayerIndexWithContext>(
+    layers: Res<PropagatedLayers<Layer>>,
+    query: Query<&RenderLayers>,
+    context: Res<Layer::Context>,
+    mut assigned: Local<EntityHashSet>,
+    mut commands: Commands,
+) {
+    assigned.retain(|entity| {
+        if layers.contains(*entity) {
+            return true;
+        }
+        if let Some(mut commands) = commands.get_entity(*entity) {
+            commands.remove::<RenderLayers>();
+        }
+        false
+    });
+    let mut cache = HashMap::<&Layer, RenderLayers>::default();
+    for (entity, layer) in layers.iter() {
+        let render_layers = cache
+            .entry(layer)
+            .or_insert_with(|| layer.render_layers_in(&context));
+        if query.get(entity).ok() != Some(render_layers) {
+            commands.entity(entity).try_insert(render_layers.clone());
+        }
+        assigned.insert(entity);
+    }
+}
+
+/// Sets the given global transform's z, only triggering change detection if `mark_changed` is set
+/// and the z-coordinate is different. Transforms that already have that z aren't touched at all.
+pub(crate) fn set_transform_z(transform: &mut Mut<GlobalTransform>, z: f32, mark_changed: bool) {
+    if transform.translation().z == z {
+        return;
+    }
+    if mark_changed {
+        transform.set_changed();
+    }
+    // hacky hacky; I can't find a way to directly mutate the GlobalTransform.
+    let transform = transform.bypass_change_detection();
+    let mut affine = transform.affine();
+    affine.translation.z = z;
+    *transform = GlobalTransform::from(affine);
+}
+
+/// Maps `y` to an integer in an order-preserving way. If `fixed_point_scale` is set, this is
+/// `round(y * scale)`; otherwise it's the float's bits, rearranged so that they compare the same
+/// way the floats do. NaN is treated as positive infinity.
+pub(crate) fn quantize_y(y: f32, fixed_point_scale: Option<f32>) -> i64 {
+    let y = if y.is_nan() { f32::INFINITY } else { y };
+    match fixed_point_scale {
+        // `as` saturates on overflow and maps NaN to 0, so this never panics.
+        Some(scale) => (y * scale).round() as i64,
+        None => {
+            let bits = y.to_bits() as i32;
+            // Negative floats compare in the opposite order from their bits, so flip everything
+            // but the sign bit for those.
+            (bits ^ (((bits >> 31) as u32) >> 1) as i32) as i64
+        }
+    }
+}
+
+/// The order in which an entity first became managed by a [`SpriteLayerPlugin`]; used to break
+/// ties if [`SpriteLayerOptions::spawn_order_tiebreak`] is enabled, and to order
+/// [stacked](LayerIndex::stack_order) layers. This is inserted automatically, and stays on the
+/// entity even if it stops being managed. Entities that are placed on a stacked layer get a new
+/// one, as if they'd just been spawned. Sequence numbers are counted separately for each `Layer`
+/// type.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Component, Reflect)]
+pub struct LayerSeq(pub u64);
+
+/// Freezes the draw order of an entity and all of its managed descendants, for things like a group
+/// of sprites the player has picked up. Instead of being y-sorted against the rest of the world
+/// individually, they're y-sorted as a unit using the y-coordinate of the entity with this
+/// component, and keep whatever relative order they had when it was added. Entities still stay in
+/// their own layer's window.
+///
+/// The order is snapshotted from each member's [`RenderZCoordinate`] the first time the plugin
+/// sees this component, and kept until it's removed; to take a new snapshot (say, once the group
+/// has been put back down and re-sorted), remove it and then add it again later. Descendants
+/// that weren't managed when the snapshot was taken go in front of the rest, ordered by
+/// [`Entity`]. If containers are nested, the outermost one wins.
+///
+/// This takes precedence over [`SortGroup`] and [`SpriteLayerOptions::sort_inherited_by_owner`],
+/// and does nothing unless y-sorting is enabled.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Component, Reflect)]
+pub struct FreezeInternalOrder;
+
+/// Draws a managed entity in front of every layer, no matter what their z-coordinates are, e.g.
+/// for a full-screen transition or a debug HUD. The entity still needs a layer (possibly
+/// inherited) to be managed, but that layer's z-coordinate is ignored.
+///
+/// Overlays are placed in the [`LAYER_WINDOW`] just above the highest layer's window, ordered by
+/// the number in this component (higher numbers are in front) and then by [`Entity`]. Like
+/// layers, this only affects the entity it's on, not its descendants.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Component, Reflect)]
+pub struct TopmostOverlay(pub u32);
+
+/// Shifts every z-coordinate computed for this entity and its descendants by the given amount,
+/// on top of their layers' z-coordinates. Put this on the root of each instance of a scene to
+/// keep their layer stacks from interleaving: give them offsets at least as far apart as the span
+/// of your layers. Nested offsets add up, so an instance inside another is shifted by both.
+///
+/// Each instance is still y-sorted together with everything else on the same layer; only the
+/// result is shifted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, PartialOrd, Component, Reflect)]
+pub struct SceneDepthOffset(pub f32);
+
+/// Puts the entity in the given slot of its layer's sorted order, counting from the back, no
+/// matter where it would be y-sorted to; everything else on the layer is sorted around it. This
+/// only has an effect if [`SpriteLayerOptions::y_sort`] or
+/// [`SpriteLayerOptions::within_layer_tiebreak`] is enabled.
+///
+/// If several entities on a layer lock the same slot, they're ordered by [`Entity`] and the later
+/// ones take the next free slots. Slots past the last entity on the layer are treated as the last
+/// slot, so `LockedSortIndex(u32::MAX)` keeps an entity at the front of its layer.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Component, Reflect)]
+pub struct LockedSortIndex(pub u32);
+
+/// Scales down how far above its layer's z-coordinate this entity is placed, so it stays within
+/// the given fraction of the layer's window instead of spreading over all of it: with
+/// `ZSlice(0.0)`, it sits exactly at its layer's z-coordinate, and with `ZSlice(0.5)` it gets half
+/// the offset it would otherwise get. Values are clamped to `0.0..=1.0`; entities without this
+/// use the whole window. This is useful for decals that must stay right on top of their layer's
+/// base.
+///
+/// The entity still takes up its slot in the layer's sorted order, so the other entities on the
+/// layer are spread out the same as if it didn't have this. That means it can end up behind
+/// entities that are sorted behind it. This only has an effect if [`SpriteLayerOptions::y_sort`]
+/// or [`SpriteLayerOptions::within_layer_tiebreak`] is enabled, since otherwise every entity is
+/// at its layer's z-coordinate anyway, and doesn't affect [`TopmostOverlay`]s.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Component, Reflect)]
+pub struct ZSlice(pub f32);
+
+/// Marks the camera that [`SpriteLayerOptions::sort_by_camera_depth`] sorts by, and that
+/// [`SpriteLayerOptions::camera_y_band`] follows. If there are several, an arbitrary one is used.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Component, Reflect)]
+pub struct SortCamera;
+
+/// Overrides the y-coordinate an entity is y-sorted by, instead of using its transform.
+///
+/// Infinite values are allowed and well-defined: `SortPosition(f32::INFINITY)` is behind
+/// everything else on its layer, so with the default options its z-coordinate is exactly its
+/// layer's z-coordinate. `SortPosition(f32::NEG_INFINITY)` is in front of everything else. NaN
+/// (whether here or in a transform) is treated as positive infinity. Entities with the same sort
+/// position are ordered by their [`Entity`], so ties like this are deterministic.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Component, Reflect)]
+pub struct SortPosition(pub f32);
+
+/// Multiplies the coordinate an entity is sorted by, for fake perspective: give sprites that are
+/// meant to be further away a smaller scale to compress their depth toward 0, so they sort as if
+/// they were closer together. It scales whatever the entity would otherwise be sorted by (its
+/// y-coordinate after [`SpriteLayerOptions::y_range`] and
+/// [`camera_y_band`](SpriteLayerOptions::camera_y_band) are applied, its [`SortPosition`], or its
+/// depth with [`SpriteLayerOptions::sort_by_camera_depth`]), but not its position along
+/// [`SpriteLayerOptions::secondary_sort_axis`]. Entities without one have a scale of `1.0`.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Component, Reflect)]
+pub struct SortScale(pub f32);
+
+/// Entities with the same `SortGroup` are y-sorted as a unit, so that the parts of a compound
+/// object made up of several entities (say, a character and their equipment) never interleave
+/// with another object's parts.
+///
+/// Every entity in a group sorts using the y-coordinate of the group's frontmost (that is,
+/// lowest) member. Within the group, entities are then y-sorted by their own y-coordinates as
+/// usual. Groups are only meaningful among entities managed by the same plugin.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Component, Reflect)]
+pub struct SortGroup(pub u64);
+
+/// Stores the z-coordinate that will be used at render time. Don't modify this yourself.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Component, Reflect)]
+pub struct RenderZCoordinate(pub f32);
+
+/// The smallest and largest [`RenderZCoordinate`] the [`SpriteLayerPlugin`] for `Layer` assigned
+/// the last time it had any entities to manage. Handy for checking that everything fits between
+/// your camera's near and far planes. Both are `0.0` until the plugin first assigns anything, and
+/// they're left alone on frames where there's nothing to manage.
+#[derive(Debug, Resource)]
+pub struct SpriteLayerZBounds<Layer> {
+    pub min: f32,
+    pub max: f32,
+    phantom: PhantomData<Layer>,
+}
+
+impl<Layer> Default for SpriteLayerZBounds<Layer> {
+    fn default() -> Self {
+        Self {
+            min: 0.0,
+            max: 0.0,
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// Every distinct layer that at least one entity managed by the [`SpriteLayerPlugin`] for `Layer`
+/// had as of the last frame, for things like listing only the layers that are in use in an
+/// editor. Updated every frame, whether or not y-sorting is enabled; it's only marked as changed
+/// when the set of layers does.
+#[derive(Clone, Debug, PartialEq, Eq, Resource)]
+pub struct ActiveLayers<Layer: LayerIndexWithContext>(pub HashSet<Layer>);
+
+impl<Layer: LayerIndexWithContext> Default for ActiveLayers<Layer> {
+    fn default() -> Self {
+        Self(HashSet::default())
+    }
+}
+
+/// Returns whichever of the given entities is drawn in front of the others, given each entity's
+/// z-coordinate (usually its [`RenderZCoordinate`]). Entities with exactly the same z-coordinate
+/// are ordered by [`Entity`]. This is handy for picking: collect the entities under the cursor,
+/// then pick the topmost one.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use extol_sprite_layer::{topmost, RenderZCoordinate};
+/// fn pick(In(hits): In<Vec<Entity>>, query: Query<&RenderZCoordinate>) -> Option<Entity> {
+///     topmost(hits.into_iter().filter_map(|entity| Some((entity, query.get(entity).ok()?.0))))
+/// }
+/// ```
+pub fn topmost(entities: impl IntoIterator<Item = (Entity, f32)>) -> Option<Entity> {
+    entities
+        .into_iter()
+        .max_by(|(a, a_z), (b, b_z)| a_z.total_cmp(b_z).then(a.cmp(b)))
+        .map(|(entity, _)| entity)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::scene::serde::SceneDeserializer;
+    use ordered_float::OrderedFloat;
+    use serde::de::DeserializeSeed;
+    use tap::Tap;
+
+    use super::*;
+
+    #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Component)]
+    enum Layer {
+        Top,
+        Middle,
+        Bottom,
+    }
+
+    impl LayerIndex for Layer {
+        fn as_z_coordinate(&self) -> f32 {
+            use Layer::*;
+            match self {
+                Bottom => 0.0,
+                Middle => 1.0,
+                Top => 2.0,
+            }
+        }
+    }
+
+    fn test_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Layer>::default());
+
+        app
+    }
+
+    /// Just verify that adding the plugin doesn't somehow blow everything up.
+    #[test]
+    fn plugin_add_smoke_check() {
+        let _ = test_app();
+    }
+
+    #[test]
+    fn reflect_options() {
+        use bevy::reflect::ReflectFromReflect;
+
+        let mut app = test_app();
+        let options = SpriteLayerOptions {
+            secondary_sort_axis: Some(Vec3::X),
+            sort_epsilon: 0.25,
+            y_range: Some(-10.0..10.0),
+            layer_transition: Some(Duration::from_millis(200)),
+            missing_transform: MissingTransform::Skip,
+            ..default()
+        };
+        app.insert_resource(options.clone());
+
+        let registry = app.world().resource::<AppTypeRegistry>().read();
+        let registration = registry
+            .get(std::any::TypeId::of::<SpriteLayerOptions>())
+            .unwrap();
+        let reflected = registration
+            .data::<ReflectResource>()
+            .unwrap()
+            .reflect(app.world())
+            .unwrap()
+            .clone_value();
+        let from_reflect = registration.data::<ReflectFromReflect>().unwrap();
+        let cloned = from_reflect.from_reflect(&*reflected).unwrap();
+        assert_eq!(cloned.downcast_ref(), Some(&options));
+
+        let default = registration.data::<ReflectDefault>().unwrap().default();
+        assert_eq!(default.downcast_ref(), Some(&SpriteLayerOptions::default()));
+    }
+
+    fn transform_at(x: f32, y: f32) -> TransformBundle {
+        TransformBundle::from_transform(Transform::from_xyz(x, y, 0.0))
+    }
+
+    fn get_z(world: &World, entity: Entity) -> f32 {
+        world
+            .get::<GlobalTransform>(entity)
+            .unwrap()
+            .translation()
+            .z
+    }
+
+    #[test]
+    fn simple() {
+        let mut app = test_app();
+        let top = app
+            .world_mut()
+            .spawn((transform_at(1.0, 1.0), Layer::Top))
+            .id();
+        let middle = app
+            .world_mut()
+            .spawn((transform_at(1.0, 1.0), Layer::Middle))
+            .id();
+        let bottom = app
+            .world_mut()
+            .spawn((transform_at(1.0, 1.0), Layer::Bottom))
+            .id();
+        app.update();
+
+        assert!(get_z(app.world(), bottom) < get_z(app.world(), middle));
+        assert!(get_z(app.world(), middle) < get_z(app.world(), top));
+    }
+
+    fn layer_bundle(layer: Layer) -> impl Bundle {
+        (transform_at(0.0, 0.0), layer)
+    }
+
+    #[test]
+    fn active_layers() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            y_sort: false,
+            ..default()
+        });
+        let top = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        app.world_mut().spawn(layer_bundle(Layer::Bottom));
+        app.world_mut()
+            .spawn(transform_at(0.0, 0.0))
+            .set_parent(top);
+        app.update();
+        let active = |app: &App| app.world().resource::<ActiveLayers<Layer>>().0.clone();
+        assert_eq!(active(&app), HashSet::from([Layer::Top, Layer::Bottom]));
+
+        app.world_mut().entity_mut(top).insert(Layer::Middle);
+        app.update();
+        assert_eq!(active(&app), HashSet::from([Layer::Middle, Layer::Bottom]));
+
+        app.world_mut().clear_entities();
+        app.update();
+        assert!(active(&app).is_empty());
+    }
+
+    #[test]
+    fn provisional_z() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Layer>::default().with_provisional_z());
+        let entity = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Top))
+            .id();
+        // the observer's commands are queued like any others
+        app.world_mut().flush_commands();
+        let z = |app: &App, entity| app.world().get::<RenderZCoordinate>(entity).copied();
+        assert_eq!(
+            z(&app, entity),
+            Some(RenderZCoordinate(Layer::Top.as_z_coordinate()))
+        );
+
+        // also visible from the system that spawned it, once its commands are applied
+        app.insert_resource(ActiveSortSet::default());
+        let spawned = app.world_mut().run_system_once(|mut commands: Commands| {
+            commands.spawn((transform_at(0.0, 0.0), Layer::Middle)).id()
+        });
+        assert_eq!(
+            z(&app, spawned),
+            Some(RenderZCoordinate(Layer::Middle.as_z_coordinate()))
+        );
+        // neither is in the active set, so they don't keep it
+        app.update();
+        assert_eq!(z(&app, entity), None);
+        assert_eq!(z(&app, spawned), None);
+    }
+
+    #[test]
+    fn flat_plugin_matches() {
+        let mut zs = vec![];
+        for flat in [false, true] {
+            let mut app = App::new();
+            app.add_plugins(MinimalPlugins).add_plugins(TransformPlugin);
+            if flat {
+                app.add_plugins(FlatSpriteLayerPlugin::<Layer>::default());
+            } else {
+                app.add_plugins(SpriteLayerPlugin::<Layer>::default());
+            }
+            let entities = (0..30)
+                .map(|i| {
+                    let layer = [Layer::Bottom, Layer::Middle, Layer::Top][i % 3];
+                    app.world_mut()
+                        .spawn((transform_at(0.0, (i * 7 % 30) as f32), layer))
+                        .id()
+                })
+                .collect::<Vec<_>>();
+            app.update();
+            zs.push(
+                entities
+                    .iter()
+                    .map(|entity| get_z(app.world(), *entity))
+                    .collect::<Vec<_>>(),
+            );
+        }
+        assert_eq!(zs[0], zs[1]);
+    }
+
+    #[test]
+    fn flat_plugin_does_not_propagate() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(FlatSpriteLayerPlugin::<Layer>::default());
+        let parent = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        let child = app
+            .world_mut()
+            .spawn(transform_at(0.0, 0.0))
+            .set_parent(parent)
+            .id();
+        app.update();
+        assert!(app.world().get::<RenderZCoordinate>(parent).is_some());
+        assert!(app.world().get::<RenderZCoordinate>(child).is_none());
+    }
+
+    #[test]
+    fn inherited() {
+        let mut app = test_app();
+        let top = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        let child_with_layer = app
+            .world_mut()
+            .spawn(layer_bundle(Layer::Middle))
+            .set_parent(top)
+            .id();
+        let child_without_layer = app
+            .world_mut()
+            .spawn(transform_at(0.0, 0.0))
+            .set_parent(top)
+            .id();
+        app.update();
+
+        // we use .floor() here since y-sorting can add a fractional amount to the coordinates
+        assert_eq!(
+            get_z(app.world(), child_with_layer).floor(),
+            Layer::Middle.as_z_coordinate()
+        );
+        assert_eq!(
+            get_z(app.world(), child_without_layer).floor(),
+            get_z(app.world(), top).floor()
+        );
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn y_sorting() {
+        let mut app = test_app();
+        for _ in 0..10 {
+            app.world_mut()
+                .spawn((transform_at(0.0, fastrand::f32()), Layer::Top));
+        }
+        app.update();
+        let positions =
+            app.world_mut()
+                .run_system_once(|query: Query<&GlobalTransform>| -> Vec<Vec3> {
+                    query
+                        .into_iter()
+                        .map(|transform| transform.translation())
+                        .collect()
+                });
+        let sorted_by_z = positions
+            .clone()
+            .tap_mut(|positions| positions.sort_by_key(|vec| OrderedFloat(vec.z)));
+        let sorted_by_y = positions.tap_mut(|positions| {
+            positions.sort_by_key(|vec| std::cmp::Reverse(OrderedFloat(vec.y)))
+        });
+        assert_eq!(sorted_by_z, sorted_by_y);
+    }
+
+    #[test]
+    fn continuous_layers() {
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Component)]
+        enum Depth {
+            Ground,
+            /// Altitude in centimeters.
+            Flying(u32),
+        }
+
+        impl LayerIndex for Depth {
+            fn as_z_coordinate(&self) -> f32 {
+                match self {
+                    Depth::Ground => 0.0,
+                    Depth::Flying(altitude) => 10.0 + *altitude as f32 / 100.0,
+                }
+            }
+
+            fn sort_window(&self) -> f32 {
+                match self {
+                    Depth::Ground => LAYER_WINDOW,
+                    Depth::Flying(_) => 0.0,
+                }
+            }
+        }
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Depth>::default());
+        let ground = (0..10)
+            .map(|_| {
+                app.world_mut()
+                    .spawn((transform_at(0.0, fastrand::f32()), Depth::Ground))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        let flying = (0..10)
+            .map(|altitude| {
+                app.world_mut()
+                    .spawn((transform_at(0.0, fastrand::f32()), Depth::Flying(altitude)))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        app.update();
+
+        for entity in ground {
+            let z = get_z(app.world(), entity);
+            assert!((0.0..LAYER_WINDOW).contains(&z));
+        }
+        // flying entities are ordered purely by altitude, regardless of y
+        for (altitude, entity) in flying.into_iter().enumerate() {
+            assert_eq!(
+                get_z(app.world(), entity),
+                Depth::Flying(altitude as u32).as_z_coordinate()
+            );
+        }
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn spawn_order_tiebreak() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            spawn_order_tiebreak: true,
+            ..default()
+        });
+        // The older entity reuses an id, so it has a higher generation than the newer one and the
+        // `Entity` tiebreak would put it in front.
+        let placeholder = app.world_mut().spawn_empty().id();
+        app.world_mut().despawn(placeholder);
+        let old = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        app.update();
+        let new = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        assert!(new < old);
+        app.update();
+        assert!(get_z(app.world(), old) < get_z(app.world(), new));
+        assert!(app.world().get::<LayerSeq>(old) < app.world().get::<LayerSeq>(new));
+    }
+
+    #[test]
+    fn without_y_sort() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            y_sort: false,
+            ..default()
+        });
+        let layers = [Layer::Bottom, Layer::Middle, Layer::Top];
+        let entities = layers.map(|layer| {
+            [-1.0, 0.0, 2.0].map(|y| app.world_mut().spawn((transform_at(0.0, y), layer)).id())
+        });
+        app.update();
+        for (layer, entities) in layers.iter().zip(&entities) {
+            for entity in entities {
+                // exactly the layer's z-coordinate, no matter the y-coordinate
+                assert_eq!(get_z(app.world(), *entity), layer.as_z_coordinate());
+                let transform = app.world().get::<GlobalTransform>(*entity).unwrap();
+                assert_eq!(transform.translation().z, layer.as_z_coordinate());
+            }
+        }
+        assert!(get_z(app.world(), entities[0][0]) < get_z(app.world(), entities[1][0]));
+        assert!(get_z(app.world(), entities[1][0]) < get_z(app.world(), entities[2][0]));
+    }
+
+    #[test]
+    fn within_layer_tiebreak() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            y_sort: false,
+            within_layer_tiebreak: true,
+            spawn_order_tiebreak: true,
+            ..default()
+        });
+        let placeholder = app.world_mut().spawn_empty().id();
+        app.world_mut().despawn(placeholder);
+        let old = app
+            .world_mut()
+            .spawn((transform_at(1.0, 1.0), Layer::Middle))
+            .id();
+        app.update();
+        let new = app
+            .world_mut()
+            .spawn((transform_at(1.0, 1.0), Layer::Middle))
+            .id();
+        app.update();
+        let zs = [get_z(app.world(), old), get_z(app.world(), new)];
+        assert_eq!(zs, [1.0, 1.5]);
+        for _ in 0..3 {
+            app.update();
+            assert_eq!([get_z(app.world(), old), get_z(app.world(), new)], zs);
+        }
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn scene_depth_offset() {
+        let mut app = test_app();
+        let mut spawn_instance = |offset: f32| {
+            let world = app.world_mut();
+            let root = world
+                .spawn((TransformBundle::default(), SceneDepthOffset(offset)))
+                .id();
+            let entities = [
+                (0.0, Layer::Top),
+                (5.0, Layer::Bottom),
+                (0.0, Layer::Bottom),
+            ]
+            .map(|(y, layer)| {
+                world
+                    .spawn((transform_at(0.0, y), layer))
+                    .set_parent(root)
+                    .id()
+            });
+            (root, entities)
+        };
+        let (_, first) = spawn_instance(10.0);
+        let (second_root, second) = spawn_instance(20.0);
+        let nested = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), SceneDepthOffset(5.0), Layer::Bottom))
+            .set_parent(second_root)
+            .id();
+        app.update();
+
+        let zs = |entities: [Entity; 3]| entities.map(|entity| get_z(app.world(), entity));
+        let (first, second) = (zs(first), zs(second));
+        // both keep the same internal order, without overlapping
+        for zs in [first, second] {
+            assert!(zs[1] < zs[2] && zs[2] < zs[0]);
+        }
+        assert!(first.iter().all(|z| (10.0..20.0).contains(z)));
+        assert!(second.iter().all(|z| (20.0..30.0).contains(z)));
+        assert!((25.0..26.0).contains(&get_z(app.world(), nested)));
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn sort_by_previous_frame() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            sort_by_previous_frame: true,
+            ..default()
+        });
+        let mover = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Middle))
+            .id();
+        let other = app
+            .world_mut()
+            .spawn((transform_at(0.0, 1.0), Layer::Middle))
+            .id();
+        app.update();
+        assert!(get_z(app.world(), other) < get_z(app.world(), mover));
+
+        app.world_mut()
+            .get_mut::<Transform>(mover)
+            .unwrap()
+            .translation
+            .y = 2.0;
+        app.update();
+        // still sorted by where it was at the start of the frame
+        assert!(get_z(app.world(), other) < get_z(app.world(), mover));
+        app.update();
+        assert!(get_z(app.world(), mover) < get_z(app.world(), other));
+    }
+
+    #[test]
+    fn z_coordinates_persist_outside_of_last() {
+        let mut app = test_app();
+        let parent = app
+            .world_mut()
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, 5.0)),
+                Layer::Top,
+            ))
+            .id();
+        let child = app
+            .world_mut()
+            .spawn(TransformBundle::from_transform(Transform::from_xyz(
+                0.0, 1.0, 3.0,
+            )))
+            .set_parent(parent)
+            .id();
+        app.add_systems(
+            Update,
+            move |query: Query<(&GlobalTransform, Option<&RenderZCoordinate>)>| {
+                // there's no window where the z-coordinate has been cleared
+                for (global, render_z) in query.many([parent, child]) {
+                    if let Some(render_z) = render_z {
+                        assert_eq!(global.translation().z, render_z.0);
+                    }
+                }
+            },
+        );
+        for _ in 0..3 {
+            app.update();
+            // the plugin only ever touches `GlobalTransform`
+            assert_eq!(
+                app.world().get::<Transform>(parent).unwrap().translation.z,
+                5.0
+            );
+            for entity in [parent, child] {
+                assert_eq!(
+                    get_z(app.world(), entity),
+                    app.world().get::<RenderZCoordinate>(entity).unwrap().0
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn movement_in_last() {
+        use bevy::transform::systems::sync_simple_transforms;
+
+        let mut app = test_app();
+        let mover = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Middle))
+            .id();
+        let other = app
+            .world_mut()
+            .spawn((transform_at(0.0, 1.0), Layer::Middle))
+            .id();
+        app.add_systems(
+            Last,
+            (
+                (|mut query: Query<&mut Transform, With<Layer>>| {
+                    for mut transform in &mut query {
+                        transform.translation.y = -transform.translation.y;
+                    }
+                })
+                .before(SpriteLayerSet::SetZCoordinates)
+                .before(TransformSystem::TransformPropagate),
+                sync_simple_transforms.in_set(TransformSystem::TransformPropagate),
+            ),
+        );
+        app.update();
+        assert!(get_z(app.world(), mover) < get_z(app.world(), other));
+    }
+
+    #[test]
+    fn layers_defined_at_runtime() {
+        #[derive(Debug, Default, Resource)]
+        struct LayerRegistry(HashMap<String, f32>);
+
+        #[derive(Clone, PartialEq, Eq, Hash, Debug, Component)]
+        struct NamedLayer(String);
+
+        impl LayerIndexWithContext for NamedLayer {
+            type Context = LayerRegistry;
+
+            fn z_coordinate_in(&self, registry: &LayerRegistry) -> f32 {
+                registry.0[&self.0]
+            }
+        }
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<NamedLayer>::default());
+        // e.g. loaded from a config file
+        let mut registry = app.world_mut().resource_mut::<LayerRegistry>();
+        registry.0.insert("ground".into(), 0.0);
+        registry.0.insert("sky".into(), 10.0);
+        let ground = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), NamedLayer("ground".into())))
+            .id();
+        let sky = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), NamedLayer("sky".into())))
+            .id();
+        app.update();
+        assert!(get_z(app.world(), ground) < get_z(app.world(), sky));
+
+        app.world_mut()
+            .resource_mut::<LayerRegistry>()
+            .0
+            .insert("ground".into(), 20.0);
+        app.update();
+        assert!(get_z(app.world(), sky) < get_z(app.world(), ground));
+        assert!(get_z(app.world(), ground) >= 20.0);
+    }
+
+    #[test]
+    fn no_layered_entities() {
+        let mut app = test_app();
+        let entity = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        app.world_mut().spawn(transform_at(0.0, 0.0));
+        app.update();
+        app.world_mut().entity_mut(entity).remove::<Layer>();
+        for _ in 0..2 {
+            app.update();
+            assert!(app.world().resource::<PropagatedLayers<Layer>>().is_empty());
+            let mut query = app.world_mut().query::<&RenderZCoordinate>();
+            assert_eq!(query.iter(app.world()).count(), 0);
+            let mut query = app.world_mut().query::<&GlobalTransform>();
+            assert!(query
+                .iter(app.world())
+                .all(|transform| transform.translation().is_finite()));
+        }
+    }
+
+    #[test]
+    fn on_sorted_callback() {
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Layer>::default().on_sorted({
+                let received = received.clone();
+                move |sorted| *received.lock().unwrap() = sorted.to_vec()
+            }));
+        let top = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        let bottom = app.world_mut().spawn(layer_bundle(Layer::Bottom)).id();
+        app.update();
+        let received = received.lock().unwrap();
+        assert_eq!(
+            received
+                .iter()
+                .map(|(entity, _)| *entity)
+                .collect::<Vec<_>>(),
+            [bottom, top]
+        );
+        for (entity, z) in received.iter() {
+            assert_eq!(get_z(app.world(), *entity), *z);
+        }
+    }
+
+    #[test]
+    fn added_twice() {
+        let runs = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counting_plugin = || {
+            let runs = runs.clone();
+            SpriteLayerPlugin::<Layer>::default().on_sorted(move |_| {
+                runs.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            })
+        };
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(counting_plugin())
+            .add_plugins(counting_plugin());
+        app.finish();
+        app.world_mut().spawn(layer_bundle(Layer::Top));
+        app.update();
+        assert_eq!(runs.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn z_bounds() {
+        let mut app = test_app();
+        app.world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Bottom));
+        app.world_mut().spawn((transform_at(0.0, 5.0), Layer::Top));
+        app.world_mut().spawn((transform_at(0.0, 0.0), Layer::Top));
+        app.update();
+        let bounds = app.world().resource::<SpriteLayerZBounds<Layer>>();
+        assert_eq!((bounds.min, bounds.max), (0.0, 2.5));
+
+        // left alone when there's nothing to manage
+        app.world_mut().clear_entities();
+        app.update();
+        let bounds = app.world().resource::<SpriteLayerZBounds<Layer>>();
+        assert_eq!((bounds.min, bounds.max), (0.0, 2.5));
+    }
+
+    #[test]
+    fn z_base() {
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Component)]
+        struct Overlay;
+
+        impl LayerIndex for Overlay {
+            fn as_z_coordinate(&self) -> f32 {
+                1.0
+            }
+        }
+
+        let mut app = test_app();
+        app.add_plugins(SpriteLayerPlugin::<Overlay>::default().with_z_base(100.0));
+        let world = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        let overlay = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Overlay))
+            .id();
+        app.update();
+        assert_eq!(get_z(app.world(), world), 2.0);
+        assert_eq!(get_z(app.world(), overlay), 101.0);
+
+        // each plugin only restores its own entities
+        app.world_mut().entity_mut(overlay).remove::<Overlay>();
+        app.update();
+        assert_eq!(get_z(app.world(), world), 2.0);
+        assert_eq!(get_z(app.world(), overlay), 0.0);
+    }
+
+    #[test]
+    fn compute_schedule() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Layer>::default().with_compute_schedule(FixedUpdate));
+        let entities = [3.0, 1.0, 2.0].map(|y| {
+            app.world_mut()
+                .spawn((TransformBundle::default(), SortPosition(y), Layer::Middle))
+                .id()
+        });
+        let step = |app: &mut App| {
+            app.world_mut().run_schedule(FixedUpdate);
+            app.world_mut().run_schedule(Last);
+        };
+        let state = |app: &App| {
+            entities.map(|entity| {
+                let world = app.world();
+                (
+                    world.get::<SortPosition>(entity).unwrap().0,
+                    world.get::<RenderZCoordinate>(entity).unwrap().0,
+                )
+            })
+        };
+        step(&mut app);
+        let saved = state(&app);
+        // applied in `Last`
+        for (entity, (_, z)) in entities.iter().zip(saved) {
+            assert_eq!(get_z(app.world(), *entity), z);
+        }
+
+        // diverge, then roll back and resimulate
+        app.world_mut()
+            .get_mut::<SortPosition>(entities[0])
+            .unwrap()
+            .0 = 0.0;
+        step(&mut app);
+        assert_ne!(state(&app), saved);
+        for (entity, (y, z)) in entities.iter().zip(saved) {
+            app.world_mut()
+                .entity_mut(*entity)
+                .insert((SortPosition(y), RenderZCoordinate(z)));
+        }
+        step(&mut app);
+        assert_eq!(state(&app), saved);
+        for (entity, (_, z)) in entities.iter().zip(saved) {
+            assert_eq!(get_z(app.world(), *entity), z);
+        }
+    }
+
+    #[test]
+    fn compute_schedule_with_tiebreaks() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Layer>::default().with_compute_schedule(FixedUpdate))
+            .insert_resource(SpriteLayerOptions {
+                spawn_order_tiebreak: true,
+                stable_ties: true,
+                ..default()
+            });
+        // all tied, so only the tiebreaks order them
+        let entities = [0; 3].map(|_| {
+            app.world_mut()
+                .spawn((TransformBundle::default(), SortPosition(1.0), Layer::Middle))
+                .id()
+        });
+        let step = |app: &mut App| {
+            app.world_mut().run_schedule(FixedUpdate);
+            app.world_mut().run_schedule(Last);
+        };
+        let state = |app: &App| {
+            entities.map(|entity| {
+                let world = app.world();
+                (
+                    world.get::<SortPosition>(entity).unwrap().0,
+                    world.get::<LayerSeq>(entity).unwrap().0,
+                    world.get::<RenderZCoordinate>(entity).unwrap().0,
+                )
+            })
+        };
+        step(&mut app);
+        step(&mut app);
+        let saved = state(&app);
+
+        // diverge, including an entity that only exists in the discarded timeline
+        app.world_mut()
+            .get_mut::<SortPosition>(entities[1])
+            .unwrap()
+            .0 = 0.0;
+        let discarded = app
+            .world_mut()
+            .spawn((TransformBundle::default(), SortPosition(1.0), Layer::Middle))
+            .id();
+        step(&mut app);
+        assert_ne!(state(&app), saved);
+        app.world_mut().despawn(discarded);
+        for (entity, (y, seq, z)) in entities.iter().zip(saved) {
+            app.world_mut().entity_mut(*entity).insert((
+                SortPosition(y),
+                LayerSeq(seq),
+                RenderZCoordinate(z),
+            ));
+        }
+        step(&mut app);
+        assert_eq!(state(&app), saved);
+        for (entity, (_, _, z)) in entities.iter().zip(saved) {
+            assert_eq!(get_z(app.world(), *entity), z);
+        }
+    }
+
+    #[test]
+    fn plugin_group() {
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Component)]
+        struct Shadow;
+
+        impl LayerIndex for Shadow {
+            fn as_z_coordinate(&self) -> f32 {
+                0.0
+            }
+        }
+
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Component)]
+        struct Hud;
+
+        impl LayerIndex for Hud {
+            fn as_z_coordinate(&self) -> f32 {
+                1.0
+            }
+        }
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(
+                SpriteLayerPluginGroup::default()
+                    .with(SpriteLayerPlugin::<Layer>::default(), 3.0)
+                    .with(
+                        SpriteLayerPlugin::<Shadow>::default().with_z_base(50.0),
+                        1.0,
+                    )
+                    .with(SpriteLayerPlugin::<Hud>::default(), 2.0),
+            );
+        let top = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        let shadows =
+            [0.0, 1.0].map(|y| app.world_mut().spawn((transform_at(0.0, y), Shadow)).id());
+        let hud = app.world_mut().spawn((transform_at(0.0, 0.0), Hud)).id();
+        app.update();
+        let z = |entity| get_z(app.world(), entity);
+        assert_eq!(z(top), 2.0);
+        for shadow in shadows {
+            assert!((3.0..4.0).contains(&z(shadow)));
+        }
+        if cfg!(feature = "y_sort") {
+            assert_ne!(z(shadows[0]), z(shadows[1]));
+        }
+        assert_eq!(z(hud), 5.0);
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Component)]
+    struct Hud;
+
+    impl LayerIndex for Hud {
+        fn as_z_coordinate(&self) -> f32 {
+            0.0
+        }
+    }
+
+    #[test]
+    fn plugin_group_shared_hierarchy() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(
+                SpriteLayerPluginGroup::default()
+                    .with(SpriteLayerPlugin::<Layer>::default(), 3.0)
+                    .with(SpriteLayerPlugin::<Hud>::default(), 1.0),
+            );
+        let parent = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        // Inherits `Layer::Top` too, but the `Hud` plugin is higher up, so it wins.
+        let child = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Hud))
+            .set_parent(parent)
+            .id();
+        app.update();
+        let render_z = |app: &App, entity| app.world().get::<RenderZCoordinate>(entity).unwrap().0;
+        let last_changed = |app: &App| {
+            app.world()
+                .entity(child)
+                .get_ref::<RenderZCoordinate>()
+                .unwrap()
+                .last_changed()
+        };
+        let settled = last_changed(&app);
+        for _ in 0..3 {
+            app.update();
+            assert_eq!(render_z(&app, parent), 2.0);
+            assert_eq!(render_z(&app, child), 3.0);
+            assert_eq!(get_z(app.world(), child), 3.0);
+            assert_eq!(last_changed(&app), settled);
+        }
+        assert!(!app
+            .world()
+            .resource::<PropagatedLayers<Layer>>()
+            .contains(child));
+        assert_eq!(app.world().resource::<GroupClaims>().get(child), Some(1));
+
+        // Handed back to the plugin below, without ever losing its `RenderZCoordinate`.
+        app.world_mut().entity_mut(child).remove::<Hud>();
+        app.update();
+        assert!((2.0..3.0).contains(&render_z(&app, child)));
+        assert!(app
+            .world()
+            .resource::<PropagatedLayers<Layer>>()
+            .contains(child));
+        assert_eq!(app.world().resource::<GroupClaims>().get(child), Some(0));
+
+        app.world_mut().entity_mut(child).insert(Hud);
+        app.update();
+        assert_eq!(render_z(&app, child), 3.0);
+        assert_eq!(get_z(app.world(), child), 3.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "already has a plugin for")]
+    fn plugin_group_rejects_duplicates() {
+        let _ = SpriteLayerPluginGroup::default()
+            .with(SpriteLayerPlugin::<Layer>::default(), 3.0)
+            .with(SpriteLayerPlugin::<Layer>::default(), 3.0);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn custom_comparator() {
+        let mut app = test_app();
+        app.insert_resource(SortComparator::new(|a, b| {
+            a.translation().x.total_cmp(&b.translation().x)
+        }));
+        let right = app
+            .world_mut()
+            .spawn((transform_at(1.0, 0.0), Layer::Middle))
+            .id();
+        let left = app
+            .world_mut()
+            .spawn((transform_at(-1.0, 5.0), Layer::Middle))
+            .id();
+        let bottom = app
+            .world_mut()
+            .spawn((transform_at(-5.0, 0.0), Layer::Bottom))
+            .id();
+        app.update();
+        assert_eq!(sorted_by_z(&mut app), [bottom, left, right]);
+    }
+
+    #[test]
+    fn layer_transition() {
+        use bevy::time::TimeUpdateStrategy;
+
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            layer_transition: Some(Duration::from_secs(1)),
+            ..default()
+        })
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+            250,
+        )));
+        let entity = app.world_mut().spawn(layer_bundle(Layer::Bottom)).id();
+        app.update();
+        let start = get_z(app.world(), entity);
+        assert_eq!(start, Layer::Bottom.as_z_coordinate());
+
+        app.world_mut().entity_mut(entity).insert(Layer::Top);
+        let target = Layer::Top.as_z_coordinate();
+        let mut previous = start;
+        for _ in 0..4 {
+            app.update();
+            let z = get_z(app.world(), entity);
+            assert!(previous <= z && z < target, "{previous} {z}");
+            previous = z;
+        }
+        assert!(previous > start);
+        for _ in 0..2 {
+            app.update();
+        }
+        assert_eq!(get_z(app.world(), entity), target);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn custom_tiebreak() {
+        let mut app = test_app();
+        let player = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Middle))
+            .id();
+        let npc = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Middle))
+            .id();
+        let above = app
+            .world_mut()
+            .spawn((transform_at(0.0, 1.0), Layer::Middle))
+            .id();
+        app.update();
+        // by entity id by default
+        assert_eq!(sorted_by_z(&mut app), [above, player, npc]);
+
+        app.insert_resource(SortTiebreak::new(move |a, b| {
+            (a == player).cmp(&(b == player))
+        }));
+        app.update();
+        assert_eq!(sorted_by_z(&mut app), [above, npc, player]);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn sort_resolution() {
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Component)]
+        struct Coarse;
+
+        impl LayerIndex for Coarse {
+            fn as_z_coordinate(&self) -> f32 {
+                0.0
+            }
+
+            fn sort_resolution(&self) -> Option<u32> {
+                Some(4)
+            }
+        }
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Coarse>::default());
+        let entities = (0..20)
+            .map(|i| {
+                app.world_mut()
+                    .spawn((transform_at(0.0, -(i as f32)), Coarse))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        app.update();
+        let zs = entities
+            .into_iter()
+            .map(|entity| get_z(app.world(), entity))
+            .collect::<Vec<_>>();
+        // still sorted, but only using 4 distinct values
+        assert!(zs.windows(2).all(|pair| pair[0] <= pair[1]));
+        for z in &zs {
+            assert_eq!((z * 4.0).fract(), 0.0);
+        }
+        assert_eq!(zs.clone().tap_mut(|zs| zs.dedup()).len(), 4);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn layer_without_y_sort() {
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Component)]
+        enum Mixed {
+            Tiles,
+            Actors,
+        }
+
+        impl LayerIndex for Mixed {
+            fn as_z_coordinate(&self) -> f32 {
+                match self {
+                    Mixed::Tiles => 0.0,
+                    Mixed::Actors => 1.0,
+                }
+            }
+
+            fn y_sort(&self) -> bool {
+                *self == Mixed::Actors
+            }
+        }
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Mixed>::default());
+        let tiles = (0..1000)
+            .map(|i| {
+                app.world_mut()
+                    .spawn((transform_at(0.0, i as f32 - 500.0), Mixed::Tiles))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        let actors = [1.0, 0.0].map(|y| {
+            app.world_mut()
+                .spawn((transform_at(0.0, y), Mixed::Actors))
+                .id()
+        });
+        app.update();
+        for tile in tiles {
+            assert_eq!(get_z(app.world(), tile), 0.0);
+        }
+        // the actors get their whole window, as if the tiles weren't there
+        assert_eq!(
+            actors.map(|actor| get_z(app.world(), actor)),
+            [1.0, 1.0 + LAYER_WINDOW / 2.0]
+        );
+    }
+
+    #[test]
+    fn stack_order() {
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Component)]
+        enum Table {
+            Pile,
+            Hand,
+        }
+
+        impl LayerIndex for Table {
+            fn as_z_coordinate(&self) -> f32 {
+                match self {
+                    Table::Pile => 0.0,
+                    Table::Hand => 1.0,
+                }
+            }
+
+            fn stack_order(&self) -> bool {
+                *self == Table::Pile
+            }
+        }
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Table>::default());
+        // each card is placed higher up than the last, so y-sorting would reverse them
+        let cards = (0..3)
+            .map(|i| {
+                let card = app
+                    .world_mut()
+                    .spawn((transform_at(0.0, i as f32), Table::Pile))
+                    .id();
+                app.update();
+                card
+            })
+            .collect::<Vec<_>>();
+        let zs = |app: &App| {
+            cards
+                .iter()
+                .map(|card| get_z(app.world(), *card))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(zs(&app), [0.0, 1.0 / 3.0, 2.0 / 3.0]);
+
+        // picking up the bottom card and putting it back puts it on top
+        app.world_mut().entity_mut(cards[0]).insert(Table::Hand);
+        app.update();
+        assert_eq!(zs(&app), [1.0, 0.0, 0.5]);
+        app.world_mut().entity_mut(cards[0]).insert(Table::Pile);
+        app.update();
+        assert_eq!(zs(&app), [2.0 / 3.0, 0.0, 1.0 / 3.0]);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn max_slots_per_layer() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            max_slots_per_layer: Some(100),
+            ..default()
+        });
+        let mut entities = (0..1000)
+            .map(|i| {
+                app.world_mut()
+                    .spawn((transform_at(0.0, -(i as f32)), Layer::Middle))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        app.update();
+        let zs = |app: &App, entities: &[Entity]| {
+            entities
+                .iter()
+                .map(|entity| get_z(app.world(), *entity))
+                .collect::<Vec<_>>()
+        };
+        let capped = zs(&app, &entities);
+        // still in order, but only in 100 slots, each at least a slot apart
+        assert!(capped.windows(2).all(|pair| pair[0] <= pair[1]));
+        let mut distinct = capped.clone();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 100);
+        for pair in distinct.windows(2) {
+            assert!(
+                pair[1] - pair[0] >= LAYER_WINDOW / 100.0 * 0.999,
+                "{pair:?}"
+            );
+        }
+
+        // below the cap, nothing changes
+        for entity in entities.drain(10..) {
+            app.world_mut().despawn(entity);
+        }
+        app.update();
+        let expected = (0..10).map(|i| 1.0 + i as f32 / 10.0).collect::<Vec<_>>();
+        assert_eq!(zs(&app, &entities), expected);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn offset_curve() {
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Component)]
+        struct Compressed;
+
+        impl LayerIndex for Compressed {
+            fn as_z_coordinate(&self) -> f32 {
+                10.0
+            }
+
+            fn sort_window(&self) -> f32 {
+                2.0
+            }
+
+            fn offset_curve(&self, normalized_rank: f32) -> f32 {
+                normalized_rank.sqrt()
+            }
+        }
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Compressed>::default());
+        let entities = (0..16)
+            .map(|i| {
+                app.world_mut()
+                    .spawn((transform_at(0.0, -(i as f32)), Compressed))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        app.update();
+        let zs = entities
+            .into_iter()
+            .map(|entity| get_z(app.world(), entity))
+            .collect::<Vec<_>>();
+        assert!(zs.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(zs.iter().all(|z| (10.0..12.0).contains(z)));
+        // the curve was applied: rank 4/16 maps to half of the window
+        assert_eq!(zs[4], 11.0);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn assign_z_values_matches_plugin() {
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Component)]
+        enum Mixed {
+            Plain,
+            Coarse,
+        }
+
+        impl LayerIndex for Mixed {
+            fn as_z_coordinate(&self) -> f32 {
+                match self {
+                    Mixed::Plain => 0.0,
+                    Mixed::Coarse => 10.0,
+                }
+            }
+
+            fn sort_window(&self) -> f32 {
+                match self {
+                    Mixed::Plain => LAYER_WINDOW,
+                    Mixed::Coarse => 2.0,
+                }
+            }
+
+            fn sort_resolution(&self) -> Option<u32> {
+                (*self == Mixed::Coarse).then_some(8)
+            }
+
+            fn offset_curve(&self, normalized_rank: f32) -> f32 {
+                match self {
+                    Mixed::Plain => normalized_rank,
+                    Mixed::Coarse => normalized_rank.sqrt(),
+                }
+            }
+        }
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Mixed>::default())
+            .insert_resource(SpriteLayerOptions {
+                sort_epsilon: 0.5,
+                max_slots_per_layer: Some(6),
+                ..default()
+            });
+        let items = (0..20)
+            .map(|i| {
+                let layer = if i % 3 == 0 { Mixed::Coarse } else { Mixed::Plain };
+                (layer, (i * 7 % 11) as f32 * 0.3)
+            })
+            .collect::<Vec<_>>();
+        let entities = items
+            .iter()
+            .map(|(layer, y)| app.world_mut().spawn((transform_at(0.0, *y), *layer)).id())
+            .collect::<Vec<_>>();
+        app.update();
+
+        let options = CoreOptions {
+            layer: |z| {
+                let layer = if z == 0.0 { Mixed::Plain } else { Mixed::Coarse };
+                CoreLayer {
+                    window: layer.sort_window(),
+                    resolution: layer.sort_resolution(),
+                    curve: if z == 0.0 { |rank| rank } else { f32::sqrt },
+                }
+            },
+            sort_epsilon: 0.5,
+            max_slots_per_layer: Some(6),
+            ..default()
+        };
+        let core_items = items
+            .iter()
+            .map(|(layer, y)| (layer.as_z_coordinate(), *y))
+            .collect::<Vec<_>>();
+        let expected = assign_z_values(&core_items, &options);
+        for (entity, expected) in entities.into_iter().zip(expected) {
+            assert_eq!(get_z(app.world(), entity), expected);
+        }
+    }
+
+    #[test]
+    fn unsorted_skips_unchanged_entities() {
+        #[derive(Default, Resource)]
+        struct Writes(usize);
+
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            y_sort: false,
+            ..default()
+        })
+        .init_resource::<Writes>()
+        .add_systems(
+            Last,
+            (|query: Query<(), Changed<RenderZCoordinate>>, mut writes: ResMut<Writes>| {
+                writes.0 = query.iter().count();
+            })
+            .after(SpriteLayerSet::SetZCoordinates),
+        );
+        let entity = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        let child = app
+            .world_mut()
+            .spawn(transform_at(0.0, 0.0))
+            .set_parent(entity)
+            .id();
+        let other = app.world_mut().spawn(layer_bundle(Layer::Bottom)).id();
+        app.update();
+        // the components are inserted at the end of the first frame
+        app.update();
+        app.update();
+        assert_eq!(app.world().resource::<Writes>().0, 0);
+
+        app.world_mut().entity_mut(entity).insert(Layer::Middle);
+        app.update();
+        assert_eq!(app.world().resource::<Writes>().0, 2);
+        assert_eq!(get_z(app.world(), entity), Layer::Middle.as_z_coordinate());
+        assert_eq!(get_z(app.world(), child), Layer::Middle.as_z_coordinate());
+        assert_eq!(get_z(app.world(), other), Layer::Bottom.as_z_coordinate());
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn y_sorted_skips_unchanged_entities() {
+        #[derive(Default, Resource)]
+        struct Changes(EntityHashSet);
+
+        let mut app = test_app();
+        app.init_resource::<Changes>().add_systems(
+            Last,
+            (|query: Query<Entity, Changed<RenderZCoordinate>>, mut changes: ResMut<Changes>| {
+                changes.0 = query.iter().collect();
+            })
+            .after(SpriteLayerSet::SetZCoordinates),
+        );
+        let back = app
+            .world_mut()
+            .spawn((transform_at(0.0, 2.0), Layer::Top))
+            .id();
+        let front = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Top))
+            .id();
+        app.world_mut()
+            .spawn((transform_at(0.0, 1.0), Layer::Bottom));
+        app.update();
+        app.update();
+        app.update();
+        assert!(app.world().resource::<Changes>().0.is_empty());
+
+        // moving within its slot doesn't change anyone's z-coordinate
+        app.world_mut()
+            .get_mut::<Transform>(front)
+            .unwrap()
+            .translation
+            .y = 1.0;
+        app.update();
+        assert!(app.world().resource::<Changes>().0.is_empty());
+
+        // swapping places changes both, but not the entity on the other layer
+        app.world_mut()
+            .get_mut::<Transform>(front)
+            .unwrap()
+            .translation
+            .y = 3.0;
+        app.update();
+        assert_eq!(
+            app.world().resource::<Changes>().0,
+            EntityHashSet::from_iter([back, front])
+        );
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn topmost_entity() {
+        let mut app = test_app();
+        let top = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        let in_front = app
+            .world_mut()
+            .spawn((transform_at(0.0, -1.0), Layer::Middle))
+            .id();
+        let behind = app
+            .world_mut()
+            .spawn((transform_at(0.0, 1.0), Layer::Middle))
+            .id();
+        app.update();
+        let z_of = |entity| (entity, get_z(app.world(), entity));
+        assert_eq!(topmost([z_of(behind), z_of(in_front)]), Some(in_front));
+        assert_eq!(
+            topmost([z_of(behind), z_of(top), z_of(in_front)]),
+            Some(top)
+        );
+        assert_eq!(topmost([]), None);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn offsets_are_contiguous_per_layer() {
+        let mut app = test_app();
+        let mut top = vec![];
+        let mut bottom = vec![];
+        // interleave the layers in the global order
+        for i in 0..8 {
+            let y = -(i as f32);
+            top.push(
+                app.world_mut()
+                    .spawn((transform_at(0.0, y), Layer::Top))
+                    .id(),
+            );
+            if i % 2 == 0 {
+                bottom.push(
+                    app.world_mut()
+                        .spawn((transform_at(0.0, y), Layer::Bottom))
+                        .id(),
+                );
+            }
+        }
+        app.update();
+        for (layer, entities) in [(Layer::Top, top), (Layer::Bottom, bottom)] {
+            let count = entities.len() as f32;
+            for (i, entity) in entities.into_iter().enumerate() {
+                assert_eq!(
+                    get_z(app.world(), entity),
+                    layer.as_z_coordinate() + i as f32 / count * LAYER_WINDOW
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn uneven_layers_each_fill_their_window() {
+        let mut app = test_app();
+        let crowded = (0..500)
+            .map(|i| {
+                app.world_mut()
+                    .spawn((transform_at(0.0, -(i as f32)), Layer::Bottom))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        let sparse = [250.5, -1000.0].map(|y| {
+            app.world_mut()
+                .spawn((transform_at(0.0, y), Layer::Middle))
+                .id()
+        });
+        app.update();
+        let z = |entity| get_z(app.world(), entity);
+        // the sparse layer isn't squeezed into a sliver by the crowded one
+        assert_eq!(z(sparse[0]), 1.0);
+        assert_eq!(z(sparse[1]), 1.5);
+        assert_eq!(z(crowded[0]), 0.0);
+        assert_eq!(z(crowded[499]), 499.0 / 500.0);
+        let mut zs = crowded.iter().map(|entity| z(*entity)).collect::<Vec<_>>();
+        zs.dedup();
+        assert_eq!(zs.len(), 500);
+    }
+
+    #[test]
+    fn z_slice() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            y_sort: false,
+            within_layer_tiebreak: true,
+            ..default()
+        });
+        let slices = [None, Some(0.0), Some(0.5), None, Some(2.0)];
+        let entities = slices
+            .iter()
+            .map(|slice| {
+                let mut entity = app.world_mut().spawn(layer_bundle(Layer::Middle));
+                if let Some(slice) = slice {
+                    entity.insert(ZSlice(*slice));
+                }
+                entity.id()
+            })
+            .collect::<Vec<_>>();
+        app.update();
+        let zs = entities
+            .iter()
+            .map(|entity| get_z(app.world(), *entity))
+            .collect::<Vec<_>>();
+        // Still spread over five slots, but pulled towards the base by their slices.
+        assert_eq!(zs, [1.0, 1.0, 1.2, 1.6, 1.8]);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn locked_sort_index() {
+        let mut app = test_app();
+        let free = (0..4)
+            .map(|i| {
+                app.world_mut()
+                    .spawn((transform_at(0.0, -(i as f32)), Layer::Middle))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        // would be at the very back or front if it weren't locked
+        let locked = app
+            .world_mut()
+            .spawn((transform_at(0.0, 10.0), Layer::Middle, LockedSortIndex(2)))
+            .id();
+        let front = app
+            .world_mut()
+            .spawn((transform_at(0.0, 20.0), Layer::Middle, LockedSortIndex(100)))
+            .id();
+        app.update();
+        let z = |entity| get_z(app.world(), entity);
+        let order = [free[0], free[1], locked, free[2], free[3], front];
+        for (slot, entity) in order.into_iter().enumerate() {
+            assert_eq!(z(entity), 1.0 + slot as f32 / 6.0);
+        }
+
+        // the rest keep y-sorting around it
+        app.world_mut()
+            .get_mut::<Transform>(free[0])
+            .unwrap()
+            .translation
+            .y = -10.0;
+        app.update();
+        let z = |entity| get_z(app.world(), entity);
+        assert_eq!(z(locked), 1.0 + 2.0 / 6.0);
+        assert_eq!(z(free[0]), 1.0 + 4.0 / 6.0);
+        assert_eq!(z(front), 1.0 + 5.0 / 6.0);
+    }
+
+    #[test]
+    fn topmost_overlays() {
+        let mut app = test_app();
+        let top = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        let hud = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Bottom, TopmostOverlay(0)))
+            .id();
+        let transition = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Bottom, TopmostOverlay(1)))
+            .id();
+        app.update();
+        assert!(get_z(app.world(), top) < get_z(app.world(), hud));
+        assert!(get_z(app.world(), hud) < get_z(app.world(), transition));
+        assert_eq!(
+            get_z(app.world(), hud),
+            Layer::Top.as_z_coordinate() + LAYER_WINDOW
+        );
+
+        app.world_mut().insert_resource(SpriteLayerOptions {
+            y_sort: false,
+            ..default()
+        });
+        app.update();
+        app.world_mut().entity_mut(hud).remove::<TopmostOverlay>();
+        app.update();
+        assert_eq!(get_z(app.world(), hud), Layer::Bottom.as_z_coordinate());
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn stable_ties() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            stable_ties: true,
+            ..default()
+        });
+        let a = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        let b = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        // start with `b` behind `a`, the opposite of the `Entity` tiebreak
+        app.world_mut().entity_mut(a).insert(SortPosition(-1.0));
+        app.update();
+        assert!(get_z(app.world(), b) < get_z(app.world(), a));
+        // now they're tied, but keep last frame's order
+        app.world_mut().entity_mut(a).remove::<SortPosition>();
+        for _ in 0..3 {
+            app.update();
+            assert!(get_z(app.world(), b) < get_z(app.world(), a));
+        }
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn mark_transforms_changed() {
+        #[derive(Resource, Default)]
+        struct ChangedCount(usize);
+
+        fn count_changed(
+            query: Query<(), Changed<GlobalTransform>>,
+            mut count: ResMut<ChangedCount>,
+        ) {
+            count.0 = query.iter().count();
+        }
+
+        for mark in [false, true] {
+            let mut app = test_app();
+            app.insert_resource(SpriteLayerOptions {
+                mark_transforms_changed: mark,
+                ..default()
+            })
+            .init_resource::<ChangedCount>()
+            .add_systems(Last, count_changed.after(SpriteLayerSet::SetZCoordinates));
+            let a = app
+                .world_mut()
+                .spawn((transform_at(0.0, 0.0), SortPosition(0.0), Layer::Top))
+                .id();
+            app.world_mut()
+                .spawn((transform_at(0.0, 0.0), SortPosition(1.0), Layer::Top));
+            app.update();
+            app.update();
+            assert_eq!(app.world().resource::<ChangedCount>().0, 0);
+
+            // reorder them without touching their transforms
+            app.world_mut().get_mut::<SortPosition>(a).unwrap().0 = 2.0;
+            app.update();
+            let expected = if mark { 2 } else { 0 };
+            assert_eq!(app.world().resource::<ChangedCount>().0, expected);
+        }
+    }
+
+    #[test]
+    fn static_transforms_are_untouched() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            mark_transforms_changed: true,
+            ..default()
+        });
+        let entity = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        app.update();
+        let last_changed = |app: &App| {
+            app.world()
+                .entity(entity)
+                .get_ref::<GlobalTransform>()
+                .unwrap()
+                .last_changed()
+        };
+        let settled = last_changed(&app);
+        for _ in 0..3 {
+            app.update();
+            assert_eq!(last_changed(&app), settled);
+            assert_eq!(get_z(app.world(), entity), 2.0);
+        }
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn late_spawned_entities() {
+        let mut app = test_app();
+        let existing = app.world_mut().spawn(layer_bundle(Layer::Middle)).id();
+        app.update();
+        // Spawned after transform propagation, so its `GlobalTransform` is still the identity.
+        app.add_systems(
+            Last,
+            (|mut commands: Commands| {
+                commands.spawn((transform_at(0.0, 5.0), Layer::Middle));
+                // Only at y = 5 once its parent's transform is taken into account.
+                commands
+                    .spawn(transform_at(0.0, 5.0))
+                    .with_children(|parent| {
+                        parent.spawn((transform_at(0.0, 0.0), Layer::Middle));
+                    });
+            })
+            .run_if(run_once())
+            .before(SpriteLayerSet::SetZCoordinates),
+        );
+        app.update();
+        let spawned = app
+            .world_mut()
+            .query_filtered::<Entity, With<Layer>>()
+            .iter(app.world())
+            .filter(|entity| *entity != existing)
+            .collect::<Vec<_>>();
+        assert_eq!(spawned.len(), 2);
+        for entity in spawned {
+            assert!(get_z(app.world(), entity) < get_z(app.world(), existing));
+        }
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn sort_inherited_by_owner() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            sort_inherited_by_owner: true,
+            ..default()
+        });
+        let parent = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Top))
+            .id();
+        // Without the option, these would sort by their own y-coordinates, on either side of
+        // `other`.
+        let behind = app
+            .world_mut()
+            .spawn(TransformBundle::from_transform(Transform::from_xyz(
+                0.0, -5.0, -1.0,
+            )))
+            .set_parent(parent)
+            .id();
+        let in_front = app
+            .world_mut()
+            .spawn(TransformBundle::from_transform(Transform::from_xyz(
+                0.0, 5.0, 1.0,
+            )))
+            .set_parent(parent)
+            .id();
+        let other = app
+            .world_mut()
+            .spawn((transform_at(0.0, 1.0), Layer::Top))
+            .id();
+        app.update();
+        assert_eq!(sorted_by_z(&mut app), [other, behind, parent, in_front]);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn freeze_internal_order() {
+        let mut app = test_app();
+        let container = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Middle))
+            .id();
+        let back = app
+            .world_mut()
+            .spawn(transform_at(0.0, 1.0))
+            .set_parent(container)
+            .id();
+        let front = app
+            .world_mut()
+            .spawn(transform_at(0.0, -1.0))
+            .set_parent(container)
+            .id();
+        let other = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.5), Layer::Middle))
+            .id();
+        app.update();
+        assert_eq!(sorted_by_z(&mut app), [back, other, container, front]);
+
+        app.world_mut()
+            .entity_mut(container)
+            .insert(FreezeInternalOrder);
+        let mut move_to = |entity, y| {
+            app.world_mut()
+                .get_mut::<Transform>(entity)
+                .unwrap()
+                .translation
+                .y = y;
+        };
+        move_to(container, 5.0);
+        move_to(back, -10.0);
+        move_to(front, 10.0);
+        app.update();
+        assert_eq!(sorted_by_z(&mut app), [back, container, front, other]);
+
+        app.world_mut()
+            .entity_mut(container)
+            .remove::<FreezeInternalOrder>();
+        app.update();
+        assert_eq!(sorted_by_z(&mut app), [front, container, other, back]);
+    }
+
+    #[test]
+    fn hierarchy_cycle_terminates() {
+        let mut app = test_app();
+        let a = app.world_mut().spawn(transform_at(0.0, 0.0)).id();
+        let b = app
+            .world_mut()
+            .spawn(transform_at(0.0, 0.0))
+            .set_parent(a)
+            .id();
+        app.world_mut().entity_mut(a).set_parent(b);
+        // also walks up the hierarchy from `layered`
+        let layered = app
+            .world_mut()
+            .spawn((layer_bundle(Layer::Top), FreezeInternalOrder))
+            .set_parent(b)
+            .id();
+        app.update();
+        let propagated = app.world().resource::<PropagatedLayers<Layer>>();
+        assert_eq!(propagated.get(layered), Some(&Layer::Top));
+        assert_eq!(propagated.len(), 1);
+    }
+
+    #[test]
+    fn max_hierarchy_depth() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            max_hierarchy_depth: 3,
+            ..default()
+        });
+        let mut chain = vec![app.world_mut().spawn(layer_bundle(Layer::Top)).id()];
+        for _ in 0..4 {
+            let parent = *chain.last().unwrap();
+            chain.push(
+                app.world_mut()
+                    .spawn(transform_at(0.0, 0.0))
+                    .set_parent(parent)
+                    .id(),
+            );
+        }
+        app.update();
+        let propagated = app.world().resource::<PropagatedLayers<Layer>>();
+        let managed = chain
+            .iter()
+            .map(|entity| propagated.contains(*entity))
+            .collect::<Vec<_>>();
+        assert_eq!(managed, [true, true, true, false, false]);
+    }
+
+    #[test]
+    fn child_with_no_transform() {
+        let mut app = test_app();
+        let entity = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        let child = app.world_mut().spawn_empty().set_parent(entity).id();
+        let grandchild = app
+            .world_mut()
+            .spawn(transform_at(0.0, 0.0))
+            .set_parent(child)
+            .id();
+        app.update();
+        assert_eq!(
+            get_z(app.world(), grandchild).floor(),
+            Layer::Top.as_z_coordinate()
+        );
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn missing_transform() {
+        for missing_transform in [
+            MissingTransform::Origin,
+            MissingTransform::Warn,
+            MissingTransform::Skip,
+        ] {
+            let mut app = test_app();
+            app.insert_resource(SpriteLayerOptions {
+                missing_transform,
+                ..default()
+            });
+            let back = app
+                .world_mut()
+                .spawn((transform_at(0.0, 1.0), Layer::Top))
+                .id();
+            let missing = app.world_mut().spawn(Layer::Top).id();
+            let child = app
+                .world_mut()
+                .spawn(transform_at(0.0, -1.0))
+                .set_parent(missing)
+                .id();
+            app.update();
+            if missing_transform == MissingTransform::Skip {
+                assert_eq!(sorted_by_z(&mut app), [back, child]);
+                assert!(!app
+                    .world()
+                    .resource::<PropagatedLayers<Layer>>()
+                    .contains(missing));
+            } else {
+                assert_eq!(sorted_by_z(&mut app), [back, missing, child]);
+            }
+        }
+    }
+
+    #[test]
+    fn render_z_coordinate_matches_transform() {
+        let mut app = test_app();
+        let entity = app.world_mut().spawn(layer_bundle(Layer::Middle)).id();
+        let child = app
+            .world_mut()
+            .spawn(transform_at(0.0, 1.0))
+            .set_parent(entity)
+            .id();
+        app.update();
+        for entity in [entity, child] {
+            let render_z = app.world().get::<RenderZCoordinate>(entity).unwrap().0;
+            assert_eq!(render_z, get_z(app.world(), entity));
+        }
+    }
+
+    /// Returns the entities sorted by their z-coordinate, back to front.
+    fn sorted_by_z(app: &mut App) -> Vec<Entity> {
+        app.world_mut()
+            .run_system_once(|query: Query<(Entity, &RenderZCoordinate)>| {
+                query
+                    .iter()
+                    .collect::<Vec<_>>()
+                    .tap_mut(|v| v.sort_by_key(|(_, z)| OrderedFloat(z.0)))
+                    .into_iter()
+                    .map(|(entity, _)| entity)
+                    .collect()
+            })
+    }
+
+    #[test]
+    fn fixed_point_matches_float_ordering() {
+        let mut ys = (0..20).map(|i| i as f32 * 0.5).collect::<Vec<_>>();
+        fastrand::shuffle(&mut ys);
+        let mut orders = vec![];
+        for fixed_point_scale in [None, Some(100.0)] {
+            let mut app = test_app();
+            app.insert_resource(SpriteLayerOptions {
+                fixed_point_scale,
+                ..default()
+            });
+            for &y in &ys {
+                app.world_mut().spawn((transform_at(0.0, y), Layer::Middle));
+            }
+            app.update();
+            orders.push(sorted_by_z(&mut app));
+        }
+        assert_eq!(orders[0], orders[1]);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn sort_epsilon() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            sort_epsilon: 1.0,
+            ..default()
+        });
+        let [a, b, c, d, e] = [0.0, 0.49, 0.5, 1.49, -0.51].map(|y| {
+            app.world_mut()
+                .spawn((transform_at(0.0, y), Layer::Middle))
+                .id()
+        });
+        app.update();
+        let z = |entity| get_z(app.world(), entity);
+        // 0.5 and 1.49 round to 1, 0.0 and 0.49 to 0, and -0.51 to -1: three slots
+        assert_eq!(z(c), 1.0);
+        assert_eq!(z(d), 1.0);
+        assert_eq!(z(a), 1.0 + 1.0 / 3.0);
+        assert_eq!(z(b), 1.0 + 1.0 / 3.0);
+        assert_eq!(z(e), 1.0 + 2.0 / 3.0);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn sort_hysteresis() {
+        // How many times `b` passes `a` while moving down past it slowly, with some jitter.
+        let swaps = |hysteresis| {
+            let mut app = test_app();
+            app.insert_resource(SpriteLayerOptions {
+                sort_hysteresis: hysteresis,
+                ..default()
+            });
+            let a = app
+                .world_mut()
+                .spawn((transform_at(0.0, 0.0), Layer::Middle))
+                .id();
+            let b = app
+                .world_mut()
+                .spawn((transform_at(0.0, 0.5), Layer::Middle))
+                .id();
+            let mut swaps = 0;
+            let mut b_in_front = None;
+            for i in 0..100 {
+                let jitter = if i % 2 == 0 { 0.04 } else { -0.04 };
+                app.world_mut()
+                    .get_mut::<Transform>(b)
+                    .unwrap()
+                    .translation
+                    .y = 0.5 - 0.01 * i as f32 + jitter;
+                app.update();
+                let in_front = get_z(app.world(), b) > get_z(app.world(), a);
+                if b_in_front.is_some_and(|was| was != in_front) {
+                    swaps += 1;
+                }
+                b_in_front = Some(in_front);
+            }
+            assert_eq!(b_in_front, Some(true));
+            swaps
+        };
+        assert!(swaps(0.0) > 1);
+        assert_eq!(swaps(0.1), 1);
+    }
+
+    #[test]
+    fn y_range_clamps() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            y_range: Some(-1000.0..1000.0),
+            ..default()
+        });
+        let mut spawn_at = |y| {
+            app.world_mut()
+                .spawn((transform_at(0.0, y), Layer::Middle))
+                .id()
+        };
+        // Without clamping, each of these pairs would be in the opposite order. With it, they're
+        // tied and fall back to being ordered by `Entity`.
+        let above = spawn_at(1500.0);
+        let far_above = spawn_at(2000.0);
+        let middle = spawn_at(0.0);
+        let far_below = spawn_at(-2000.0);
+        let below = spawn_at(-1000.0);
+        app.update();
+        assert_eq!(
+            sorted_by_z(&mut app),
+            [above, far_above, middle, far_below, below]
+        );
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn camera_y_band() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            camera_y_band: Some(100.0),
+            ..default()
+        });
+        let camera = app
+            .world_mut()
+            .spawn((TransformBundle::default(), SortCamera))
+            .id();
+        let mut spawn_at = |y| {
+            app.world_mut()
+                .spawn((transform_at(0.0, y), Layer::Middle))
+                .id()
+        };
+        // outside the band, these pairs are tied and ordered by `Entity`
+        let above = spawn_at(300.0);
+        let far_above = spawn_at(500.0);
+        let high = spawn_at(50.0);
+        let low = spawn_at(-50.0);
+        let far_below = spawn_at(-500.0);
+        let below = spawn_at(-300.0);
+        app.update();
+        assert_eq!(
+            sorted_by_z(&mut app),
+            [above, far_above, high, low, far_below, below]
+        );
+
+        // the band follows the camera
+        app.world_mut()
+            .entity_mut(camera)
+            .insert(Transform::from_xyz(0.0, -400.0, 0.0));
+        app.update();
+        assert_eq!(
+            sorted_by_z(&mut app),
+            [above, far_above, high, low, below, far_below]
+        );
+    }
+
+    #[test]
+    fn unmanaged_render_z_coordinate_is_left_alone() {
+        let mut app = test_app();
+        let stray = app
+            .world_mut()
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, 3.0)),
+                RenderZCoordinate(7.0),
+            ))
+            .id();
+        app.world_mut().spawn(layer_bundle(Layer::Top));
+        app.update();
+        app.update();
+        let transform = app.world().get::<Transform>(stray).unwrap();
+        assert_eq!(transform.translation.z, 3.0);
+        assert_eq!(get_z(app.world(), stray), 3.0);
+    }
+
+    #[test]
+    fn render_z_coordinate_removed_when_unmanaged() {
+        let mut app = test_app();
+        let entity = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        app.update();
+        assert!(app.world().get::<RenderZCoordinate>(entity).is_some());
+        app.world_mut().entity_mut(entity).remove::<Layer>();
+        app.update();
+        assert!(app.world().get::<RenderZCoordinate>(entity).is_none());
+    }
+
+    #[test]
+    fn z_restored_when_unmanaged() {
+        let mut app = test_app();
+        let entity = app
+            .world_mut()
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, 0.25)),
+                Layer::Top,
+            ))
+            .id();
+        app.update();
+        assert_eq!(get_z(app.world(), entity), 2.0);
+        // no clearing in between frames
+        app.world_mut().run_schedule(First);
+        assert_eq!(get_z(app.world(), entity), 2.0);
+        app.world_mut().entity_mut(entity).remove::<Layer>();
+        app.update();
+        assert_eq!(get_z(app.world(), entity), 0.25);
+    }
+
+    #[test]
+    fn z_remap() {
+        let mut app = test_app();
+        app.insert_resource(ZRemap::new(|z| -z));
+        let top = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        let bottom = app.world_mut().spawn(layer_bundle(Layer::Bottom)).id();
+        app.update();
+        // the sorted z-coordinates are untouched, only what's written is flipped
+        assert_eq!(
+            app.world().get::<RenderZCoordinate>(top),
+            Some(&RenderZCoordinate(Layer::Top.as_z_coordinate()))
+        );
+        assert_eq!(get_z(app.world(), top), -Layer::Top.as_z_coordinate());
+        assert_eq!(get_z(app.world(), bottom), -Layer::Bottom.as_z_coordinate());
+
+        // and the original comes back once it's unmanaged
+        app.world_mut().entity_mut(top).remove::<Layer>();
+        app.update();
+        assert_eq!(get_z(app.world(), top), 0.0);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn bias_add() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            apply_to: ApplyTo::BiasAdd,
+            ..default()
+        });
+        let back = app
+            .world_mut()
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 1.0, 10.0)),
+                Layer::Top,
+            ))
+            .id();
+        let front = app
+            .world_mut()
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, 10.0)),
+                Layer::Top,
+            ))
+            .id();
+        for _ in 0..2 {
+            app.update();
+            assert_eq!(get_z(app.world(), back), 10.0);
+            assert_eq!(get_z(app.world(), front), 10.5);
+        }
+        // the user's z still counts
+        app.world_mut()
+            .get_mut::<Transform>(front)
+            .unwrap()
+            .translation
+            .z = -3.0;
+        app.update();
+        assert_eq!(get_z(app.world(), front), -2.5);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn bias_add_leaves_out_scene_offsets() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            apply_to: ApplyTo::BiasAdd,
+            offset_center: true,
+            ..default()
+        });
+        let [back, front] = [1.0, 0.0].map(|y| {
+            app.world_mut()
+                .spawn((
+                    TransformBundle::from_transform(Transform::from_xyz(0.0, y, 10.0)),
+                    Layer::Top,
+                    SceneDepthOffset(100.0),
+                ))
+                .id()
+        });
+        app.update();
+        // the scene offset and the centered window are in the `RenderZCoordinate`...
+        let render_z = |entity| app.world().get::<RenderZCoordinate>(entity).unwrap().0;
+        assert_eq!(render_z(back), 101.5);
+        assert_eq!(render_z(front), 102.0);
+        // ...but only the offset from the start of the window is added
+        assert_eq!(get_z(app.world(), back), 10.0);
+        assert_eq!(get_z(app.world(), front), 10.5);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn bias_add_with_overlay() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            apply_to: ApplyTo::BiasAdd,
+            ..default()
+        });
+        let spawn = |app: &mut App, layer| {
+            app.world_mut()
+                .spawn((
+                    TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, 10.0)),
+                    layer,
+                ))
+                .id()
+        };
+        spawn(&mut app, Layer::Top);
+        let middle = spawn(&mut app, Layer::Middle);
+        let overlay = spawn(&mut app, Layer::Middle);
+        app.world_mut()
+            .entity_mut(overlay)
+            .insert(TopmostOverlay(0));
+        app.update();
+        // above everything in the `RenderZCoordinate`, but only just in front of its own layer
+        assert_eq!(
+            app.world().get::<RenderZCoordinate>(overlay).unwrap().0,
+            3.0
+        );
+        assert_eq!(get_z(app.world(), middle), 10.0);
+        assert_eq!(get_z(app.world(), overlay), 11.0);
+    }
+
+    #[test]
+    fn z_restored_when_apply_to_changes() {
+        let mut app = test_app();
+        let entity = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        app.update();
+        assert_eq!(get_z(app.world(), entity), 2.0);
+        app.insert_resource(SpriteLayerOptions {
+            apply_to: ApplyTo::ComponentOnly,
+            ..default()
+        });
+        app.update();
+        assert_eq!(get_z(app.world(), entity), 0.0);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn active_sort_set() {
+        let mut app = test_app();
+        let back = app
+            .world_mut()
+            .spawn((transform_at(0.0, 1.0), Layer::Top))
+            .id();
+        let culled = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Top))
+            .id();
+        let front = app
+            .world_mut()
+            .spawn((transform_at(0.0, -1.0), Layer::Top))
+            .id();
+        app.insert_resource(ActiveSortSet(EntityHashSet::from_iter([back, front])));
+        app.update();
+        assert_eq!(sorted_by_z(&mut app), [back, front]);
+        assert!(!app
+            .world()
+            .resource::<PropagatedLayers<Layer>>()
+            .contains(culled));
+
+        app.world_mut()
+            .resource_mut::<ActiveSortSet>()
+            .0
+            .insert(culled);
+        app.update();
+        assert_eq!(sorted_by_z(&mut app), [back, culled, front]);
+
+        app.world_mut()
+            .insert_resource(ActiveSortSet(EntityHashSet::from_iter([culled])));
+        app.update();
+        assert_eq!(sorted_by_z(&mut app), [culled]);
+        assert_eq!(get_z(app.world(), back), 0.0);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn without_transforms() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(SpriteLayerOptions {
+                apply_to: ApplyTo::ComponentOnly,
+                ..default()
+            })
+            .add_plugins(SpriteLayerPlugin::<Layer>::default());
+        let front = app
+            .world_mut()
+            .spawn((SortPosition(-1.0), Layer::Middle))
+            .id();
+        let back = app
+            .world_mut()
+            .spawn((SortPosition(1.0), Layer::Middle))
+            .id();
+        let top = app.world_mut().spawn((SortPosition(5.0), Layer::Top)).id();
+        let child = app
+            .world_mut()
+            .spawn(SortPosition(0.0))
+            .set_parent(back)
+            .id();
+        app.update();
+        assert_eq!(sorted_by_z(&mut app), [back, child, front, top]);
+        assert_eq!(
+            app.world_mut()
+                .query::<&GlobalTransform>()
+                .iter(app.world())
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn layer_round_trips_through_scene() {
+        #[derive(Debug, Clone, Component, PartialEq, Eq, Hash, Reflect)]
+        #[reflect(Component)]
+        enum Reflected {
+            Back,
+            Front,
+        }
+
+        impl LayerIndex for Reflected {
+            fn as_z_coordinate(&self) -> f32 {
+                match self {
+                    Reflected::Back => 0.0,
+                    Reflected::Front => 1.0,
+                }
+            }
+        }
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Reflected>::default().with_reflect());
+        let entity = app.world_mut().spawn(Reflected::Front).id();
+        let registry = app.world().resource::<AppTypeRegistry>().clone();
+        let scene = DynamicSceneBuilder::from_world(app.world())
+            .extract_entity(entity)
+            .build();
+        let serialized = scene.serialize(&registry.read()).unwrap();
+
+        let deserializer = SceneDeserializer {
+            type_registry: &registry.read(),
+        };
+        let scene = deserializer
+            .deserialize(&mut ron::Deserializer::from_str(&serialized).unwrap())
+            .unwrap();
+        let mut world = World::new();
+        world.insert_resource(registry.clone());
+        scene.write_to_world(&mut world, &mut default()).unwrap();
+        let layers = world
+            .query::<&Reflected>()
+            .iter(&world)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(layers, [Reflected::Front]);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn sort_anchor_accounts_for_rotation() {
         let mut app = test_app();
-        let top = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
-        let child_with_layer = app
+        app.insert_resource(SpriteLayerOptions {
+            sort_anchor_local: Vec3::new(0.0, -10.0, 0.0),
+            ..default()
+        });
+        // Upside down, so its anchor is at y = 10.
+        let flipped = app
             .world_mut()
-            .spawn(layer_bundle(Layer::Middle))
-            .set_parent(top)
+            .spawn((
+                TransformBundle::from_transform(Transform::from_rotation(Quat::from_rotation_z(
+                    std::f32::consts::PI,
+                ))),
+                Layer::Top,
+            ))
             .id();
-        let child_without_layer = app
+        // Anchor at y = -9.
+        let upright = app
             .world_mut()
-            .spawn(transform_at(0.0, 0.0))
-            .set_parent(top)
+            .spawn((transform_at(0.0, 1.0), Layer::Top))
+            .id();
+        app.update();
+        // Without the rotation, `flipped` would be lower on screen and so in front.
+        assert!(get_z(app.world(), flipped) < get_z(app.world(), upright));
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn freeze_y_sort() {
+        let mut app = test_app();
+        let back = app
+            .world_mut()
+            .spawn((transform_at(0.0, 1.0), Layer::Middle))
+            .id();
+        let front = app
+            .world_mut()
+            .spawn((transform_at(0.0, 0.0), Layer::Middle))
             .id();
         app.update();
+        assert_eq!(get_z(app.world(), back), 1.0);
+        assert_eq!(get_z(app.world(), front), 1.5);
 
-        // we use .floor() here since y-sorting can add a fractional amount to the coordinates
+        app.insert_resource(SpriteLayerOptions {
+            freeze_y_sort: true,
+            ..default()
+        });
+        app.world_mut()
+            .get_mut::<Transform>(front)
+            .unwrap()
+            .translation
+            .y = 5.0;
+        let late = app
+            .world_mut()
+            .spawn((transform_at(0.0, -5.0), Layer::Middle))
+            .id();
+        app.update();
+        assert_eq!(get_z(app.world(), back), 1.0);
+        assert_eq!(get_z(app.world(), front), 1.5);
+        assert_eq!(get_z(app.world(), late), 1.0);
+
+        // layers still apply, at the same offset
+        app.world_mut().entity_mut(front).insert(Layer::Top);
+        app.update();
+        assert_eq!(get_z(app.world(), front), 2.5);
+
+        app.insert_resource(SpriteLayerOptions::default());
+        app.world_mut().entity_mut(front).insert(Layer::Middle);
+        app.update();
+        assert!(get_z(app.world(), front) < get_z(app.world(), back));
+        assert!(get_z(app.world(), back) < get_z(app.world(), late));
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn offset_center() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            offset_center: true,
+            ..default()
+        });
+        let spawn_layer = |app: &mut App, layer: Layer| {
+            (0..4)
+                .map(|i| {
+                    app.world_mut()
+                        .spawn((transform_at(0.0, -(i as f32)), layer))
+                        .id()
+                })
+                .collect::<Vec<_>>()
+        };
+        let middle = spawn_layer(&mut app, Layer::Middle);
+        let top = spawn_layer(&mut app, Layer::Top);
+        app.update();
+        let zs = |app: &App, entities: &[Entity]| {
+            entities
+                .iter()
+                .map(|entity| get_z(app.world(), *entity))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(zs(&app, &middle), [0.5, 0.75, 1.0, 1.25]);
+        assert_eq!(zs(&app, &top), [1.5, 1.75, 2.0, 2.25]);
+
+        // without y-sorting, they're all at the layer's z-coordinate
+        app.insert_resource(SpriteLayerOptions {
+            offset_center: true,
+            y_sort: false,
+            ..default()
+        });
+        app.update();
+        assert_eq!(zs(&app, &middle), [1.0; 4]);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn sort_by_camera_depth() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            sort_by_camera_depth: true,
+            apply_to: ApplyTo::ComponentOnly,
+            ..default()
+        });
+        let camera = app
+            .world_mut()
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, 10.0)),
+                SortCamera,
+            ))
+            .id();
+        // y-sorting would put these the other way around
+        let billboards = [(10.0, 5.0), (0.0, 0.0), (-10.0, -10.0)].map(|(y, z)| {
+            app.world_mut()
+                .spawn((
+                    TransformBundle::from_transform(Transform::from_xyz(0.0, y, z)),
+                    Layer::Middle,
+                ))
+                .id()
+        });
+        let zs = |app: &App| {
+            billboards.map(|entity| app.world().get::<RenderZCoordinate>(entity).unwrap().0)
+        };
+        app.update();
+        let [near, middle, far] = zs(&app);
+        assert!(far < middle && middle < near);
+
+        // from the other side
+        app.world_mut()
+            .entity_mut(camera)
+            .insert(Transform::from_xyz(0.0, 0.0, -30.0).looking_to(Vec3::Z, Vec3::Y));
+        app.update();
+        let [far, middle, near] = zs(&app);
+        assert!(far < middle && middle < near);
+
+        // without a camera, it's back to y-sorting
+        app.world_mut().despawn(camera);
+        app.update();
+        let [top, middle, bottom] = zs(&app);
+        assert!(top < middle && middle < bottom);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn z_hint_tiebreak() {
+        let mut app = test_app();
+        let hinted = |app: &mut App, z| {
+            app.world_mut()
+                .spawn((
+                    TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, z)),
+                    Layer::Middle,
+                ))
+                .id()
+        };
+        // by `Entity`, `front` would be behind
+        let front = hinted(&mut app, 5.0);
+        let back = hinted(&mut app, 1.0);
+        app.update();
+        assert!(get_z(app.world(), front) < get_z(app.world(), back));
+
+        app.insert_resource(SpriteLayerOptions {
+            z_hint_tiebreak: true,
+            ..default()
+        });
+        // the z-coordinates the plugin wrote last frame don't count
+        for _ in 0..3 {
+            app.update();
+            assert!(get_z(app.world(), back) < get_z(app.world(), front));
+        }
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn sort_scale() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            secondary_sort_axis: Some(Vec3::X),
+            ..default()
+        });
+        let far = app
+            .world_mut()
+            .spawn((transform_at(0.0, 10.0), Layer::Middle))
+            .id();
+        let near = app
+            .world_mut()
+            .spawn((transform_at(0.0, 5.0), Layer::Middle))
+            .id();
+        app.update();
+        let z = |app: &App, entity| get_z(app.world(), entity);
+        assert!(z(&app, far) < z(&app, near));
+
+        // 10 * 0.1 sorts in front of 5
+        app.world_mut().entity_mut(far).insert(SortScale(0.1));
+        app.update();
+        assert!(z(&app, near) < z(&app, far));
+        // and 10 * 0.6 behind it again
+        app.world_mut().entity_mut(far).insert(SortScale(0.6));
+        app.update();
+        assert!(z(&app, far) < z(&app, near));
+
+        // the secondary axis isn't scaled
+        let right = app
+            .world_mut()
+            .spawn((transform_at(1.0, 0.0), Layer::Bottom, SortScale(0.5)))
+            .id();
+        let left = app
+            .world_mut()
+            .spawn((transform_at(-1.0, 0.0), Layer::Bottom, SortScale(2.0)))
+            .id();
+        app.update();
+        assert!(z(&app, left) < z(&app, right));
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn secondary_sort_axis() {
+        let mut app = test_app();
+        // spawned right to left, so entity order would put the left one in front
+        let right = app
+            .world_mut()
+            .spawn((transform_at(5.0, 0.0), Layer::Top))
+            .id();
+        let left = app
+            .world_mut()
+            .spawn((transform_at(-5.0, 0.0), Layer::Top))
+            .id();
+        let below = app
+            .world_mut()
+            .spawn((transform_at(-10.0, -1.0), Layer::Top))
+            .id();
+        app.update();
+        assert!(get_z(app.world(), right) < get_z(app.world(), left));
+
+        for (axis, expected) in [(Vec3::X, [left, right]), (Vec3::NEG_X, [right, left])] {
+            app.insert_resource(SpriteLayerOptions {
+                secondary_sort_axis: Some(axis),
+                ..default()
+            });
+            app.update();
+            let [back, front] = expected;
+            assert!(get_z(app.world(), back) < get_z(app.world(), front));
+            // y still comes first
+            assert!(get_z(app.world(), front) < get_z(app.world(), below));
+        }
+    }
+
+    #[cfg(all(feature = "bevy_render", feature = "y_sort"))]
+    #[test]
+    fn sort_by_aabb_bottom() {
+        use bevy::render::primitives::Aabb;
+
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            sort_by_aabb_bottom: true,
+            ..default()
+        });
+        // A 2x20 box, scaled up 2x and turned on its side: it's 40 wide and 4 tall, so its bottom
+        // is at y = 3 - 2 = 1. Unrotated, it'd reach down to 3 - 20 = -17.
+        let rotated = app
+            .world_mut()
+            .spawn((
+                TransformBundle::from_transform(
+                    Transform::from_xyz(0.0, 3.0, 0.0)
+                        .with_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_2))
+                        .with_scale(Vec3::splat(2.0)),
+                ),
+                Aabb::from_min_max(Vec3::new(-1.0, -10.0, 0.0), Vec3::new(1.0, 10.0, 0.0)),
+                Layer::Top,
+            ))
+            .id();
+        // A 2x2 box with its bottom at y = 0.
+        let small = app
+            .world_mut()
+            .spawn((
+                transform_at(0.0, 1.0),
+                Aabb::from_min_max(Vec3::splat(-1.0), Vec3::splat(1.0)),
+                Layer::Top,
+            ))
+            .id();
+        // No `Aabb`, so sorted by its translation, y = 2.
+        let plain = app
+            .world_mut()
+            .spawn((transform_at(0.0, 2.0), Layer::Top))
+            .id();
+        app.update();
+        let z = |entity| get_z(app.world(), entity);
+        assert!(z(plain) < z(rotated));
+        assert!(z(rotated) < z(small));
+    }
+
+    #[cfg(feature = "bevy_ui")]
+    #[test]
+    fn ui_nodes_are_left_alone() {
+        let mut app = test_app();
+        let node = app
+            .world_mut()
+            .spawn((
+                NodeBundle {
+                    transform: Transform::from_xyz(10.0, 20.0, 0.0),
+                    ..default()
+                },
+                Layer::Top,
+            ))
+            .id();
+        let sprite = app
+            .world_mut()
+            .spawn(TransformBundle::default())
+            .set_parent(node)
+            .id();
+        app.update();
+        let global = app.world().get::<GlobalTransform>(node).unwrap();
+        assert_eq!(global.translation(), Vec3::new(10.0, 20.0, 0.0));
+        assert!((2.0..3.0).contains(&app.world().get::<RenderZCoordinate>(node).unwrap().0));
+        // sprites under it still get their layer's z
+        assert!((2.0..3.0).contains(&get_z(app.world(), sprite)));
+    }
+
+    #[cfg(feature = "bevy_render")]
+    #[test]
+    fn layer_render_layers() {
+        #[derive(Debug, Copy, Clone, Component, PartialEq, Eq, Hash)]
+        enum MapLayer {
+            World,
+            Minimap,
+        }
+
+        impl LayerIndex for MapLayer {
+            fn as_z_coordinate(&self) -> f32 {
+                0.0
+            }
+
+            fn render_layers(&self) -> RenderLayers {
+                match self {
+                    MapLayer::World => RenderLayers::layer(0),
+                    MapLayer::Minimap => RenderLayers::layer(1),
+                }
+            }
+        }
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<MapLayer>::default().with_layer_render_layers());
+        let world = app
+            .world_mut()
+            .spawn((TransformBundle::default(), MapLayer::World))
+            .id();
+        let marker = app
+            .world_mut()
+            .spawn((TransformBundle::default(), MapLayer::Minimap))
+            .id();
+        let child = app
+            .world_mut()
+            .spawn(TransformBundle::default())
+            .set_parent(marker)
+            .id();
+        app.update();
+        let render_layers = |app: &App, entity| app.world().get::<RenderLayers>(entity).cloned();
+        assert_eq!(render_layers(&app, world), Some(RenderLayers::layer(0)));
+        assert_eq!(render_layers(&app, marker), Some(RenderLayers::layer(1)));
+        assert_eq!(render_layers(&app, child), Some(RenderLayers::layer(1)));
+
+        app.world_mut().entity_mut(marker).insert(MapLayer::World);
+        app.update();
+        assert_eq!(render_layers(&app, child), Some(RenderLayers::layer(0)));
+
+        app.world_mut().entity_mut(marker).remove::<MapLayer>();
+        app.update();
+        assert_eq!(render_layers(&app, marker), None);
+        assert_eq!(render_layers(&app, child), None);
+    }
+
+    #[cfg(feature = "bevy_render")]
+    #[test]
+    fn render_layers_filter() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(
+                SpriteLayerPlugin::<Layer>::default().with_render_layers(RenderLayers::layer(1)),
+            );
+        let on_layer = app
+            .world_mut()
+            .spawn((layer_bundle(Layer::Top), RenderLayers::layer(1)))
+            .id();
+        // on layer 0 by default
+        let off_layer = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
+        app.update();
+        assert!(app.world().get::<RenderZCoordinate>(on_layer).is_some());
+        assert!(app.world().get::<RenderZCoordinate>(off_layer).is_none());
+        assert_eq!(get_z(app.world(), off_layer), 0.0);
+    }
+
+    #[test]
+    fn auto_layer() {
+        #[derive(Component)]
+        struct Enemy;
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Layer>::default().auto_layer::<Enemy>(Layer::Middle));
+        let enemy = app.world_mut().spawn((transform_at(0.0, 0.0), Enemy)).id();
+        let explicit = app
+            .world_mut()
+            .spawn((layer_bundle(Layer::Top), Enemy))
+            .id();
+        app.update();
+        assert_eq!(app.world().get::<Layer>(enemy), Some(&Layer::Middle));
+        assert_eq!(app.world().get::<Layer>(explicit), Some(&Layer::Top));
+        // and it's picked up in the same frame
         assert_eq!(
-            get_z(app.world(), child_with_layer).floor(),
+            get_z(app.world(), enemy).floor(),
             Layer::Middle.as_z_coordinate()
         );
+    }
+
+    #[test]
+    fn layered_leaf_under_unlayered_ancestors() {
+        let mut app = test_app();
+        let root = app.world_mut().spawn(transform_at(0.0, 0.0)).id();
+        let mid = app
+            .world_mut()
+            .spawn(transform_at(0.0, 0.0))
+            .set_parent(root)
+            .id();
+        let leaf = app
+            .world_mut()
+            .spawn(layer_bundle(Layer::Top))
+            .set_parent(mid)
+            .id();
+        let leaf_child = app
+            .world_mut()
+            .spawn(transform_at(0.0, 0.0))
+            .set_parent(leaf)
+            .id();
+        app.update();
         assert_eq!(
-            get_z(app.world(), child_without_layer).floor(),
-            get_z(app.world(), top).floor()
+            get_z(app.world(), leaf).floor(),
+            Layer::Top.as_z_coordinate()
         );
+        assert_eq!(
+            get_z(app.world(), leaf_child).floor(),
+            Layer::Top.as_z_coordinate()
+        );
+        assert!(app.world().get::<RenderZCoordinate>(root).is_none());
+        assert!(app.world().get::<RenderZCoordinate>(mid).is_none());
+    }
+
+    #[cfg(feature = "y_sort")]
+    fn set_y_without_change_detection(world: &mut World, entity: Entity, y: f32) {
+        let mut transform = world.get_mut::<GlobalTransform>(entity).unwrap();
+        let transform = transform.bypass_change_detection();
+        let mut affine = transform.affine();
+        affine.translation.y = y;
+        *transform = GlobalTransform::from(affine);
     }
 
+    #[cfg(feature = "y_sort")]
     #[test]
-    fn y_sorting() {
+    fn time_sliced_sort() {
         let mut app = test_app();
-        for _ in 0..10 {
-            app.world_mut()
-                .spawn((transform_at(0.0, fastrand::f32()), Layer::Top));
+        app.insert_resource(SpriteLayerOptions {
+            time_sliced_sort: Some(4),
+            ..default()
+        });
+        let entities = (0..10)
+            .map(|i| {
+                app.world_mut()
+                    .spawn((transform_at(0.0, i as f32), Layer::Top))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+        app.update();
+        // higher y is further back
+        let mut expected = entities.clone();
+        expected.reverse();
+        assert_eq!(sorted_by_z(&mut app), expected);
+
+        // moving normally is reflected immediately
+        app.world_mut()
+            .get_mut::<Transform>(entities[0])
+            .unwrap()
+            .translation
+            .y = 100.0;
+        app.update();
+        assert_eq!(sorted_by_z(&mut app)[0], entities[0]);
+
+        // moving behind change detection's back is reflected within 4 frames
+        set_y_without_change_detection(app.world_mut(), entities[9], -100.0);
+        for _ in 0..4 {
+            app.update();
+        }
+        assert_eq!(sorted_by_z(&mut app).last(), Some(&entities[9]));
+    }
+
+    #[test]
+    fn layer_order_macro() {
+        #[derive(Debug, Clone, Component, PartialEq, Eq, Hash)]
+        enum Ordered {
+            Back,
+            Middle,
+            Front,
         }
+        layer_order!(Ordered: Back, Middle, Front);
+
+        assert_eq!(Ordered::Back.as_z_coordinate(), 0.0);
+        assert_eq!(Ordered::Middle.as_z_coordinate(), LAYER_WINDOW);
+        assert_eq!(Ordered::Front.as_z_coordinate(), 2.0 * LAYER_WINDOW);
+    }
+
+    #[test]
+    fn component_only_leaves_transforms_alone() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            apply_to: ApplyTo::ComponentOnly,
+            ..default()
+        });
+        let entity = app
+            .world_mut()
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, 0.5)),
+                Layer::Top,
+            ))
+            .id();
         app.update();
-        let positions =
+        app.update();
+        let render_z = app.world().get::<RenderZCoordinate>(entity).unwrap().0;
+        assert_eq!(render_z.floor(), Layer::Top.as_z_coordinate());
+        assert_eq!(get_z(app.world(), entity), 0.5);
+    }
+
+    #[test]
+    fn float_keys_preserve_order() {
+        let ys = [
+            f32::NEG_INFINITY,
+            -100.0,
+            -1.5,
+            -0.0,
+            0.0,
+            0.25,
+            3.0,
+            f32::INFINITY,
+        ];
+        for pair in ys.windows(2) {
+            assert!(quantize_y(pair[0], None) < quantize_y(pair[1], None));
+        }
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn sort_groups_stay_contiguous() {
+        let mut app = test_app();
+        let spawn = |app: &mut App, y: f32, group: u64| {
             app.world_mut()
-                .run_system_once(|query: Query<&GlobalTransform>| -> Vec<Vec3> {
-                    query
-                        .into_iter()
-                        .map(|transform| transform.translation())
-                        .collect()
-                });
-        let sorted_by_z = positions
-            .clone()
-            .tap_mut(|positions| positions.sort_by_key(|vec| OrderedFloat(vec.z)));
-        let sorted_by_y = positions
-            .tap_mut(|positions| positions.sort_by_key(|vec| Reverse(OrderedFloat(vec.y))));
-        assert_eq!(sorted_by_z, sorted_by_y);
+                .spawn((transform_at(0.0, y), Layer::Top, SortGroup(group)))
+                .id()
+        };
+        let a_front = spawn(&mut app, 0.0, 1);
+        let a_back = spawn(&mut app, 5.0, 1);
+        let b_front = spawn(&mut app, 2.0, 2);
+        let b_back = spawn(&mut app, 3.0, 2);
+        app.update();
+        // group 2 is behind group 1, even though `a_back` is higher up than all of group 2
+        assert_eq!(sorted_by_z(&mut app), [b_back, b_front, a_back, a_front]);
     }
 
+    #[cfg(feature = "y_sort")]
     #[test]
-    fn child_with_no_transform() {
+    fn infinite_sort_position_is_at_the_back() {
         let mut app = test_app();
-        let entity = app.world_mut().spawn(layer_bundle(Layer::Top)).id();
-        let child = app.world_mut().spawn_empty().set_parent(entity).id();
-        let grandchild = app
+        let back = app
             .world_mut()
-            .spawn(transform_at(0.0, 0.0))
-            .set_parent(child)
+            .spawn((
+                transform_at(0.0, -100.0),
+                Layer::Top,
+                SortPosition(f32::INFINITY),
+            ))
+            .id();
+        let nan = app
+            .world_mut()
+            .spawn((
+                transform_at(0.0, -100.0),
+                Layer::Top,
+                SortPosition(f32::NAN),
+            ))
+            .id();
+        let front = app
+            .world_mut()
+            .spawn((
+                transform_at(0.0, 100.0),
+                Layer::Top,
+                SortPosition(f32::NEG_INFINITY),
+            ))
             .id();
+        for _ in 0..5 {
+            app.world_mut()
+                .spawn((transform_at(0.0, fastrand::f32()), Layer::Top));
+        }
         app.update();
-        assert_eq!(
-            get_z(app.world(), grandchild).floor(),
-            Layer::Top.as_z_coordinate()
-        );
+        let sorted = sorted_by_z(&mut app);
+        // the two infinitely far back entities are tied, so they're ordered by entity
+        assert_eq!(sorted[..2], [back, nan]);
+        assert_eq!(sorted.last(), Some(&front));
+        assert_eq!(get_z(app.world(), back), Layer::Top.as_z_coordinate());
     }
 }
@@ -0,0 +1,81 @@
+//! Helpers for adopting sprite layers in a project that already uses z-coordinates directly.
+use std::ops::Range;
+
+use bevy::prelude::*;
+
+use crate::LayerIndexWithContext;
+
+/// Gives a layer to every root entity (one with no [`Parent`]) that has a [`Transform`] but no
+/// `Layer`, based on its current z-coordinate: the entity gets the layer from the first range in
+/// `ranges` that contains its z-coordinate, or no layer if there isn't one. Returns the number of
+/// entities that were given a layer.
+///
+/// Descendants aren't given layers, since they'll inherit their root's. This doesn't change any
+/// transforms, so the old z-coordinates stay in the `Transform`s: the plugin overwrites them in the
+/// `GlobalTransform`, except with [`ApplyTo::BiasAdd`](crate::ApplyTo::BiasAdd), which adds to
+/// them. Zero them out yourself if they shouldn't count.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use extol_sprite_layer::{assign_layers_from_z, LayerIndex};
+/// # #[derive(Debug, Clone, Component, PartialEq, Eq, Hash)]
+/// # enum Layer { Background, Foreground }
+/// # impl LayerIndex for Layer { fn as_z_coordinate(&self) -> f32 { 0.0 } }
+/// let mut world = World::new();
+/// let entity = world.spawn(Transform::from_xyz(0.0, 0.0, 15.0)).id();
+/// assign_layers_from_z(
+///     &mut world,
+///     &[(0.0..10.0, Layer::Background), (10.0..20.0, Layer::Foreground)],
+/// );
+/// assert_eq!(world.get::<Layer>(entity), Some(&Layer::Foreground));
+/// ```
+pub fn assign_layers_from_z<Layer: LayerIndexWithContext>(
+    world: &mut World,
+    ranges: &[(Range<f32>, Layer)],
+) -> usize {
+    let assignments = world
+        .query_filtered::<(Entity, &Transform), (Without<Layer>, Without<Parent>)>()
+        .iter(world)
+        .filter_map(|(entity, transform)| {
+            ranges
+                .iter()
+                .find(|(range, _)| range.contains(&transform.translation.z))
+                .map(|(_, layer)| (entity, layer.clone()))
+        })
+        .collect::<Vec<_>>();
+    let count = assignments.len();
+    for (entity, layer) in assignments {
+        world.entity_mut(entity).insert(layer);
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixture::Layer;
+
+    #[test]
+    fn assigns_by_range() {
+        let mut world = World::new();
+        let back = world.spawn(Transform::from_xyz(0.0, 0.0, 1.0)).id();
+        let front = world.spawn(Transform::from_xyz(0.0, 0.0, 5.0)).id();
+        let out_of_range = world.spawn(Transform::from_xyz(0.0, 0.0, 50.0)).id();
+        let explicit = world
+            .spawn((Transform::from_xyz(0.0, 0.0, 1.0), Layer::Front))
+            .id();
+        let child = world.spawn(Transform::from_xyz(0.0, 0.0, 1.0)).id();
+        world.entity_mut(back).add_child(child);
+
+        let count = assign_layers_from_z(
+            &mut world,
+            &[(0.0..2.0, Layer::Back), (2.0..10.0, Layer::Front)],
+        );
+        assert_eq!(count, 2);
+        assert_eq!(world.get::<Layer>(back), Some(&Layer::Back));
+        assert_eq!(world.get::<Layer>(front), Some(&Layer::Front));
+        assert_eq!(world.get::<Layer>(out_of_range), None);
+        assert_eq!(world.get::<Layer>(explicit), Some(&Layer::Front));
+        assert_eq!(world.get::<Layer>(child), None);
+    }
+}
@@ -0,0 +1,107 @@
+//! An on-screen panel showing how many entities are on each layer. Only available with the
+//! `occupancy-overlay` feature.
+use std::marker::PhantomData;
+
+use bevy::prelude::*;
+
+use crate::{LayerIndexWithContext, PropagatedLayers, SpriteLayerSet};
+
+/// Shows a small table in the corner of the screen with every layer that has entities on it and
+/// how many it has, from the front layer down, updated every frame. Handy for finding out which
+/// layers are hot when profiling.
+///
+/// This needs `bevy_ui` to be set up (it's part of `DefaultPlugins`), and only counts entities
+/// managed by the [`SpriteLayerPlugin`](crate::SpriteLayerPlugin) for `Layer`.
+pub struct LayerOccupancyPlugin<Layer> {
+    phantom: PhantomData<Layer>,
+}
+
+impl<Layer> Default for LayerOccupancyPlugin<Layer> {
+    fn default() -> Self {
+        Self {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Layer: LayerIndexWithContext> Plugin for LayerOccupancyPlugin<Layer> {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, spawn_occupancy_text::<Layer>)
+            .add_systems(
+                Last,
+                update_occupancy_text::<Layer>.after(SpriteLayerSet::SetZCoordinates),
+            );
+    }
+}
+
+/// Marks the text that [`LayerOccupancyPlugin`] writes the table into.
+#[derive(Component)]
+pub struct LayerOccupancyText<Layer> {
+    phantom: PhantomData<Layer>,
+}
+
+fn spawn_occupancy_text<Layer: LayerIndexWithContext>(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 16.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(4.0),
+            left: Val::Px(4.0),
+            ..default()
+        })
+        .with_background_color(Color::BLACK.with_alpha(0.5)),
+        LayerOccupancyText::<Layer> {
+            phantom: PhantomData,
+        },
+    ));
+}
+
+fn update_occupancy_text<Layer: LayerIndexWithContext>(
+    layers: Res<PropagatedLayers<Layer>>,
+    context: Res<Layer::Context>,
+    mut query: Query<&mut Text, With<LayerOccupancyText<Layer>>>,
+) {
+    let mut counts = layers.layer_counts().collect::<Vec<_>>();
+    counts.sort_by(|(a, _), (b, _)| {
+        b.z_coordinate_in(&context)
+            .total_cmp(&a.z_coordinate_in(&context))
+    });
+    let table = counts
+        .iter()
+        .map(|(layer, count)| format!("{}: {count}", layer.display_name_in(&context)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    for mut text in &mut query {
+        // avoid triggering a relayout every frame
+        if text.sections[0].value != table {
+            text.sections[0].value.clone_from(&table);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixture::{test_app, Layer};
+
+    #[test]
+    fn counts_entities_per_layer() {
+        let mut app = test_app();
+        app.add_plugins(LayerOccupancyPlugin::<Layer>::default());
+        for layer in [Layer::Back, Layer::Front, Layer::Back] {
+            app.world_mut().spawn((TransformBundle::default(), layer));
+        }
+        app.update();
+        let text = app
+            .world_mut()
+            .query_filtered::<&Text, With<LayerOccupancyText<Layer>>>()
+            .single(app.world());
+        assert_eq!(text.sections[0].value, "Front: 1\nbackground: 2");
+    }
+}
@@ -0,0 +1,158 @@
+//! Live diagnostics for how close the sort is to running out of precision.
+use std::marker::PhantomData;
+
+use bevy::prelude::*;
+
+use crate::{
+    max_entities_for_precision, LayerIndexWithContext, PropagatedLayers, SpriteLayerOptions,
+    SpriteLayerSettings,
+};
+
+/// How much precision the [`SpriteLayerPlugin`](crate::SpriteLayerPlugin) for `Layer` has to
+/// spare, as of the last frame. Values below `1.0` mean entities that should be drawn in some
+/// order may tie instead. Only updated if the plugin was built with
+/// [`SpriteLayerPlugin::with_precision_diagnostics`](crate::SpriteLayerPlugin::with_precision_diagnostics).
+#[derive(Debug, Resource)]
+pub struct PrecisionHeadroom<Layer> {
+    /// The smallest ratio, over every layer, of [`max_entities_for_precision`] to the number of
+    /// entities on that layer. Infinite if there are no managed entities.
+    pub entities: f32,
+    /// How many distinct sort keys [`SpriteLayerOptions::fixed_point_scale`] gives each pixel of
+    /// the most zoomed-in active orthographic camera, i.e. the camera's world units per pixel
+    /// times the scale. Below `1.0`, entities less than a pixel apart can tie. `None` if there's
+    /// no fixed-point scale or no such camera.
+    pub y_resolution: Option<f32>,
+    phantom: PhantomData<Layer>,
+}
+
+impl<Layer> Default for PrecisionHeadroom<Layer> {
+    fn default() -> Self {
+        Self {
+            entities: f32::INFINITY,
+            y_resolution: None,
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// Updates [`PrecisionHeadroom`], logging a warning whenever either kind of headroom drops
+/// below `1.0`.
+pub fn update_precision_headroom<Layer: LayerIndexWithContext>(
+    layers: Res<PropagatedLayers<Layer>>,
+    context: Res<Layer::Context>,
+    settings: Res<SpriteLayerSettings<Layer>>,
+    options: Res<SpriteLayerOptions>,
+    #[cfg(feature = "bevy_render")] cameras: Query<(&Camera, &OrthographicProjection)>,
+    mut headroom: ResMut<PrecisionHeadroom<Layer>>,
+) {
+    let entities = layers
+        .layer_counts()
+        .map(|(layer, count)| {
+            let z = layer.z_coordinate_in(&context) + settings.z_base();
+            max_entities_for_precision(z) as f32 / count as f32
+        })
+        .fold(f32::INFINITY, f32::min);
+
+    #[cfg(feature = "bevy_render")]
+    let units_per_pixel = cameras
+        .iter()
+        .filter(|(camera, _)| camera.is_active)
+        .map(
+            |(camera, projection)| match camera.logical_viewport_size() {
+                Some(size) if size.y > 0.0 => projection.area.height() / size.y,
+                // Before the viewport is known, assume the default scaling mode.
+                _ => projection.scale,
+            },
+        )
+        .reduce(f32::min);
+    #[cfg(not(feature = "bevy_render"))]
+    let units_per_pixel: Option<f32> = None;
+    let y_resolution = options
+        .fixed_point_scale
+        .zip(units_per_pixel)
+        .map(|(scale, units_per_pixel)| scale * units_per_pixel);
+
+    if entities < 1.0 && headroom.entities >= 1.0 {
+        warn!(
+            "a sprite layer has more entities than its z-coordinates can distinguish; some will \
+             tie (headroom {entities})"
+        );
+    }
+    if let Some(y_resolution) = y_resolution {
+        if y_resolution < 1.0 && headroom.y_resolution.filter(|old| *old < 1.0).is_none() {
+            warn!(
+                "SpriteLayerOptions::fixed_point_scale is too coarse for the camera's zoom; \
+                 entities less than {} pixels apart may tie",
+                1.0 / y_resolution
+            );
+        }
+    }
+    headroom.entities = entities;
+    headroom.y_resolution = y_resolution;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LayerIndex, SpriteLayerPlugin};
+
+    #[derive(Debug, Copy, Clone, Component, PartialEq, Eq, Hash)]
+    struct Layer;
+
+    impl LayerIndex for Layer {
+        fn as_z_coordinate(&self) -> f32 {
+            990.0
+        }
+    }
+
+    fn test_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(TransformPlugin)
+            .add_plugins(SpriteLayerPlugin::<Layer>::default().with_precision_diagnostics());
+        app
+    }
+
+    #[test]
+    fn entity_headroom() {
+        let mut app = test_app();
+        for _ in 0..4 {
+            app.world_mut().spawn((TransformBundle::default(), Layer));
+        }
+        app.update();
+        let headroom = app.world().resource::<PrecisionHeadroom<Layer>>();
+        assert_eq!(headroom.entities, (1 << 14) as f32 / 4.0);
+        assert_eq!(headroom.y_resolution, None);
+    }
+
+    #[cfg(feature = "bevy_render")]
+    #[test]
+    fn y_resolution_follows_zoom() {
+        let mut app = test_app();
+        app.insert_resource(SpriteLayerOptions {
+            fixed_point_scale: Some(4.0),
+            ..default()
+        });
+        let camera = app
+            .world_mut()
+            .spawn((
+                Camera::default(),
+                OrthographicProjection {
+                    scale: 0.5,
+                    ..default()
+                },
+            ))
+            .id();
+        app.update();
+        let headroom = app.world().resource::<PrecisionHeadroom<Layer>>();
+        assert_eq!(headroom.y_resolution, Some(2.0));
+
+        app.world_mut()
+            .get_mut::<OrthographicProjection>(camera)
+            .unwrap()
+            .scale = 0.125;
+        app.update();
+        let headroom = app.world().resource::<PrecisionHeadroom<Layer>>();
+        assert_eq!(headroom.y_resolution, Some(0.5));
+    }
+}
@@ -0,0 +1,327 @@
+//! Propagating layers incrementally with observers, instead of walking every layered hierarchy
+//! each frame. See
+//! [`SpriteLayerPlugin::with_reactive_propagation`](crate::SpriteLayerPlugin::with_reactive_propagation).
+use std::marker::PhantomData;
+
+use bevy::ecs::entity::{EntityHashMap, EntityHashSet};
+use bevy::ecs::system::RunSystemOnce;
+use bevy::hierarchy::HierarchyEvent;
+use bevy::prelude::*;
+#[cfg(feature = "bevy_render")]
+use bevy::render::view::RenderLayers;
+use bevy::utils::HashMap;
+
+#[cfg(feature = "bevy_render")]
+use crate::{retain_render_layers, SpriteLayerSettings};
+use crate::{self_and_ancestors, LayerIndexWithContext, LayerMap, SceneDepthOffset};
+
+/// The entity whose `Layer` this entity has: itself, if it has its own, or else its nearest
+/// ancestor that does. Only maintained if the plugin was built with
+/// [`SpriteLayerPlugin::with_reactive_propagation`](crate::SpriteLayerPlugin::with_reactive_propagation),
+/// in which case every entity that has a layer (its own or inherited) has one of these by the
+/// time the plugin runs.
+#[derive(Debug, Clone, PartialEq, Eq, Component)]
+pub struct InheritedLayer<Layer> {
+    source: Entity,
+    phantom: PhantomData<Layer>,
+}
+
+impl<Layer> InheritedLayer<Layer> {
+    /// The entity this entity's layer comes from.
+    pub fn source(&self) -> Entity {
+        self.source
+    }
+}
+
+/// The parts of the hierarchy [`update_subtree`] looks at.
+type HierarchyQuery<'w, 's, Layer> = Query<
+    'w,
+    's,
+    (
+        Option<&'static Parent>,
+        Option<&'static Children>,
+        Has<Layer>,
+    ),
+>;
+
+/// Registers the observers that keep [`InheritedLayer`]s up to date, and gives the entities that
+/// already exist theirs. Moving an entity to a new parent doesn't trigger any observers, so those
+/// are handled by [`update_moved_subtrees`] instead.
+pub(crate) fn add_observers<Layer: LayerIndexWithContext>(app: &mut App) {
+    app.add_event::<HierarchyEvent>()
+        .observe(on_add_layer::<Layer>)
+        .observe(on_remove_layer::<Layer>)
+        .observe(on_insert_parent::<Layer>)
+        .observe(on_remove_parent::<Layer>);
+    app.world_mut()
+        .run_system_once(seed_inherited_layers::<Layer>);
+}
+
+fn on_add_layer<Layer: LayerIndexWithContext>(
+    trigger: Trigger<OnAdd, Layer>,
+    hierarchy: HierarchyQuery<Layer>,
+    mut commands: Commands,
+) {
+    update_subtree(trigger.entity(), None, None, &hierarchy, &mut commands);
+}
+
+/// Runs while the entity still has its layer, so it's told to ignore it.
+fn on_remove_layer<Layer: LayerIndexWithContext>(
+    trigger: Trigger<OnRemove, Layer>,
+    hierarchy: HierarchyQuery<Layer>,
+    mut commands: Commands,
+) {
+    let entity = trigger.entity();
+    update_subtree(entity, Some(entity), None, &hierarchy, &mut commands);
+}
+
+fn on_insert_parent<Layer: LayerIndexWithContext>(
+    trigger: Trigger<OnInsert, Parent>,
+    hierarchy: HierarchyQuery<Layer>,
+    mut commands: Commands,
+) {
+    update_subtree(trigger.entity(), None, None, &hierarchy, &mut commands);
+}
+
+/// Runs while the entity still has its parent, so it's told to ignore it.
+fn on_remove_parent<Layer: LayerIndexWithContext>(
+    trigger: Trigger<OnRemove, Parent>,
+    hierarchy: HierarchyQuery<Layer>,
+    mut commands: Commands,
+) {
+    let entity = trigger.entity();
+    update_subtree(entity, None, Some(entity), &hierarchy, &mut commands);
+}
+
+/// Updates the [`InheritedLayer`]s of entities that were moved to a new parent since the last
+/// frame. Runs right before [`collect_inherited_layers`].
+pub fn update_moved_subtrees<Layer: LayerIndexWithContext>(
+    mut events: EventReader<HierarchyEvent>,
+    hierarchy: HierarchyQuery<Layer>,
+    mut commands: Commands,
+) {
+    for event in events.read() {
+        if let HierarchyEvent::ChildMoved { child, .. } = event {
+            update_subtree(*child, None, None, &hierarchy, &mut commands);
+        }
+    }
+}
+
+/// Gives every entity that was spawned before the observers were registered its
+/// [`InheritedLayer`].
+fn seed_inherited_layers<Layer: LayerIndexWithContext>(
+    layered: Query<Entity, With<Layer>>,
+    hierarchy: HierarchyQuery<Layer>,
+    mut commands: Commands,
+) {
+    for entity in &layered {
+        update_subtree(entity, None, None, &hierarchy, &mut commands);
+    }
+}
+
+/// Recomputes the [`InheritedLayer`] of `root` and of the descendants that inherit the same layer
+/// as it. Observers for removals run before the component is actually removed, so
+/// `without_layer` and `without_parent` are treated as if they didn't have a layer or a parent.
+fn update_subtree<Layer: LayerIndexWithContext>(
+    root: Entity,
+    without_layer: Option<Entity>,
+    without_parent: Option<Entity>,
+    hierarchy: &HierarchyQuery<Layer>,
+    commands: &mut Commands,
+) {
+    let has_layer = |entity: Entity| {
+        Some(entity) != without_layer
+            && hierarchy
+                .get(entity)
+                .is_ok_and(|(_, _, has_layer)| has_layer)
+    };
+    let parent_of = |entity: Entity| {
+        if Some(entity) == without_parent {
+            return None;
+        }
+        hierarchy.get(entity).ok()?.0.map(Parent::get)
+    };
+    let source = self_and_ancestors(root, parent_of).find(|entity| has_layer(*entity));
+
+    let mut stack = vec![root];
+    // Guards against cycles, like `self_and_ancestors` does.
+    let mut visited = EntityHashSet::default();
+    while let Some(entity) = stack.pop() {
+        if !visited.insert(entity) {
+            continue;
+        }
+        // Layered descendants are the source of their own subtrees, which haven't changed.
+        if entity != root && has_layer(entity) {
+            continue;
+        }
+        if let Some(mut entity_commands) = commands.get_entity(entity) {
+            match source {
+                Some(source) => {
+                    entity_commands.try_insert(InheritedLayer::<Layer> {
+                        source,
+                        phantom: PhantomData,
+                    });
+                }
+                None => {
+                    entity_commands.remove::<InheritedLayer<Layer>>();
+                }
+            }
+        }
+        if let Ok((_, Some(children), _)) = hierarchy.get(entity) {
+            stack.extend(children.iter().copied());
+        }
+    }
+}
+
+/// Collects the layer of every entity from its [`InheritedLayer`]. Used in place of
+/// [`propagate_layers`](crate::propagate_layers) if the plugin was built with
+/// [`SpriteLayerPlugin::with_reactive_propagation`](crate::SpriteLayerPlugin::with_reactive_propagation),
+/// and gives the same result, except that
+/// [`SpriteLayerOptions::max_hierarchy_depth`](crate::SpriteLayerOptions::max_hierarchy_depth)
+/// isn't enforced.
+pub fn collect_inherited_layers<Layer: LayerIndexWithContext>(
+    query: Query<(Entity, &InheritedLayer<Layer>)>,
+    layers: Query<&Layer>,
+    parents: Query<&Parent>,
+    scene_offset_query: Query<&SceneDepthOffset>,
+    #[cfg(feature = "bevy_render")] settings: Res<SpriteLayerSettings<Layer>>,
+    #[cfg(feature = "bevy_render")] render_layers_query: Query<&RenderLayers>,
+) -> LayerMap<Layer> {
+    let mut layer_map = LayerMap::default();
+    layer_map.entities.reserve(query.iter().len());
+    let mut indices = HashMap::<&Layer, usize>::default();
+    let mut source_indices = EntityHashMap::<Option<usize>>::default();
+    let parent_of = |entity| parents.get(entity).ok().map(Parent::get);
+    for (entity, inherited) in &query {
+        let index = *source_indices.entry(inherited.source).or_insert_with(|| {
+            let layer = layers.get(inherited.source).ok()?;
+            Some(*indices.entry(layer).or_insert_with(|| {
+                layer_map.distinct.push(layer.clone());
+                layer_map.distinct.len() - 1
+            }))
+        });
+        // The source can lose its layer after this entity's `InheritedLayer` was last updated
+        // if the source was despawned along with it.
+        let Some(index) = index else {
+            continue;
+        };
+        layer_map.entities.insert(entity, index);
+        if !scene_offset_query.is_empty() {
+            let offset = self_and_ancestors(entity, parent_of)
+                .filter_map(|entity| scene_offset_query.get(entity).ok())
+                .map(|offset| offset.0)
+                .sum::<f32>();
+            if offset != 0.0 {
+                layer_map.scene_offsets.insert(entity, offset);
+            }
+        }
+    }
+    #[cfg(feature = "bevy_render")]
+    retain_render_layers(&mut layer_map, &settings, &render_layers_query);
+    layer_map
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::hierarchy::despawn_with_children_recursive;
+    use bevy::prelude::*;
+    use bevy::utils::HashMap;
+
+    use super::*;
+    use crate::{propagate_layers, LayerIndex, SpriteLayerPlugin};
+
+    #[derive(Debug, Copy, Clone, Component, PartialEq, Eq, Hash)]
+    struct Layer(u8);
+
+    impl LayerIndex for Layer {
+        fn as_z_coordinate(&self) -> f32 {
+            self.0 as f32
+        }
+    }
+
+    /// Each entity's layer and scene offset, as computed by `system`.
+    fn layers_from<M>(
+        world: &mut World,
+        system: impl IntoSystem<(), LayerMap<Layer>, M>,
+    ) -> HashMap<Entity, (Layer, f32)> {
+        let layer_map = world.run_system_once(system);
+        layer_map
+            .iter()
+            .map(|(entity, layer)| (entity, (*layer, layer_map.scene_offset(&entity))))
+            .collect()
+    }
+
+    #[test]
+    fn matches_full_propagation() {
+        let mut app = App::new();
+        // spawned before the plugin is added, to check that existing hierarchies are picked up
+        let root = app.world_mut().spawn(Layer(0)).id();
+        let child = app.world_mut().spawn_empty().set_parent(root).id();
+        let grandchild = app.world_mut().spawn_empty().set_parent(child).id();
+        app.add_plugins(SpriteLayerPlugin::<Layer>::default().with_reactive_propagation());
+
+        let mut rng = fastrand::Rng::with_seed(7);
+        let mut entities = vec![root, child, grandchild];
+        for step in 0..2000 {
+            let world = app.world_mut();
+            let pick = |rng: &mut fastrand::Rng, entities: &[Entity]| {
+                entities[rng.usize(..entities.len())]
+            };
+            match rng.u8(0..8) {
+                0 | 1 => {
+                    let mut entity = world.spawn_empty();
+                    if rng.bool() {
+                        entity.insert(Layer(rng.u8(0..4)));
+                    }
+                    let entity = entity.id();
+                    if rng.bool() && !entities.is_empty() {
+                        let parent = pick(&mut rng, &entities);
+                        world.entity_mut(parent).add_child(entity);
+                    }
+                    entities.push(entity);
+                }
+                _ if entities.is_empty() => continue,
+                2 => {
+                    let entity = pick(&mut rng, &entities);
+                    world.entity_mut(entity).insert(Layer(rng.u8(0..4)));
+                }
+                3 => {
+                    let entity = pick(&mut rng, &entities);
+                    world.entity_mut(entity).remove::<Layer>();
+                }
+                4 => {
+                    let (child, parent) = (pick(&mut rng, &entities), pick(&mut rng, &entities));
+                    let parent_of = |entity| world.get::<Parent>(entity).map(Parent::get);
+                    // don't create cycles
+                    if !self_and_ancestors(parent, parent_of).any(|entity| entity == child) {
+                        world.entity_mut(child).set_parent(parent);
+                    }
+                }
+                5 => {
+                    let entity = pick(&mut rng, &entities);
+                    world.entity_mut(entity).remove_parent();
+                }
+                6 => {
+                    let entity = pick(&mut rng, &entities);
+                    world
+                        .entity_mut(entity)
+                        .insert(SceneDepthOffset(rng.u8(0..3) as f32));
+                }
+                _ => {
+                    let entity = pick(&mut rng, &entities);
+                    despawn_with_children_recursive(world, entity);
+                    entities.retain(|entity| world.get_entity(*entity).is_some());
+                }
+            }
+            // moves are only picked up once a frame
+            app.update();
+            let world = app.world_mut();
+            assert_eq!(
+                layers_from(world, collect_inherited_layers::<Layer>),
+                layers_from(world, propagate_layers::<Layer>),
+                "after step {step}"
+            );
+        }
+    }
+}
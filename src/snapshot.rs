@@ -0,0 +1,126 @@
+//! Publishing each frame's sort results for code that runs outside of the schedule.
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+
+use crate::{LayerIndexWithContext, PropagatedLayers, RenderZCoordinate};
+
+/// A complete copy of one frame's sort results. See [`SortSnapshots`].
+#[derive(Debug)]
+pub struct SortSnapshot<Layer> {
+    /// How many times the snapshot has been updated before this one.
+    pub frame: u64,
+    /// Every managed entity and its z-coordinate, from back to front.
+    pub order: Vec<(Entity, f32)>,
+    /// Every layer with entities on it, and how many, in arbitrary order.
+    pub layer_counts: Vec<(Layer, usize)>,
+}
+
+impl<Layer> Default for SortSnapshot<Layer> {
+    fn default() -> Self {
+        Self {
+            frame: 0,
+            order: Vec::new(),
+            layer_counts: Vec::new(),
+        }
+    }
+}
+
+/// A handle to the latest [`SortSnapshot`] for `Layer`, for tools that run outside of the
+/// schedule (say, an inspector on another thread). Only present if the plugin was built with
+/// [`SpriteLayerPlugin::with_snapshots`](crate::SpriteLayerPlugin::with_snapshots).
+///
+/// Clone it out of the world once and call [`latest`](Self::latest) whenever you like. Each frame
+/// the plugin builds a whole new snapshot and then swaps it in, so you always get a complete
+/// frame's data, and holding on to a snapshot never blocks the plugin; the lock is only held for
+/// long enough to swap or clone a pointer.
+#[derive(Debug, Resource)]
+pub struct SortSnapshots<Layer> {
+    latest: Arc<Mutex<Arc<SortSnapshot<Layer>>>>,
+}
+
+impl<Layer> Clone for SortSnapshots<Layer> {
+    fn clone(&self) -> Self {
+        Self {
+            latest: self.latest.clone(),
+        }
+    }
+}
+
+impl<Layer> Default for SortSnapshots<Layer> {
+    fn default() -> Self {
+        Self {
+            latest: Default::default(),
+        }
+    }
+}
+
+impl<Layer> SortSnapshots<Layer> {
+    /// The most recently published snapshot.
+    pub fn latest(&self) -> Arc<SortSnapshot<Layer>> {
+        self.latest.lock().unwrap().clone()
+    }
+
+    fn publish(&self, snapshot: SortSnapshot<Layer>) {
+        *self.latest.lock().unwrap() = Arc::new(snapshot);
+    }
+}
+
+/// Publishes a new [`SortSnapshot`] to [`SortSnapshots`].
+pub fn publish_sort_snapshot<Layer: LayerIndexWithContext>(
+    layers: Res<PropagatedLayers<Layer>>,
+    z_query: Query<&RenderZCoordinate>,
+    snapshots: Res<SortSnapshots<Layer>>,
+    mut frame: Local<u64>,
+) {
+    let mut order = layers
+        .iter()
+        .filter_map(|(entity, _)| Some((entity, z_query.get(entity).ok()?.0)))
+        .collect::<Vec<_>>();
+    order.sort_by(|(a_entity, a_z), (b_entity, b_z)| {
+        a_z.total_cmp(b_z).then(a_entity.cmp(b_entity))
+    });
+    snapshots.publish(SortSnapshot {
+        frame: *frame,
+        order,
+        layer_counts: layers
+            .layer_counts()
+            .map(|(layer, count)| (layer.clone(), count))
+            .collect(),
+    });
+    *frame += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixture::{app_with, Layer};
+    use crate::SpriteLayerPlugin;
+
+    #[test]
+    fn snapshots_are_complete_frames() {
+        let mut app = app_with(SpriteLayerPlugin::default().with_snapshots());
+        let snapshots = app.world().resource::<SortSnapshots<Layer>>().clone();
+        let front = app
+            .world_mut()
+            .spawn((TransformBundle::default(), Layer::Front))
+            .id();
+        let back = app
+            .world_mut()
+            .spawn((TransformBundle::default(), Layer::Back))
+            .id();
+        app.update();
+        let first = snapshots.latest();
+        assert_eq!(first.frame, 0);
+        assert_eq!(first.order, [(back, 0.0), (front, 1.0)]);
+
+        app.world_mut().despawn(back);
+        app.update();
+        let second = snapshots.latest();
+        assert_eq!(second.frame, 1);
+        assert_eq!(second.order, [(front, 1.0)]);
+        assert_eq!(second.layer_counts, [(Layer::Front, 1)]);
+        // older snapshots are untouched
+        assert_eq!(first.order.len(), 2);
+    }
+}
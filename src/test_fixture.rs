@@ -0,0 +1,42 @@
+//! A layer type and app shared by the tests of the smaller modules.
+use std::borrow::Cow;
+
+use bevy::prelude::*;
+
+use crate::{LayerIndex, SpriteLayerPlugin};
+
+#[derive(Debug, Copy, Clone, Component, PartialEq, Eq, Hash)]
+pub(crate) enum Layer {
+    Back,
+    Front,
+}
+
+impl LayerIndex for Layer {
+    fn as_z_coordinate(&self) -> f32 {
+        match self {
+            Layer::Back => 0.0,
+            Layer::Front => 1.0,
+        }
+    }
+
+    fn display_name(&self) -> Cow<'_, str> {
+        match self {
+            Layer::Back => "background".into(),
+            Layer::Front => format!("{self:?}").into(),
+        }
+    }
+}
+
+/// An app with the transform plugin and the given plugin for [`Layer`].
+pub(crate) fn app_with(plugin: SpriteLayerPlugin<Layer>) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugins(TransformPlugin)
+        .add_plugins(plugin);
+    app
+}
+
+/// An app with the transform plugin and a default plugin for [`Layer`].
+pub(crate) fn test_app() -> App {
+    app_with(SpriteLayerPlugin::default())
+}
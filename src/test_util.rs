@@ -0,0 +1,503 @@
+//! Utilities for testing code that uses sprite layers. Only available with the `test-util`
+//! feature.
+use std::marker::PhantomData;
+
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::{
+    ApplyTo, LayerIndexWithContext, LockedSortIndex, PropagatedLayers, RenderZCoordinate,
+    SortGroup, SortPosition, SpriteLayerOptions, SpriteLayerSet, TopmostOverlay,
+};
+
+/// Which invariants [`SpriteLayerTestPlugin`] and [`z_ordering_violations`] check.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ZOrderingChecks {
+    /// Check that, within each layer, entities further up are drawn further back. Only checked if
+    /// y-sorting is enabled; entities in a [`SortGroup`] or with a [`LockedSortIndex`] are
+    /// skipped, since they're not sorted by their own position.
+    pub y_sort: bool,
+    /// Allow entities on the same layer to have exactly the same z-coordinate. It's always an
+    /// error for entities on *different* layers to be out of order or tied.
+    pub allow_ties: bool,
+}
+
+impl Default for ZOrderingChecks {
+    fn default() -> Self {
+        Self {
+            y_sort: true,
+            allow_ties: false,
+        }
+    }
+}
+
+/// Checks the z-coordinates assigned by the [`SpriteLayerPlugin`](crate::SpriteLayerPlugin) for
+/// `Layer` after every update, and panics if any of the invariants in [`ZOrderingChecks`] are
+/// violated. Add this to your integration tests' apps to catch layering regressions.
+pub struct SpriteLayerTestPlugin<Layer> {
+    checks: ZOrderingChecks,
+    phantom: PhantomData<Layer>,
+}
+
+impl<Layer> Default for SpriteLayerTestPlugin<Layer> {
+    fn default() -> Self {
+        Self::new(ZOrderingChecks::default())
+    }
+}
+
+impl<Layer> SpriteLayerTestPlugin<Layer> {
+    pub fn new(checks: ZOrderingChecks) -> Self {
+        Self {
+            checks,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Layer: LayerIndexWithContext> Plugin for SpriteLayerTestPlugin<Layer> {
+    fn build(&self, app: &mut App) {
+        let checks = self.checks;
+        app.add_systems(
+            Last,
+            (move |world: &mut World| {
+                let violations = z_ordering_violations::<Layer>(world, checks);
+                assert!(
+                    violations.is_empty(),
+                    "sprite layer invariants violated:\n{}",
+                    violations.join("\n")
+                );
+            })
+            .after(SpriteLayerSet::SetZCoordinates),
+        );
+    }
+}
+
+/// One managed entity's data, as far as the checks are concerned.
+struct Entry<Layer> {
+    entity: Entity,
+    layer: Layer,
+    /// The entity's [`SceneDepthOffset`](crate::SceneDepthOffset), including its ancestors'.
+    scene_offset: f32,
+    z: f32,
+    /// `None` if we shouldn't check this entity's y-sorting.
+    sort_y: Option<f32>,
+}
+
+/// Returns a description of every way the current z-coordinates of the entities managed by the
+/// plugin for `Layer` violate the given checks. Entities with a [`TopmostOverlay`] are ignored.
+pub fn z_ordering_violations<Layer: LayerIndexWithContext>(
+    world: &mut World,
+    checks: ZOrderingChecks,
+) -> Vec<String> {
+    let Some(layers) = world.get_resource::<PropagatedLayers<Layer>>() else {
+        return vec![];
+    };
+    let options = world.resource::<SpriteLayerOptions>();
+    let check_y_sort = cfg!(feature = "y_sort") && checks.y_sort && options.y_sort;
+    let anchor = options.sort_anchor_local;
+    let context = world.resource::<Layer::Context>();
+    let mut entries = layers
+        .iter()
+        .filter_map(|(entity, layer)| {
+            let entity_ref = world.get_entity(entity)?;
+            if entity_ref.contains::<TopmostOverlay>() {
+                return None;
+            }
+            let z = entity_ref.get::<RenderZCoordinate>()?.0;
+            let sort_y = if !check_y_sort
+                || entity_ref.contains::<SortGroup>()
+                || entity_ref.contains::<LockedSortIndex>()
+            {
+                None
+            } else if let Some(position) = entity_ref.get::<SortPosition>() {
+                Some(position.0)
+            } else {
+                entity_ref
+                    .get::<GlobalTransform>()
+                    .map(|transform| transform.transform_point(anchor).y)
+            };
+            Some(Entry {
+                entity,
+                layer: layer.clone(),
+                scene_offset: layers.scene_offset(entity),
+                z,
+                // match how the plugin sorts NaN
+                sort_y: sort_y.map(|y| if y.is_nan() { f32::INFINITY } else { y }),
+            })
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.z.total_cmp(&b.z));
+
+    let mut violations = vec![];
+    for pair in entries.windows(2) {
+        let [back, front] = pair else { unreachable!() };
+        let (back_base, front_base) = (
+            back.layer.z_coordinate_in(context) + back.scene_offset,
+            front.layer.z_coordinate_in(context) + front.scene_offset,
+        );
+        if back_base > front_base || (back_base != front_base && back.z == front.z) {
+            violations.push(format!(
+                "{:?} on layer {:?} (z = {}) is not behind {:?} on layer {:?} (z = {})",
+                back.entity, back.layer, back.z, front.entity, front.layer, front.z
+            ));
+        }
+    }
+
+    // Scenes with different offsets have separate copies of each layer.
+    let mut by_layer = HashMap::<(Layer, u32), Vec<&Entry<Layer>>>::default();
+    for entry in &entries {
+        by_layer
+            .entry((entry.layer.clone(), entry.scene_offset.to_bits()))
+            .or_default()
+            .push(entry);
+    }
+    for layer_entries in by_layer.values() {
+        for pair in layer_entries.windows(2) {
+            let [back, front] = pair else { unreachable!() };
+            if !checks.allow_ties && back.z == front.z {
+                violations.push(format!(
+                    "{:?} and {:?} on layer {:?} have the same z-coordinate {}",
+                    back.entity, front.entity, back.layer, back.z
+                ));
+            }
+        }
+        // entities we're not y-sort checking can be anywhere in the layer
+        let y_sorted = layer_entries
+            .iter()
+            .filter_map(|entry| Some((entry, entry.sort_y?)))
+            .collect::<Vec<_>>();
+        for pair in y_sorted.windows(2) {
+            let [(back, back_y), (front, front_y)] = pair else {
+                unreachable!()
+            };
+            if back_y < front_y {
+                violations.push(format!(
+                    "{:?} (y = {back_y}, z = {}) is behind {:?} (y = {front_y}, z = {}) on layer {:?}",
+                    back.entity, back.z, front.entity, front.z, back.layer
+                ));
+            }
+        }
+    }
+    violations
+}
+
+/// A layer type with a conceptual back-to-front order, which its z-coordinates should agree
+/// with. This is separate from [`LayerIndexWithContext`] so that layer types don't need to be
+/// ordered; it's only used by [`layer_order_violations`].
+///
+/// For a layer type that's already [`Ord`], use the type itself as the rank:
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use extol_sprite_layer::LayerIndex;
+/// # use extol_sprite_layer::test_util::RankedLayer;
+/// #[derive(Debug, Clone, Component, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// enum Layer {
+///     Background,
+///     Foreground,
+/// }
+/// # impl LayerIndex for Layer { fn as_z_coordinate(&self) -> f32 { 0.0 } }
+///
+/// impl RankedLayer for Layer {
+///     type Rank = Self;
+///
+///     fn layer_rank(&self) -> Self {
+///         self.clone()
+///     }
+/// }
+/// ```
+pub trait RankedLayer: LayerIndexWithContext {
+    type Rank: Ord;
+
+    /// Where this layer conceptually is. Layers with a lower rank should be further back.
+    fn layer_rank(&self) -> Self::Rank;
+}
+
+/// Returns a description of every pair of the given layers where the one with the lower
+/// [`RankedLayer::layer_rank`] has a higher z-coordinate than the other, which usually means a
+/// z-coordinate constant is wrong. Layers with equal ranks can be in any order.
+pub fn layer_order_violations<Layer: RankedLayer>(
+    layers: &[Layer],
+    context: &Layer::Context,
+) -> Vec<String> {
+    let mut ranked = layers
+        .iter()
+        .map(|layer| (layer.layer_rank(), layer.z_coordinate_in(context), layer))
+        .collect::<Vec<_>>();
+    ranked.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+    let mut violations = vec![];
+    for (i, (back_rank, back_z, back)) in ranked.iter().enumerate() {
+        for (front_rank, front_z, front) in &ranked[i + 1..] {
+            if back_rank < front_rank && back_z > front_z {
+                violations.push(format!(
+                    "layer {back:?} (z = {back_z}) is ranked behind layer {front:?} (z = {front_z})"
+                ));
+            }
+        }
+    }
+    violations
+}
+
+/// Panics unless `front` is drawn in front of `back`, i.e. has a strictly greater
+/// [`RenderZCoordinate`]. If the plugin is writing z-coordinates into [`GlobalTransform`]s (see
+/// [`SpriteLayerOptions::apply_to`]), their transforms are checked too.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use extol_sprite_layer::{LayerIndex, SpriteLayerPlugin};
+/// # use extol_sprite_layer::test_util::assert_drawn_in_front;
+/// # #[derive(Debug, Clone, Component, PartialEq, Eq, Hash)]
+/// # enum Layer { Background, Foreground }
+/// # impl LayerIndex for Layer {
+/// #     fn as_z_coordinate(&self) -> f32 { if *self == Layer::Background { 0.0 } else { 1.0 } }
+/// # }
+/// # let mut app = App::new();
+/// # app.add_plugins((MinimalPlugins, TransformPlugin, SpriteLayerPlugin::<Layer>::default()));
+/// let player = app.world_mut().spawn((TransformBundle::default(), Layer::Foreground)).id();
+/// let tree = app.world_mut().spawn((TransformBundle::default(), Layer::Background)).id();
+/// app.update();
+/// assert_drawn_in_front(app.world(), player, tree);
+/// ```
+#[track_caller]
+pub fn assert_drawn_in_front(world: &World, front: Entity, back: Entity) {
+    let render_z = |entity: Entity| match world.get::<RenderZCoordinate>(entity) {
+        Some(z) => z.0,
+        None => panic!("{entity:?} has no RenderZCoordinate; is it managed by a sprite layer?"),
+    };
+    let (front_z, back_z) = (render_z(front), render_z(back));
+    assert!(
+        front_z > back_z,
+        "expected {front:?} (z = {front_z}) to be drawn in front of {back:?} (z = {back_z})"
+    );
+
+    let writes_transforms = world
+        .get_resource::<SpriteLayerOptions>()
+        .is_some_and(|options| options.apply_to == ApplyTo::GlobalTransform);
+    let transform_z = |entity| {
+        world
+            .get::<GlobalTransform>(entity)
+            .map(|transform| transform.translation().z)
+    };
+    if let (true, Some(front_z), Some(back_z)) =
+        (writes_transforms, transform_z(front), transform_z(back))
+    {
+        assert!(
+            front_z > back_z,
+            "{front:?} is in front of {back:?} according to their RenderZCoordinates, but not \
+             their GlobalTransforms (z = {front_z} and {back_z})"
+        );
+    }
+}
+
+/// Runs `f` with `options` in place of the world's [`SpriteLayerOptions`], and puts the previous
+/// options back afterwards (or removes them, if there weren't any). Anything `f` does to the
+/// options is discarded. If `f` panics, the options aren't restored.
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use extol_sprite_layer::{LayerIndex, RenderZCoordinate, SpriteLayerOptions, SpriteLayerPlugin};
+/// # use extol_sprite_layer::test_util::with_sprite_layer_options;
+/// # #[derive(Debug, Clone, Component, PartialEq, Eq, Hash)]
+/// # struct Layer;
+/// # impl LayerIndex for Layer { fn as_z_coordinate(&self) -> f32 { 0.0 } }
+/// # let mut app = App::new();
+/// # app.add_plugins((MinimalPlugins, TransformPlugin, SpriteLayerPlugin::<Layer>::default()));
+/// let low = app.world_mut().spawn((TransformBundle::default(), Layer)).id();
+/// let high = app
+///     .world_mut()
+///     .spawn((TransformBundle::from_transform(Transform::from_xyz(0.0, 5.0, 0.0)), Layer))
+///     .id();
+/// let unsorted = SpriteLayerOptions { y_sort: false, ..default() };
+/// with_sprite_layer_options(app.world_mut(), unsorted, |world| {
+///     world.run_schedule(Main);
+///     let z = |entity| world.get::<RenderZCoordinate>(entity).unwrap().0;
+///     assert_eq!(z(low), z(high));
+/// });
+/// ```
+pub fn with_sprite_layer_options<R>(
+    world: &mut World,
+    options: SpriteLayerOptions,
+    f: impl FnOnce(&mut World) -> R,
+) -> R {
+    let previous = world.remove_resource::<SpriteLayerOptions>();
+    world.insert_resource(options);
+    let result = f(world);
+    match previous {
+        Some(previous) => world.insert_resource(previous),
+        None => {
+            world.remove_resource::<SpriteLayerOptions>();
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixture::Layer;
+    use crate::{LayerIndex, NoContext};
+
+    fn test_app() -> App {
+        let mut app = crate::test_fixture::test_app();
+        app.add_plugins(SpriteLayerTestPlugin::<Layer>::default());
+        for i in 0..10 {
+            let layer = if i % 2 == 0 {
+                Layer::Back
+            } else {
+                Layer::Front
+            };
+            app.world_mut().spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.0, i as f32, 0.0)),
+                layer,
+            ));
+        }
+        app
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn passes_for_plugin_output() {
+        let mut app = test_app();
+        app.update();
+        app.update();
+    }
+
+    #[test]
+    #[should_panic(expected = "sprite layer invariants violated")]
+    fn catches_misordered_layers() {
+        let mut app = test_app();
+        app.add_systems(
+            Last,
+            (|mut query: Query<(&Layer, &mut RenderZCoordinate)>| {
+                for (layer, mut z) in &mut query {
+                    if *layer == Layer::Back {
+                        z.0 += 5.0;
+                    }
+                }
+            })
+            .in_set(SpriteLayerSet::SetZCoordinates)
+            .after(crate::compute_render_z_coordinates::<Layer>),
+        );
+        app.update();
+    }
+
+    #[cfg(feature = "y_sort")]
+    fn spawn_pair(app: &mut App) -> (Entity, Entity) {
+        let front = app
+            .world_mut()
+            .spawn((TransformBundle::default(), Layer::Front))
+            .id();
+        let back = app
+            .world_mut()
+            .spawn((TransformBundle::default(), Layer::Back))
+            .id();
+        app.update();
+        (front, back)
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn drawn_in_front() {
+        let mut app = test_app();
+        let (front, back) = spawn_pair(&mut app);
+        assert_drawn_in_front(app.world(), front, back);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    #[should_panic(expected = "to be drawn in front of")]
+    fn drawn_in_front_catches_misordering() {
+        let mut app = test_app();
+        let (front, back) = spawn_pair(&mut app);
+        assert_drawn_in_front(app.world(), back, front);
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn scoped_options() {
+        let mut app = test_app();
+        let (front, back) = spawn_pair(&mut app);
+        let options = SpriteLayerOptions {
+            fixed_point_scale: Some(1.0),
+            ..default()
+        };
+        let scale = with_sprite_layer_options(app.world_mut(), options, |world| {
+            world.run_schedule(Main);
+            assert_drawn_in_front(world, front, back);
+            world.resource::<SpriteLayerOptions>().fixed_point_scale
+        });
+        assert_eq!(scale, Some(1.0));
+        assert_eq!(
+            app.world().resource::<SpriteLayerOptions>(),
+            &SpriteLayerOptions::default()
+        );
+
+        let mut world = World::new();
+        with_sprite_layer_options(&mut world, SpriteLayerOptions::default(), |_| {});
+        assert!(!world.contains_resource::<SpriteLayerOptions>());
+    }
+
+    #[test]
+    fn catches_misranked_layers() {
+        #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Component)]
+        enum Ranked {
+            Back,
+            Middle,
+            Front,
+        }
+
+        impl LayerIndex for Ranked {
+            fn as_z_coordinate(&self) -> f32 {
+                match self {
+                    Ranked::Back => 0.0,
+                    // oops
+                    Ranked::Middle => 5.0,
+                    Ranked::Front => 2.0,
+                }
+            }
+        }
+
+        impl RankedLayer for Ranked {
+            type Rank = Self;
+
+            fn layer_rank(&self) -> Self {
+                *self
+            }
+        }
+
+        let layers = [Ranked::Front, Ranked::Back, Ranked::Middle];
+        assert_eq!(
+            layer_order_violations(&layers, &NoContext),
+            ["layer Middle (z = 5) is ranked behind layer Front (z = 2)"]
+        );
+        assert_eq!(
+            layer_order_violations(&[Ranked::Back, Ranked::Front], &NoContext),
+            Vec::<String>::new()
+        );
+    }
+
+    #[cfg(feature = "y_sort")]
+    #[test]
+    fn catches_y_sort_violations() {
+        let mut app = test_app();
+        app.update();
+        let world = app.world_mut();
+        let mut query = world.query::<(&Layer, &mut RenderZCoordinate)>();
+        for (layer, mut z) in query.iter_mut(world) {
+            if *layer == Layer::Back {
+                // reverse the y-sort within the layer
+                z.0 = 0.9 - z.0;
+            }
+        }
+        let violations = z_ordering_violations::<Layer>(world, ZOrderingChecks::default());
+        assert!(!violations.is_empty());
+        let violations = z_ordering_violations::<Layer>(
+            world,
+            ZOrderingChecks {
+                y_sort: false,
+                ..default()
+            },
+        );
+        assert_eq!(violations, Vec::<String>::new());
+    }
+}
@@ -0,0 +1,555 @@
+//! Y-sorting: ordering the entities on each layer by their y-coordinates. Only available with the
+//! `y_sort` feature.
+use std::cmp::Reverse;
+
+use bevy::ecs::entity::{EntityHashMap, EntityHashSet};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+#[cfg(feature = "bevy_render")]
+use bevy::render::primitives::Aabb;
+use bevy::utils::HashMap;
+use tap::Tap;
+
+#[cfg(feature = "bevy_render")]
+use crate::aabb_bottom;
+#[cfg(feature = "async-sort")]
+use crate::AsyncSort;
+use crate::{
+    apply_locked_indices, quantize_y, self_and_ancestors, spread_within_layers,
+    FreezeInternalOrder, LayerIndexWithContext, LayerMap, OriginalZs, PreviousSortYs,
+    RenderZCoordinate, SortCamera, SortComparator, SortGroup, SortInputs, SortPosition, SortScale,
+    SpriteLayerOptions,
+};
+
+/// The state and extra inputs [`compute_render_z_coordinates`](crate::compute_render_z_coordinates)
+/// needs for y-sorting, on top of [`SortInputs`].
+#[derive(SystemParam)]
+pub struct YSortParams<'w, 's, Layer: LayerIndexWithContext> {
+    comparator: Res<'w, SortComparator>,
+    changed: Query<'w, 's, (), ChangedSortInputs>,
+    groups: Query<'w, 's, (Entity, &'static SortGroup)>,
+    frozen: Query<'w, 's, (Entity, Ref<'static, FreezeInternalOrder>)>,
+    previous_ys: Res<'w, PreviousSortYs<Layer>>,
+    original_zs: Res<'w, OriginalZs<Layer>>,
+    families: Query<'w, 's, Family<Layer>>,
+    #[cfg(feature = "bevy_render")]
+    aabbs: Query<'w, 's, &'static Aabb>,
+    cameras: Query<'w, 's, Ref<'static, GlobalTransform>, With<SortCamera>>,
+    scales: Query<'w, 's, &'static SortScale>,
+    time_sliced: Local<'s, TimeSlicedSort>,
+    #[cfg(feature = "async-sort")]
+    async_sort: Local<'s, AsyncSort>,
+    /// Each entity's position in last frame's order, for [`SpriteLayerOptions::stable_ties`].
+    previous_order: Local<'s, EntityHashMap<u32>>,
+    /// The snapshotted order within each container with a [`FreezeInternalOrder`].
+    frozen_ranks: Local<'s, EntityHashMap<EntityHashMap<u32>>>,
+    /// The y-coordinate each entity was last sorted by, for
+    /// [`SpriteLayerOptions::sort_hysteresis`].
+    sticky_ys: Local<'s, EntityHashMap<f32>>,
+}
+
+/// An entity's parent, its local transform, and whether it has its own layer.
+type Family<Layer> = (
+    Option<&'static Parent>,
+    Option<&'static Transform>,
+    Has<Layer>,
+);
+
+/// The outermost container with a [`FreezeInternalOrder`] that the entity is in, if any.
+fn frozen_container<Layer: Component>(
+    entity: Entity,
+    families: &Query<Family<Layer>>,
+    frozen_ranks: &EntityHashMap<EntityHashMap<u32>>,
+) -> Option<Entity> {
+    if frozen_ranks.is_empty() {
+        return None;
+    }
+    let parent_of = |entity| families.get(entity).ok()?.0.map(Parent::get);
+    self_and_ancestors(entity, parent_of)
+        .filter(|entity| frozen_ranks.contains_key(entity))
+        .last()
+}
+
+impl<Layer: LayerIndexWithContext> YSortParams<'_, '_, Layer> {
+    /// Snapshots the order of the entities in containers that just got a [`FreezeInternalOrder`],
+    /// from their current z-coordinates, and forgets containers that lost it. This has to run
+    /// before this frame's z-coordinates are written.
+    pub(crate) fn update_frozen(
+        &mut self,
+        layers: &LayerMap<Layer>,
+        z_query: &Query<&mut RenderZCoordinate>,
+    ) {
+        let frozen_ranks = &mut *self.frozen_ranks;
+        frozen_ranks.retain(|container, _| self.frozen.contains(*container));
+        let mut newly_frozen = EntityHashMap::<Vec<(f32, Entity)>>::default();
+        for (container, freeze) in &self.frozen {
+            if freeze.is_added() || !frozen_ranks.contains_key(&container) {
+                frozen_ranks.insert(container, EntityHashMap::default());
+                newly_frozen.insert(container, Vec::new());
+            }
+        }
+        if newly_frozen.is_empty() {
+            return;
+        }
+        for entity in layers.keys() {
+            let Some(members) = frozen_container(*entity, &self.families, frozen_ranks)
+                .and_then(|container| newly_frozen.get_mut(&container))
+            else {
+                continue;
+            };
+            let z = z_query.get(*entity).map_or(f32::INFINITY, |z| z.0);
+            members.push((z, *entity));
+        }
+        for (container, mut members) in newly_frozen {
+            members.sort_by(|(a_z, a), (b_z, b)| a_z.total_cmp(b_z).then(a.cmp(b)));
+            let ranks = frozen_ranks.get_mut(&container).unwrap();
+            ranks.extend(
+                members
+                    .into_iter()
+                    .enumerate()
+                    .map(|(rank, (_, entity))| (entity, rank as u32)),
+            );
+        }
+    }
+
+    /// Y-sorts the entities in `layers`, and spreads each layer's entities over its window in
+    /// that order with `set_z`. `new_seqs` holds the [`LayerSeq`](crate::LayerSeq)s of entities
+    /// that don't have theirs inserted yet.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn sort(
+        &mut self,
+        layers: &LayerMap<Layer>,
+        inputs: &SortInputs,
+        options: &Res<SpriteLayerOptions>,
+        new_seqs: &EntityHashMap<u64>,
+        windows: &[(f32, f32, Option<u32>)],
+        context: &Layer::Context,
+        set_z: impl FnMut(Entity, f32),
+    ) {
+        let Self {
+            comparator,
+            changed,
+            groups,
+            previous_ys,
+            original_zs,
+            families,
+            #[cfg(feature = "bevy_render")]
+            aabbs,
+            cameras,
+            scales,
+            time_sliced,
+            #[cfg(feature = "async-sort")]
+            async_sort,
+            previous_order,
+            frozen_ranks,
+            sticky_ys,
+            ..
+        } = self;
+        let is_overlay = |entity: &Entity| inputs.is_overlay(entity);
+        let frozen_container = |entity| frozen_container(entity, families, frozen_ranks);
+        // We y-sort everything at once because this avoids the overhead of grouping
+        // entities by their layer; each layer's order is the same either way.
+        let anchor = options.sort_anchor_local;
+        let sort_camera = (options.sort_by_camera_depth || options.camera_y_band.is_some())
+            .then(|| cameras.iter().next())
+            .flatten();
+        if sort_camera
+            .as_ref()
+            .is_some_and(|camera| camera.is_changed())
+        {
+            // everyone's depth (or clamped y) changes when the camera moves
+            time_sliced.order.clear();
+        }
+        let band = options
+            .camera_y_band
+            .filter(|_| !options.sort_by_camera_depth)
+            .zip(sort_camera.as_ref())
+            .map(|(half_height, camera)| {
+                let y = camera.translation().y;
+                y - half_height..y + half_height
+            });
+        let camera = sort_camera
+            .filter(|_| options.sort_by_camera_depth)
+            .map(|camera| (camera.translation(), camera.forward()));
+        let parent_of = |entity| families.get(entity).ok()?.0.map(Parent::get);
+        let global_transform = |entity: Entity| {
+            inputs.transforms.get(entity).ok()?;
+            // Entities spawned after transforms were propagated this frame (and their descendants)
+            // still have the identity, so compose their local transforms up to the nearest
+            // ancestor that was propagated.
+            let mut global = GlobalTransform::IDENTITY;
+            for current in self_and_ancestors(entity, parent_of) {
+                if let Ok(propagated) = inputs.transforms.get(current) {
+                    if *propagated != GlobalTransform::IDENTITY {
+                        return Some(*propagated * global);
+                    }
+                }
+                if let Ok((_, Some(transform), _)) = families.get(current) {
+                    global = GlobalTransform::from(*transform) * global;
+                }
+            }
+            Some(global)
+        };
+        let raw_y = |entity: Entity| {
+            let y = match (inputs.positions.get(entity), camera) {
+                (Ok(position), _) => position.0,
+                // Depth in front of the camera, so larger is further back, just like y.
+                (Err(_), Some((position, forward))) => global_transform(entity)
+                    .map_or(0.0, |transform| {
+                        (transform.transform_point(anchor) - position).dot(*forward)
+                    }),
+                (Err(_), None) => previous_ys.get(entity).unwrap_or_else(|| {
+                    let Some(transform) = global_transform(entity) else {
+                        return 0.0;
+                    };
+                    #[cfg(feature = "bevy_render")]
+                    if options.sort_by_aabb_bottom {
+                        if let Ok(aabb) = aabbs.get(entity) {
+                            return aabb_bottom(&transform, aabb);
+                        }
+                    }
+                    transform.transform_point(anchor).y
+                }),
+            };
+            // Not `f32::clamp`, which panics if the range is backwards.
+            let y = [&options.y_range, &band]
+                .into_iter()
+                .flatten()
+                .fold(y, |y, range| y.max(range.start).min(range.end));
+            scales.get(entity).map_or(y, |scale| y * scale.0)
+        };
+        let secondary = |entity: Entity| {
+            let Some(axis) = options.secondary_sort_axis else {
+                return 0.0;
+            };
+            global_transform(entity)
+                .map_or(0.0, |transform| transform.transform_point(anchor).dot(axis))
+        };
+        let z_hint = |entity: Entity| {
+            if !options.z_hint_tiebreak {
+                return 0.0;
+            }
+            global_transform(entity).map_or(0.0, |transform| original_zs.get(entity, &transform))
+        };
+        let hysteresis = options.sort_hysteresis;
+        if hysteresis > 0.0 {
+            sticky_ys.retain(|entity, _| layers.contains_key(entity));
+            for entity in layers.keys() {
+                let y = raw_y(*entity);
+                sticky_ys
+                    .entry(*entity)
+                    .and_modify(|sticky| {
+                        if (y - *sticky).abs() > hysteresis {
+                            *sticky = y;
+                        }
+                    })
+                    .or_insert(y);
+            }
+        } else {
+            sticky_ys.clear();
+        }
+        let sort_y = |entity: Entity| {
+            let y = sticky_ys
+                .get(&entity)
+                .copied()
+                .unwrap_or_else(|| raw_y(entity));
+            if options.sort_epsilon > 0.0 {
+                (y / options.sort_epsilon).round() * options.sort_epsilon
+            } else {
+                y
+            }
+        };
+        // Each group sorts by the y-coordinate of its frontmost (lowest) member.
+        let mut group_ys = HashMap::<u64, f32>::default();
+        for (entity, group) in groups.iter() {
+            if layers.contains_key(&entity) {
+                let y = sort_y(entity);
+                group_ys
+                    .entry(group.0)
+                    .and_modify(|group_y| *group_y = group_y.min(y))
+                    .or_insert(y);
+            }
+        }
+        // The nearest ancestor (or self) that has its own layer, and the entity's z relative to it.
+        let owner_of = |entity: Entity| {
+            let mut relative_z = 0.0;
+            for current in self_and_ancestors(entity, parent_of) {
+                let (_, transform, has_layer) = families.get(current).ok()?;
+                if has_layer {
+                    return Some((current, relative_z));
+                }
+                relative_z += transform.map_or(0.0, |transform| transform.translation.z);
+            }
+            None
+        };
+        let key_of = |entity: Entity| {
+            let mut y = sort_y(entity);
+            let mut group = groups
+                .get(entity)
+                .ok()
+                .and_then(|(_, group)| Some((group.0, *group_ys.get(&group.0)?)));
+            if let Some(container) = frozen_container(entity) {
+                // Sort as a unit with the container, in the snapshotted order within that.
+                let rank = frozen_ranks[&container]
+                    .get(&entity)
+                    .copied()
+                    .unwrap_or(u32::MAX);
+                group = Some((container.to_bits(), sort_y(container)));
+                y = -(rank as f32);
+            } else if group.is_none() && options.sort_inherited_by_owner {
+                if let Some((owner, relative_z)) = owner_of(entity) {
+                    // Sort as a unit with the owner, and by local z within that.
+                    group = Some((owner.to_bits(), sort_y(owner)));
+                    y = -relative_z;
+                }
+            }
+            let seq = options.spawn_order_tiebreak.then(|| {
+                inputs
+                    .seqs
+                    .get(entity)
+                    .map_or_else(|_| new_seqs[&entity], |seq| seq.0)
+            });
+            let previous = options
+                .stable_ties
+                .then(|| previous_order.get(&entity).copied())
+                .flatten();
+            ZIndexSortKey::new(
+                entity,
+                y,
+                secondary(entity),
+                z_hint(entity),
+                group,
+                seq,
+                previous,
+                options.fixed_point_scale,
+            )
+        };
+        let y_sorted = match (&comparator.0, options.time_sliced_sort) {
+            (Some(compare), _) => {
+                // don't reuse an order from before the comparator was set
+                time_sliced.order.clear();
+                let mut entries = layers
+                    .keys()
+                    .map(|entity| (global_transform(*entity), key_of(*entity)))
+                    .collect::<Vec<_>>();
+                let identity = GlobalTransform::IDENTITY;
+                entries.sort_by(|(a, a_key), (b, b_key)| {
+                    compare(
+                        a.as_ref().unwrap_or(&identity),
+                        b.as_ref().unwrap_or(&identity),
+                    )
+                    .then_with(|| inputs.compare_tied(a_key.entity, b_key.entity))
+                    .then_with(|| a_key.tiebreak().cmp(&b_key.tiebreak()))
+                });
+                entries.into_iter().map(|(_, key)| key.entity).collect()
+            }
+            (None, _) if inputs.tiebreak.0.is_some() => {
+                time_sliced.order.clear();
+                let mut keys = layers
+                    .keys()
+                    .map(|entity| key_of(*entity))
+                    .collect::<Vec<_>>();
+                keys.sort_by(|a, b| {
+                    a.primary()
+                        .cmp(&b.primary())
+                        .then_with(|| inputs.compare_tied(a.entity, b.entity))
+                        .then_with(|| a.tiebreak().cmp(&b.tiebreak()))
+                });
+                keys.into_iter().map(|key| key.entity).collect()
+            }
+            #[cfg(feature = "async-sort")]
+            (None, _) if options.async_sort => async_sort.sort(layers, key_of),
+            (None, Some(slices)) => {
+                if options.is_changed() {
+                    // the cached keys might have been computed differently
+                    time_sliced.order.clear();
+                }
+                // A group's key can change when any of its members moves, so we always
+                // re-read grouped entities. The same goes for entities sorted by their owner or
+                // their frozen container.
+                let changed = |entity| {
+                    changed.contains(entity)
+                        || previous_ys.moved(entity)
+                        || groups.contains(entity)
+                        || frozen_container(entity).is_some()
+                        || (options.sort_inherited_by_owner
+                            && owner_of(entity).is_some_and(|(owner, _)| changed.contains(owner)))
+                };
+                time_sliced.sort(layers, changed, slices, key_of)
+            }
+            (None, None) => y_sort(layers, key_of),
+        };
+        previous_order.clear();
+        if options.stable_ties {
+            previous_order.extend(
+                y_sorted
+                    .iter()
+                    .enumerate()
+                    .map(|(i, entity)| (*entity, i as u32)),
+            );
+        }
+
+        let y_sorted = apply_locked_indices(y_sorted, layers, &inputs.locked, is_overlay);
+        let slot_key = |entity| quantize_y(sort_y(entity), Some(1.0 / options.sort_epsilon));
+        let slot_key = (options.sort_epsilon > 0.0).then_some(&slot_key as &dyn Fn(Entity) -> i64);
+        spread_within_layers(
+            y_sorted,
+            layers,
+            windows,
+            context,
+            is_overlay,
+            slot_key,
+            options.max_slots_per_layer,
+            inputs,
+            set_z,
+        );
+    }
+}
+
+/// Filter for entities whose sort key might have changed.
+type ChangedSortInputs = Or<(
+    Changed<GlobalTransform>,
+    Changed<SortPosition>,
+    Changed<SortScale>,
+)>;
+
+/// Returns the entities in `layers`, sorted by their keys.
+fn y_sort<Layer>(
+    layers: &LayerMap<Layer>,
+    key_of: impl Fn(Entity) -> ZIndexSortKey,
+) -> Vec<Entity> {
+    // note: parallelizing with rayon is slower(!) here. I'm not sure why. maybe it has to do
+    // with some kind of inter-thread overhead or L1/L2 cache not being shared?
+    layers
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>()
+        .tap_mut(|v| v.sort_by_cached_key(|entity| key_of(*entity)))
+}
+
+/// State for [`SpriteLayerOptions::time_sliced_sort`]: last frame's order, along with the
+/// (possibly stale) key each entity was sorted by.
+#[derive(Debug, Default)]
+pub struct TimeSlicedSort {
+    order: Vec<(ZIndexSortKey, Entity)>,
+    frame: u32,
+}
+
+impl TimeSlicedSort {
+    /// Like [`y_sort`], but only recomputes the keys of entities that are new, changed, or in
+    /// this frame's slice. Everything else keeps its position relative to the others from last
+    /// frame, so we only have to sort the recomputed entities and merge them back in.
+    fn sort<Layer>(
+        &mut self,
+        layers: &LayerMap<Layer>,
+        changed: impl Fn(Entity) -> bool,
+        slices: u32,
+        key_of: impl Fn(Entity) -> ZIndexSortKey,
+    ) -> Vec<Entity> {
+        let slices = slices.max(1);
+        let slice = self.frame % slices;
+        self.frame = self.frame.wrapping_add(1);
+
+        // These are still sorted relative to each other, since their keys haven't changed.
+        let mut kept = Vec::with_capacity(layers.len());
+        let mut kept_set = EntityHashSet::default();
+        for &(key, entity) in &self.order {
+            if layers.contains_key(&entity) && entity.index() % slices != slice && !changed(entity)
+            {
+                kept.push((key, entity));
+                kept_set.insert(entity);
+            }
+        }
+        let refreshed = layers
+            .keys()
+            .filter(|entity| !kept_set.contains(*entity))
+            .map(|entity| (key_of(*entity), *entity))
+            .collect::<Vec<_>>()
+            .tap_mut(|v| v.sort_by_key(|(key, _)| *key));
+
+        self.order.clear();
+        let mut kept = kept.into_iter().peekable();
+        let mut refreshed = refreshed.into_iter().peekable();
+        loop {
+            let next = match (kept.peek(), refreshed.peek()) {
+                (Some(a), Some(b)) if b.0 < a.0 => refreshed.next(),
+                (Some(_), _) => kept.next(),
+                (None, _) => refreshed.next(),
+            };
+            let Some(next) = next else {
+                break;
+            };
+            self.order.push(next);
+        }
+        self.order.iter().map(|(_, entity)| *entity).collect()
+    }
+}
+
+/// Used to sort the entities within a sprite layer. Entities with smaller keys are drawn further
+/// back.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ZIndexSortKey {
+    // These are reversed because bevy uses +y pointing upwards, which is the
+    // opposite of what you generally want.
+    /// The y-coordinate of the entity's [`SortGroup`], or its own if it doesn't have one.
+    group_y: Reverse<i64>,
+    /// Keeps groups contiguous even if they have the same y-coordinate.
+    group: Option<u64>,
+    y: Reverse<i64>,
+    /// The entity's position along [`SpriteLayerOptions::secondary_sort_axis`], or 0 if it isn't
+    /// set. Unlike `y`, larger values are drawn in front.
+    secondary: i64,
+    /// The entity's original z-coordinate, if [`SpriteLayerOptions::z_hint_tiebreak`] is
+    /// enabled, or 0. Larger values are drawn in front.
+    z_hint: i64,
+    /// The entity's [`LayerSeq`](crate::LayerSeq), if [`SpriteLayerOptions::spawn_order_tiebreak`]
+    /// is enabled.
+    seq: Option<u64>,
+    /// The entity's position in last frame's order, if [`SpriteLayerOptions::stable_ties`] is
+    /// enabled.
+    previous: Option<u32>,
+    /// Makes the order deterministic when everything else is tied.
+    entity: Entity,
+}
+
+impl ZIndexSortKey {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        entity: Entity,
+        y: f32,
+        secondary: f32,
+        z_hint: f32,
+        group: Option<(u64, f32)>,
+        seq: Option<u64>,
+        previous: Option<u32>,
+        fixed_point_scale: Option<f32>,
+    ) -> Self {
+        let quantize = |y| quantize_y(y, fixed_point_scale);
+        Self {
+            group_y: Reverse(quantize(group.map_or(y, |(_, group_y)| group_y))),
+            group: group.map(|(group, _)| group),
+            y: Reverse(quantize(y)),
+            secondary: quantize(secondary),
+            z_hint: quantize(z_hint),
+            seq,
+            previous,
+            entity,
+        }
+    }
+
+    /// The parts of the key that depend on the entity's position.
+    fn primary(&self) -> (Reverse<i64>, Option<u64>, Reverse<i64>, i64, i64) {
+        (
+            self.group_y,
+            self.group,
+            self.y,
+            self.secondary,
+            self.z_hint,
+        )
+    }
+
+    /// The parts of the key that don't depend on the entity's position.
+    fn tiebreak(&self) -> (Option<u64>, Option<u32>, Entity) {
+        (self.seq, self.previous, self.entity)
+    }
+}
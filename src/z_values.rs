@@ -0,0 +1,361 @@
+//! The z-coordinate computation, independent of Bevy's ECS.
+use std::cmp::Reverse;
+
+use bevy::utils::HashMap;
+
+use crate::{quantize_y, LAYER_WINDOW};
+
+/// Options for [`assign_z_values`]. These mirror the relevant parts of
+/// [`SpriteLayerOptions`](crate::SpriteLayerOptions) and [`LayerIndex`](crate::LayerIndex).
+#[derive(Copy, Clone, Debug)]
+pub struct CoreOptions {
+    /// See [`SpriteLayerOptions::y_sort`](crate::SpriteLayerOptions::y_sort).
+    pub y_sort: bool,
+    /// The sort window, resolution and offset curve of the layer with the given z-coordinate.
+    pub layer: fn(f32) -> CoreLayer,
+    /// See [`SpriteLayerOptions::fixed_point_scale`](crate::SpriteLayerOptions::fixed_point_scale).
+    pub fixed_point_scale: Option<f32>,
+    /// See [`SpriteLayerOptions::sort_epsilon`](crate::SpriteLayerOptions::sort_epsilon).
+    pub sort_epsilon: f32,
+    /// See [`SpriteLayerOptions::offset_center`](crate::SpriteLayerOptions::offset_center).
+    pub offset_center: bool,
+    /// See
+    /// [`SpriteLayerOptions::max_slots_per_layer`](crate::SpriteLayerOptions::max_slots_per_layer).
+    pub max_slots_per_layer: Option<u32>,
+}
+
+impl Default for CoreOptions {
+    fn default() -> Self {
+        Self {
+            y_sort: true,
+            layer: |_| CoreLayer::default(),
+            fixed_point_scale: None,
+            sort_epsilon: 0.0,
+            offset_center: false,
+            max_slots_per_layer: None,
+        }
+    }
+}
+
+/// How [`assign_z_values`] spreads a layer's items over its window. These mirror the methods of
+/// [`LayerIndex`](crate::LayerIndex).
+#[derive(Copy, Clone, Debug)]
+pub struct CoreLayer {
+    /// See [`LayerIndex::sort_window`](crate::LayerIndex::sort_window).
+    pub window: f32,
+    /// See [`LayerIndex::sort_resolution`](crate::LayerIndex::sort_resolution).
+    pub resolution: Option<u32>,
+    /// See [`LayerIndex::offset_curve`](crate::LayerIndex::offset_curve).
+    pub curve: fn(f32) -> f32,
+}
+
+impl Default for CoreLayer {
+    fn default() -> Self {
+        Self {
+            window: LAYER_WINDOW,
+            resolution: None,
+            curve: |rank| rank,
+        }
+    }
+}
+
+/// Computes the z-coordinate of each item, given as `(layer_z, sort_y)`, the same way the
+/// [`SpriteLayerPlugin`](crate::SpriteLayerPlugin) does: items with the same `layer_z` are on the
+/// same layer, and are spread over the window above it by descending `sort_y`. Items with the same
+/// sort position are ordered by their index. The returned z-coordinates are in the same order as
+/// `items`.
+///
+/// This doesn't need a Bevy app, so it's useful for tooling or for checking how a scene will be
+/// ordered. Since there are no entities, it leaves out everything the plugin reads from
+/// components or resources other than the layer and the sort position: [`SortGroup`]s,
+/// [`ZSlice`]s, [`TopmostOverlay`]s, [`LockedSortIndex`]es, [`SceneDepthOffset`]s, the
+/// [`SortTiebreak`] and [`SortComparator`], as well as the options that depend on them or on
+/// previous frames (such as `spawn_order_tiebreak`, `stable_ties`, `z_hint_tiebreak`,
+/// `within_layer_tiebreak`, `layer_transition` and the plugin's z base).
+///
+/// [`SortGroup`]: crate::SortGroup
+/// [`ZSlice`]: crate::ZSlice
+/// [`TopmostOverlay`]: crate::TopmostOverlay
+/// [`LockedSortIndex`]: crate::LockedSortIndex
+/// [`SceneDepthOffset`]: crate::SceneDepthOffset
+/// [`SortTiebreak`]: crate::SortTiebreak
+/// [`SortComparator`]: crate::SortComparator
+///
+/// ```
+/// # use extol_sprite_layer::{assign_z_values, CoreOptions};
+/// let z = assign_z_values(&[(0.0, 1.0), (0.0, 0.0), (5.0, 0.0)], &CoreOptions::default());
+/// assert_eq!(z, [0.0, 0.5, 5.0]);
+/// ```
+pub fn assign_z_values(items: &[(f32, f32)], opts: &CoreOptions) -> Vec<f32> {
+    if !opts.y_sort {
+        return items.iter().map(|(layer_z, _)| *layer_z).collect();
+    }
+    // Each distinct layer's index, by the bits of its z-coordinate.
+    let mut indices = HashMap::<u32, usize>::default();
+    let (mut windows, mut curves) = (Vec::new(), Vec::new());
+    for (layer_z, _) in items {
+        indices.entry(layer_z.to_bits()).or_insert_with(|| {
+            let layer = (opts.layer)(*layer_z);
+            let start = if opts.offset_center {
+                layer_z - layer.window / 2.0
+            } else {
+                *layer_z
+            };
+            windows.push((start, layer.window, layer.resolution));
+            curves.push(layer.curve);
+            windows.len() - 1
+        });
+    }
+    let mut order = (0..items.len()).collect::<Vec<_>>();
+    order.sort_by_cached_key(|i| (Reverse(quantize_y(items[*i].1, opts.fixed_point_scale)), *i));
+    let spread = order
+        .iter()
+        .map(|i| {
+            let (layer_z, sort_y) = items[*i];
+            SpreadItem {
+                layer: indices[&layer_z.to_bits()],
+                slot_key: (opts.sort_epsilon > 0.0)
+                    .then(|| quantize_y(sort_y, Some(1.0 / opts.sort_epsilon))),
+                slice: 1.0,
+            }
+        })
+        .collect::<Vec<_>>();
+    let spread_zs = spread_over_windows(&spread, &windows, opts.max_slots_per_layer, |index, rank| {
+        curves[index](rank)
+    });
+    let mut z_values = vec![0.0; items.len()];
+    for (i, z) in order.into_iter().zip(spread_zs) {
+        z_values[i] = z;
+    }
+    z_values
+}
+
+/// An item for [`spread_over_windows`] to place.
+pub(crate) struct SpreadItem {
+    /// The index of the item's layer.
+    pub(crate) layer: usize,
+    /// Consecutive items on the same layer with the same key share a slot (see
+    /// [`SpriteLayerOptions::sort_epsilon`](crate::SpriteLayerOptions::sort_epsilon)).
+    pub(crate) slot_key: Option<i64>,
+    /// The fraction of its offset the item gets (see [`ZSlice`](crate::ZSlice)).
+    pub(crate) slice: f32,
+}
+
+/// Spreads each layer's items evenly over its own window, in the given order, regardless of how
+/// they're interleaved with other layers' items, and returns their z-coordinates in that order.
+/// `windows` holds each layer's start, sort window and sort resolution, and `curve` applies a
+/// layer's [`offset_curve`](crate::LayerIndex::offset_curve) given its index. Layers with more
+/// than `max_slots` slots are rounded down to that many (see
+/// [`SpriteLayerOptions::max_slots_per_layer`](crate::SpriteLayerOptions::max_slots_per_layer)).
+///
+/// This is the part of the computation that [`assign_z_values`] shares with the plugin.
+pub(crate) fn spread_over_windows(
+    order: &[SpreadItem],
+    windows: &[(f32, f32, Option<u32>)],
+    max_slots: Option<u32>,
+    curve: impl Fn(usize, f32) -> f32,
+) -> Vec<f32> {
+    // Whether the item has the same key as the last one seen on its layer.
+    let shares_slot = |item: &SpreadItem, last_keys: &mut [Option<i64>]| {
+        item.slot_key.is_some()
+            && std::mem::replace(&mut last_keys[item.layer], item.slot_key) == item.slot_key
+    };
+    let mut counts = vec![0usize; windows.len()];
+    let mut last_keys = vec![None; windows.len()];
+    for item in order {
+        counts[item.layer] += usize::from(!shares_slot(item, &mut last_keys));
+    }
+    last_keys.fill(None);
+    let mut positions = vec![0usize; windows.len()];
+    order
+        .iter()
+        .map(|item| {
+            let index = item.layer;
+            if !shares_slot(item, &mut last_keys) {
+                positions[index] += 1;
+            }
+            let (base, window, mut resolution) = windows[index];
+            if let Some(max) = max_slots.filter(|max| counts[index] > *max as usize) {
+                resolution = Some(resolution.map_or(max, |resolution| resolution.min(max)));
+            }
+            let offset = layer_offset(positions[index] - 1, counts[index], resolution, |rank| {
+                curve(index, rank)
+            });
+            base + offset * item.slice * window
+        })
+        .collect()
+}
+
+/// Spreads `n_layers` layers evenly below a camera with the given `far` plane, returning the
+/// z-coordinate of each one in order from back to front. Bevy's 2D camera sits at `far - 0.1` and
+/// clips anything above that, so the frontmost layer is placed a full [`LAYER_WINDOW`] below the
+/// camera and the rest are spaced evenly between it and 0. Pass the distance to your camera's far
+/// plane, which is `1000.0` for Bevy's default 2D camera, and look the result up from your
+/// [`as_z_coordinate`](crate::LayerIndex::as_z_coordinate):
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use extol_sprite_layer::{even_layer_z, LayerIndex};
+/// # use std::sync::OnceLock;
+/// #[derive(Debug, Clone, Copy, Component, PartialEq, Eq, Hash)]
+/// enum Layer {
+///     Background,
+///     Actors,
+///     Ui,
+/// }
+///
+/// impl LayerIndex for Layer {
+///     fn as_z_coordinate(&self) -> f32 {
+///         static LAYER_ZS: OnceLock<Vec<f32>> = OnceLock::new();
+///         LAYER_ZS.get_or_init(|| even_layer_z(3, 1000.0))[*self as usize]
+///     }
+/// }
+/// # assert!((Layer::Ui.as_z_coordinate() - 998.9).abs() < 1e-3);
+/// ```
+///
+/// Layers don't overlap as long as the spacing is at least each layer's
+/// [`sort_window`](crate::LayerIndex::sort_window), so with the default window up to
+/// `(far - 0.1) / LAYER_WINDOW` layers fit (999 for `far = 1000.0`); past that, adjacent layers'
+/// windows overlap, though the frontmost layer still stays below the camera.
+/// Spacing layers further apart doesn't give y-sorting any more precision (that depends on the
+/// window and the z-coordinate's magnitude), so if you don't need the room, fewer layers or a
+/// smaller `far` keep z-coordinates smaller and more precise.
+pub fn even_layer_z(n_layers: usize, far: f32) -> Vec<f32> {
+    // Leave room for the frontmost layer's whole window below the camera.
+    let top = far - CAMERA_2D_DEPTH - LAYER_WINDOW;
+    let spacing = top / n_layers.saturating_sub(1).max(1) as f32;
+    (0..n_layers).map(|i| i as f32 * spacing).collect()
+}
+
+/// How far below its far plane Bevy's 2D camera sits.
+const CAMERA_2D_DEPTH: f32 = 0.1;
+
+/// The offset, as a fraction of the layer's window, of the entity at `position` in its layer's
+/// y-sorted order, out of `count` entities on the layer. See
+/// [`LayerIndex::sort_resolution`](crate::LayerIndex::sort_resolution) for `resolution` and
+/// [`LayerIndex::offset_curve`](crate::LayerIndex::offset_curve) for `curve`.
+///
+/// The rank is always `position / count`, so with `count` entities the offsets are
+/// `0, 1/count, ..., (count - 1)/count`: the backmost entity is at the layer's base and the
+/// frontmost is a full step short of the window's end, so it can't reach the next layer. A
+/// single entity gets offset 0. For counts so large that `(count - 1) / count` rounds to `1.0`
+/// in `f32`, the rank is clamped to the largest `f32` below `1.0`.
+pub(crate) fn layer_offset(
+    position: usize,
+    count: usize,
+    resolution: Option<u32>,
+    curve: impl Fn(f32) -> f32,
+) -> f32 {
+    let rank = (position as f32 / count as f32).min(BELOW_ONE);
+    let offset = curve(rank);
+    match resolution {
+        Some(slots) => {
+            let slots = slots.max(1) as f32;
+            (offset * slots).floor() / slots
+        }
+        None => offset,
+    }
+}
+
+/// The largest `f32` less than `1.0`.
+const BELOW_ONE: f32 = 1.0 - f32::EPSILON / 2.0;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        assert_eq!(
+            assign_z_values(&[], &CoreOptions::default()),
+            Vec::<f32>::new()
+        );
+    }
+
+    #[test]
+    fn single_layer() {
+        let items = [(2.0, 0.0), (2.0, 3.0), (2.0, 1.0), (2.0, 2.0)];
+        assert_eq!(
+            assign_z_values(&items, &CoreOptions::default()),
+            [2.75, 2.0, 2.5, 2.25]
+        );
+        let unsorted = CoreOptions {
+            y_sort: false,
+            ..CoreOptions::default()
+        };
+        assert_eq!(assign_z_values(&items, &unsorted), [2.0; 4]);
+    }
+
+    #[test]
+    fn tiny_layers_stay_in_their_window() {
+        // zero, one and two entities on each of two adjacent layers
+        for count in 0..=2 {
+            let items = (0..count)
+                .flat_map(|i| [(0.0, i as f32), (LAYER_WINDOW, i as f32)])
+                .collect::<Vec<_>>();
+            let z_values = assign_z_values(&items, &CoreOptions::default());
+            assert_eq!(z_values.len(), items.len());
+            for ((layer_z, _), z) in items.iter().zip(&z_values) {
+                assert!(
+                    (*layer_z..layer_z + LAYER_WINDOW).contains(z),
+                    "{count}: {z}"
+                );
+            }
+        }
+        assert_eq!(layer_offset(0, 1, None, |rank| rank), 0.0);
+        assert_eq!(layer_offset(1, 2, None, |rank| rank), 0.5);
+    }
+
+    #[test]
+    fn huge_counts_stay_below_one() {
+        let count = 1 << 25;
+        // `(count - 1) as f32 / count as f32` rounds to exactly 1.0
+        assert!(layer_offset(count - 1, count, None, |rank| rank) < 1.0);
+    }
+
+    #[test]
+    fn even_layers_fit() {
+        let camera_z = 1000.0 - CAMERA_2D_DEPTH;
+        assert_eq!(even_layer_z(0, 1000.0), Vec::<f32>::new());
+        assert_eq!(even_layer_z(1, 1000.0), [0.0]);
+        let zs = even_layer_z(4, 1000.0);
+        assert_eq!(zs[0], 0.0);
+        assert!((zs[3] - (camera_z - LAYER_WINDOW)).abs() < 1e-3, "{zs:?}");
+        for n_layers in [1, 2, 3, 7, 100, 999, 1000, 5000] {
+            let zs = even_layer_z(n_layers, 1000.0);
+            assert_eq!(zs.len(), n_layers);
+            assert_eq!(zs[0], 0.0);
+            if n_layers <= 999 {
+                for pair in zs.windows(2) {
+                    assert!(pair[1] - pair[0] >= LAYER_WINDOW, "{n_layers}: {pair:?}");
+                }
+            }
+            // the frontmost entity on the frontmost layer is still below the camera
+            let top = zs[n_layers - 1] + layer_offset(999, 1000, None, |rank| rank) * LAYER_WINDOW;
+            assert!(top < camera_z, "{n_layers}: {top}");
+        }
+    }
+
+    #[test]
+    fn dense_layers() {
+        let items = (0..10_000)
+            .map(|i| ((i % 3) as f32 * 10.0, fastrand::f32()))
+            .collect::<Vec<_>>();
+        let z_values = assign_z_values(&items, &CoreOptions::default());
+        for ((layer_z, _), z) in items.iter().zip(&z_values) {
+            assert!((*layer_z..layer_z + LAYER_WINDOW).contains(z));
+        }
+        // every entity in a layer gets its own z-coordinate, in y-sorted order
+        let mut by_z = items.iter().zip(&z_values).collect::<Vec<_>>();
+        by_z.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        for pair in by_z.windows(2) {
+            let [((a_layer, a_y), a_z), ((b_layer, b_y), b_z)] = pair else {
+                unreachable!()
+            };
+            if a_layer == b_layer {
+                assert!(a_z < b_z);
+                assert!(a_y >= b_y);
+            }
+        }
+    }
+}